@@ -0,0 +1,896 @@
+use crate::deploy::{find_artifact, DeployPlan, DeployTarget};
+use crate::wrappers::escrow::EscrowContract;
+use crate::wrappers::strategy_pool::StrategyPoolContract;
+use crate::wrappers::strategy_pool_factory::StrategyPoolFactoryContract;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crypto::keystore::KeystoreV3;
+use crypto::secp256k1::Secp256k1Key;
+use crypto::securosys_pkcs11::{Pkcs11Config, Pkcs11Session};
+use crypto::CryptoAlgorithm;
+use eth_sdk::amount::Amount;
+use eth_sdk::artifact::Artifact;
+use eth_sdk::block::BlockRef;
+use eth_sdk::deployments::Deployments;
+use eth_sdk::dry_run::{DryRun, DryRunError};
+use eth_sdk::erc20::Erc20Token;
+use eth_sdk::proxy::Erc1967Proxy;
+use eth_sdk::signer::EthereumSigner;
+use eth_sdk::storage_layout::StorageLayout;
+use eth_sdk::utils::wait_for_success;
+use eth_sdk::verify::{verify_deployments, ExpectedCode};
+use eth_sdk::EthereumNet;
+use eyre::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use web3::ethabi::Token;
+use web3::transports::Http;
+use web3::types::{Address, H256, U256};
+use web3::Web3;
+
+/// Operates the Escrow, StrategyPool and StrategyPoolFactory contracts
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub network: NetworkArgs,
+    #[command(flatten)]
+    pub key: KeyArgs,
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    /// Shows what a write would return or why it reverts, with its gas estimate, without
+    /// sending it
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Args, Debug)]
+pub struct NetworkArgs {
+    /// JSON-RPC endpoint of the node
+    #[arg(
+        long,
+        global = true,
+        env = "ETH_RPC_URL",
+        default_value = "http://127.0.0.1:8545"
+    )]
+    pub rpc_url: String,
+    /// Fails unless the node is on this chain
+    #[arg(long, global = true, env = "ETH_CHAIN_ID")]
+    pub chain_id: Option<u64>,
+    /// Block reads are made at: a number, a hash, latest, safe, finalized...
+    #[arg(long, global = true, value_parser = parse_block)]
+    pub block: Option<BlockRef>,
+}
+
+/// Where the signing key comes from. Secrets are only ever read from files or the environment
+#[derive(Args, Debug)]
+pub struct KeyArgs {
+    /// Keystore v3 JSON file
+    #[arg(long, global = true)]
+    pub keystore: Option<PathBuf>,
+    /// Environment variable holding the keystore password
+    #[arg(long, global = true, default_value = "KEYSTORE_PASSWORD")]
+    pub password_env: String,
+    /// Environment variable holding a BIP-39 mnemonic
+    #[arg(long, global = true)]
+    pub mnemonic_env: Option<String>,
+    #[arg(long, global = true, default_value_t = 0)]
+    pub mnemonic_index: u32,
+    /// Environment variable holding a hex private key
+    #[arg(long, global = true)]
+    pub private_key_env: Option<String>,
+    /// Label of a key on the PKCS#11 token set by PKCS11_MODULE, PKCS11_TOKEN_LABEL and PKCS11_PIN
+    #[arg(long, global = true)]
+    pub hsm_label: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Amounts are in base units
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(subcommand)]
+    Escrow(EscrowCommand),
+    #[command(subcommand)]
+    Pool(PoolCommand),
+    #[command(subcommand)]
+    Factory(FactoryCommand),
+    #[command(subcommand)]
+    Erc20(Erc20Command),
+    #[command(subcommand)]
+    Deploy(DeployCommand),
+    #[command(subcommand)]
+    Proxy(ProxyCommand),
+    /// Compares the code of every contract in the deployments file with its artifact, ignoring
+    /// immutables, libraries and the metadata hash
+    Verify {
+        #[arg(long, default_value = "deployments")]
+        deployments_dir: PathBuf,
+        /// Forge output artifacts are looked up in, by the label up to any `:`
+        #[arg(long, default_value = "out")]
+        out: PathBuf,
+        /// Artifact of a label, as `LABEL=PATH`
+        #[arg(long = "artifact", value_parser = parse_artifact)]
+        artifacts: Vec<(String, PathBuf)>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum EscrowCommand {
+    /// Sends tokens held by the escrow
+    TransferToken {
+        #[arg(long)]
+        escrow: Address,
+        #[arg(long)]
+        token: Address,
+        #[arg(long)]
+        recipient: Address,
+        #[arg(long, value_parser = parse_u256)]
+        amount: U256,
+    },
+    Owner {
+        #[arg(long)]
+        escrow: Address,
+    },
+    TransferOwnership {
+        #[arg(long)]
+        escrow: Address,
+        #[arg(long)]
+        new_owner: Address,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PoolCommand {
+    /// Deposits `--amount` of each `--asset`, in the same order
+    Deposit {
+        #[arg(long)]
+        pool: Address,
+        #[arg(long = "asset", required = true)]
+        assets: Vec<Address>,
+        #[arg(long = "amount", required = true, value_parser = parse_u256)]
+        amounts: Vec<U256>,
+        /// Defaults to the signer
+        #[arg(long)]
+        receiver: Option<Address>,
+    },
+    Redeem {
+        #[arg(long)]
+        pool: Address,
+        #[arg(long, value_parser = parse_u256)]
+        shares: U256,
+        /// Defaults to the signer
+        #[arg(long)]
+        receiver: Option<Address>,
+        /// Defaults to the signer
+        #[arg(long)]
+        owner: Option<Address>,
+    },
+    /// Shares a deposit would mint, or with `--shares` the assets a redemption would pay
+    Preview {
+        #[arg(long)]
+        pool: Address,
+        #[arg(long = "asset", conflicts_with = "shares")]
+        assets: Vec<Address>,
+        #[arg(long = "amount", conflicts_with = "shares", value_parser = parse_u256)]
+        amounts: Vec<U256>,
+        #[arg(long, value_parser = parse_u256)]
+        shares: Option<U256>,
+        /// Runs the deposit or redemption itself as this account, faking the tokens,
+        /// approvals or shares it needs through eth_call state overrides
+        #[arg(long)]
+        holder: Option<Address>,
+    },
+    /// Moves the pool's holding of each `--asset` by the matching signed `--delta`
+    ChangeStrategy {
+        #[arg(long)]
+        pool: Address,
+        #[arg(long = "asset", required = true)]
+        assets: Vec<Address>,
+        #[arg(long = "delta", required = true, allow_negative_numbers = true)]
+        deltas: Vec<i128>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FactoryCommand {
+    CreatePool {
+        #[arg(long)]
+        factory: Address,
+        #[arg(long)]
+        trader: Address,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        symbol: String,
+        #[arg(long, value_parser = parse_u256)]
+        initial_share_value: U256,
+    },
+    /// Every trader and their pool
+    List {
+        #[arg(long)]
+        factory: Address,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Erc20Command {
+    Balance {
+        #[arg(long)]
+        token: Address,
+        #[arg(long)]
+        account: Address,
+    },
+    Approve {
+        #[arg(long)]
+        token: Address,
+        #[arg(long)]
+        spender: Address,
+        #[arg(long, value_parser = parse_u256)]
+        amount: U256,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct DeployArgs {
+    /// Foundry, Hardhat or Truffle artifact such as `out/Escrow.sol/Escrow.json`. The pool and
+    /// factory default to the bundled ones
+    #[arg(long)]
+    pub artifact: Option<PathBuf>,
+    /// Library to link, as `Name=0x...` or `src/Lib.sol:Name=0x...`
+    #[arg(long = "library", value_parser = parse_library)]
+    pub libraries: Vec<(String, Address)>,
+    /// Key in the deployments file. Already deployed keys are skipped
+    #[arg(long)]
+    pub label: Option<String>,
+    /// Where the `<network>.json` deployments files are
+    #[arg(long, default_value = "deployments")]
+    pub deployments_dir: PathBuf,
+    /// Deploys through CREATE2 with this salt, to the same address on every chain
+    #[arg(long)]
+    pub salt: Option<H256>,
+    /// Only prints the CREATE2 address for `--salt`, offline
+    #[arg(long, requires = "salt")]
+    pub predict: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DeployCommand {
+    Escrow {
+        #[command(flatten)]
+        args: DeployArgs,
+        #[arg(long)]
+        owner: Address,
+    },
+    Factory {
+        #[command(flatten)]
+        args: DeployArgs,
+        #[arg(long)]
+        owner: Address,
+        #[arg(long)]
+        pool_owner: Address,
+    },
+    /// A pool on its own, outside of the factory
+    Pool {
+        #[command(flatten)]
+        args: DeployArgs,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        symbol: String,
+        #[arg(long)]
+        owner: Address,
+        #[arg(long, value_parser = parse_u256)]
+        initial_share_value: U256,
+    },
+}
+
+/// ERC-1967 transparent and UUPS proxies
+#[derive(Subcommand, Debug)]
+pub enum ProxyCommand {
+    Implementation {
+        #[arg(long)]
+        proxy: Address,
+    },
+    /// Zero for UUPS proxies
+    Admin {
+        #[arg(long)]
+        proxy: Address,
+    },
+    /// Points the proxy at a new implementation, through its admin
+    Upgrade {
+        #[arg(long)]
+        proxy: Address,
+        #[arg(long)]
+        implementation: Address,
+        /// Hex call data to run on the new implementation, e.g. a reinitializer
+        #[arg(long, value_parser = parse_hex)]
+        call: Option<Vec<u8>>,
+        /// Forge artifact of the current implementation, to check storage layouts first
+        #[arg(long, requires = "new_artifact")]
+        old_artifact: Option<PathBuf>,
+        #[arg(long, requires = "old_artifact")]
+        new_artifact: Option<PathBuf>,
+    },
+    /// Whether the Forge artifact `--new` can replace `--old` behind a proxy, offline
+    CheckLayout {
+        #[arg(long)]
+        old: PathBuf,
+        #[arg(long)]
+        new: PathBuf,
+    },
+}
+
+impl DeployCommand {
+    fn plan(&self) -> Result<(&DeployArgs, DeployPlan)> {
+        let (args, target) = match self {
+            DeployCommand::Escrow { args, owner } => (args, DeployTarget::Escrow { owner: *owner }),
+            DeployCommand::Factory {
+                args,
+                owner,
+                pool_owner,
+            } => (
+                args,
+                DeployTarget::StrategyPoolFactory {
+                    owner: *owner,
+                    pool_owner: *pool_owner,
+                },
+            ),
+            DeployCommand::Pool {
+                args,
+                name,
+                symbol,
+                owner,
+                initial_share_value,
+            } => (
+                args,
+                DeployTarget::StrategyPool {
+                    name: name.clone(),
+                    symbol: symbol.clone(),
+                    owner: *owner,
+                    initial_deposit_share_value: *initial_share_value,
+                },
+            ),
+        };
+        let plan = DeployPlan {
+            artifact: target.artifact(args.artifact.as_deref())?,
+            libraries: args.libraries.iter().cloned().collect(),
+            label: args.label.clone().unwrap_or_else(|| target.default_label()),
+            salt: args.salt,
+            target,
+        };
+        Ok((args, plan))
+    }
+}
+
+/// Decimal, or hex with `0x`. `U256::from_str` alone would read `100` as hex
+fn parse_u256(s: &str) -> Result<U256, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| format!("{}: {}", s, e)),
+        None => U256::from_dec_str(s).map_err(|e| format!("{}: {:?}", s, e)),
+    }
+}
+fn parse_library(s: &str) -> Result<(String, Address), String> {
+    let (name, address) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("{}: expected NAME=ADDRESS", s))?;
+    let address = Address::from_str(address).map_err(|e| format!("{}: {}", s, e))?;
+    Ok((name.to_owned(), address))
+}
+fn parse_artifact(s: &str) -> Result<(String, PathBuf), String> {
+    let (label, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{}: expected LABEL=PATH", s))?;
+    Ok((label.to_owned(), PathBuf::from(path)))
+}
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|e| format!("{}: {}", s, e))
+}
+fn parse_block(s: &str) -> Result<BlockRef, String> {
+    BlockRef::from_str(s).map_err(|e| e.to_string())
+}
+
+impl NetworkArgs {
+    pub async fn connect(&self) -> Result<(Web3<Http>, u64)> {
+        let web3 = Web3::new(Http::new(&self.rpc_url)?);
+        let chain_id = web3
+            .eth()
+            .chain_id()
+            .await
+            .with_context(|| format!("connecting to {}", self.rpc_url))?
+            .as_u64();
+        if let Some(expected) = self.chain_id {
+            ensure!(
+                chain_id == expected,
+                "{} is on chain {}, not {}",
+                self.rpc_url,
+                chain_id,
+                expected
+            );
+        }
+        Ok((web3, chain_id))
+    }
+}
+
+impl KeyArgs {
+    pub fn signer(&self) -> Result<EthereumSigner> {
+        let sources = [
+            self.keystore.is_some(),
+            self.mnemonic_env.is_some(),
+            self.private_key_env.is_some(),
+            self.hsm_label.is_some(),
+        ];
+        ensure!(
+            sources.iter().filter(|x| **x).count() == 1,
+            "pass exactly one of --keystore, --mnemonic-env, --private-key-env or --hsm-label"
+        );
+        let env = |name: &str| std::env::var(name).with_context(|| format!("{} is not set", name));
+        if let Some(path) = &self.keystore {
+            let keystore = KeystoreV3::load(path)?;
+            let key = keystore.decrypt_key(&env(&self.password_env)?)?;
+            let signer = EthereumSigner::new(Arc::new(key))?;
+            if let Some(address) = &keystore.address {
+                let expected = Address::from_str(address)
+                    .with_context(|| format!("keystore address {}", address))?;
+                ensure!(
+                    expected == signer.address,
+                    "keystore address {:?} does not match key address {:?}",
+                    expected,
+                    signer.address
+                );
+            }
+            return Ok(signer);
+        }
+        if let Some(name) = &self.mnemonic_env {
+            return EthereumSigner::new_from_mnemonic(&env(name)?, "", self.mnemonic_index);
+        }
+        if let Some(name) = &self.private_key_env {
+            return EthereumSigner::new(Arc::new(Secp256k1Key::from_hex(&env(name)?)?));
+        }
+        let label = self.hsm_label.as_deref().unwrap();
+        let session = Pkcs11Session::open(&Pkcs11Config::from_env()?)?;
+        let key = session
+            .find_key(label, CryptoAlgorithm::Secp256k1None)?
+            .with_context(|| format!("no key labelled {} on the token", label))?;
+        EthereumSigner::new(Arc::new(key))
+    }
+}
+
+fn storage_layout(path: &Path) -> Result<StorageLayout> {
+    Artifact::load(path)?.storage_layout.with_context(|| {
+        format!(
+            "{} has no storageLayout, build with extra_output = [\"storageLayout\"]",
+            path.display()
+        )
+    })
+}
+/// Storage layout errors of upgrading from `old` to `new`, as JSON
+fn check_layout(old: &Path, new: &Path) -> Result<Value> {
+    let errors = storage_layout(old)?.upgrade_errors(&storage_layout(new)?)?;
+    Ok(json!({ "compatible": errors.is_empty(), "errors": errors }))
+}
+
+async fn sent(web3: &Web3<Http>, hash: H256) -> Result<Value> {
+    let receipt = wait_for_success(&web3.eth(), hash).await?;
+    Ok(json!({
+        "transaction": hash,
+        "block": receipt.block_number.map(|x| x.as_u64()),
+        "gas_used": receipt.gas_used.map(|x| x.to_string()),
+        "status": "success",
+    }))
+}
+
+/// Only ERC-20 commands need to know the network, the others work on any chain
+fn network(chain_id: u64) -> Result<EthereumNet> {
+    EthereumNet::from_chain_id(chain_id)
+        .with_context(|| format!("chain {} is not a known network", chain_id))
+}
+
+/// Return values are in the order of the ABI outputs
+fn dry_run_output(dry_run: DryRun) -> Value {
+    let tx = &dry_run.transaction;
+    let mut output = json!({
+        "dry_run": true,
+        "gas_estimate": dry_run.gas.map(|x| x.to_string()),
+        "to": tx.to,
+        "nonce": tx.nonce.map(|x| x.to_string()),
+        "gas": tx.gas.to_string(),
+        "gas_price": tx.gas_price.map(|x| x.to_string()),
+        "value": tx.value.to_string(),
+        "data": format!("0x{}", hex::encode(&tx.data.0)),
+    });
+    match dry_run.result {
+        Ok(tokens) => {
+            output["status"] = json!("success");
+            output["output"] = tokens.into_iter().map(token_json).collect();
+        }
+        Err(DryRunError::Revert(reason)) => {
+            output["status"] = json!("reverted");
+            output["revert_reason"] = json!(reason);
+        }
+        Err(DryRunError::NoCode(_)) => output["status"] = json!("no_code"),
+    }
+    output
+}
+
+fn token_json(token: Token) -> Value {
+    match token {
+        Token::Address(x) => json!(x),
+        Token::Uint(x) => json!(x.to_string()),
+        // two's complement
+        Token::Int(x) if x.bit(255) => json!(format!("-{}", !x + 1)),
+        Token::Int(x) => json!(x.to_string()),
+        Token::Bool(x) => json!(x),
+        Token::String(x) => json!(x),
+        Token::Bytes(x) | Token::FixedBytes(x) => json!(format!("0x{}", hex::encode(x))),
+        Token::Array(x) | Token::FixedArray(x) | Token::Tuple(x) => {
+            x.into_iter().map(token_json).collect()
+        }
+    }
+}
+
+fn holdings(assets: Vec<Address>, amounts: Vec<U256>) -> Value {
+    assets
+        .into_iter()
+        .zip(amounts)
+        .map(|(asset, amount)| json!({"asset": asset, "amount": amount.to_string()}))
+        .collect()
+}
+
+pub async fn run(cli: Cli) -> Result<Value> {
+    // predicting a CREATE2 address needs no node
+    if let Command::Deploy(command) = &cli.command {
+        let (args, plan) = command.plan()?;
+        if args.predict {
+            return Ok(json!({ "address": plan.predict_address()? }));
+        }
+    }
+    if let Command::Proxy(ProxyCommand::CheckLayout { old, new }) = &cli.command {
+        return check_layout(old, new);
+    }
+    if cli.dry_run && matches!(cli.command, Command::Deploy(_) | Command::Proxy(_)) {
+        bail!("--dry-run is not supported for deployments and proxy upgrades");
+    }
+    let (web3, chain_id) = cli.network.connect().await?;
+    let block = cli.network.block;
+    let key = &cli.key;
+    let dry_run = cli.dry_run;
+    match cli.command {
+        Command::Escrow(command) => match command {
+            EscrowCommand::TransferToken {
+                escrow,
+                token,
+                recipient,
+                amount,
+            } => {
+                let escrow = EscrowContract::new(web3.clone(), escrow)?;
+                let signer = key.signer()?;
+                if dry_run {
+                    let dry_run = escrow
+                        .dry_run_transfer_token_to(&signer, token, recipient, amount)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = escrow
+                    .transfer_token_to(&signer, token, recipient, amount)
+                    .await?;
+                sent(&web3, hash).await
+            }
+            EscrowCommand::Owner { escrow } => {
+                let escrow = EscrowContract::new(web3, escrow)?;
+                Ok(json!({ "owner": escrow.owner(block).await? }))
+            }
+            EscrowCommand::TransferOwnership { escrow, new_owner } => {
+                let escrow = EscrowContract::new(web3.clone(), escrow)?;
+                let signer = key.signer()?;
+                if dry_run {
+                    let dry_run = escrow
+                        .dry_run_transfer_ownership(&signer, new_owner)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = escrow.transfer_ownership(&signer, new_owner).await?;
+                sent(&web3, hash).await
+            }
+        },
+        Command::Pool(command) => match command {
+            PoolCommand::Deposit {
+                pool,
+                assets,
+                amounts,
+                receiver,
+            } => {
+                let pool = StrategyPoolContract::new(web3.clone(), pool)?;
+                let signer = key.signer()?;
+                let receiver = receiver.unwrap_or(signer.address);
+                if dry_run {
+                    let dry_run = pool
+                        .dry_run_deposit(&signer, assets, amounts, receiver)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = pool.deposit(&signer, assets, amounts, receiver).await?;
+                sent(&web3, hash).await
+            }
+            PoolCommand::Redeem {
+                pool,
+                shares,
+                receiver,
+                owner,
+            } => {
+                let pool = StrategyPoolContract::new(web3.clone(), pool)?;
+                let signer = key.signer()?;
+                let receiver = receiver.unwrap_or(signer.address);
+                let owner = owner.unwrap_or(signer.address);
+                if dry_run {
+                    let dry_run = pool
+                        .dry_run_redeem(&signer, shares, receiver, owner)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = pool.redeem(&signer, shares, receiver, owner).await?;
+                sent(&web3, hash).await
+            }
+            PoolCommand::Preview {
+                pool,
+                assets,
+                amounts,
+                shares,
+                holder,
+            } => {
+                let pool = StrategyPoolContract::new(web3, pool)?;
+                match (shares, holder) {
+                    (Some(shares), Some(holder)) => {
+                        let (assets, amounts) =
+                            pool.simulate_redeem(holder, shares, holder, block).await?;
+                        Ok(json!({ "assets": holdings(assets, amounts) }))
+                    }
+                    (Some(shares), None) => {
+                        let (assets, amounts) = pool.preview_redeem(shares, block).await?;
+                        Ok(json!({ "assets": holdings(assets, amounts) }))
+                    }
+                    (None, holder) => {
+                        ensure!(!assets.is_empty(), "pass --asset and --amount, or --shares");
+                        let shares = match holder {
+                            Some(holder) => {
+                                pool.simulate_deposit(holder, assets, amounts, holder, block)
+                                    .await?
+                            }
+                            None => pool.preview_deposit(assets, amounts, block).await?,
+                        };
+                        Ok(json!({ "shares": shares.to_string() }))
+                    }
+                }
+            }
+            PoolCommand::ChangeStrategy {
+                pool,
+                assets,
+                deltas,
+            } => {
+                let pool = StrategyPoolContract::new(web3.clone(), pool)?;
+                let signer = key.signer()?;
+                if dry_run {
+                    let dry_run = pool
+                        .dry_run_change_strategy(&signer, assets, deltas)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = pool.change_strategy(&signer, assets, deltas).await?;
+                sent(&web3, hash).await
+            }
+        },
+        Command::Factory(command) => match command {
+            FactoryCommand::CreatePool {
+                factory,
+                trader,
+                name,
+                symbol,
+                initial_share_value,
+            } => {
+                let factory = StrategyPoolFactoryContract::new(web3.clone(), factory)?;
+                let signer = key.signer()?;
+                if dry_run {
+                    let dry_run = factory
+                        .dry_run_create_pool(&signer, trader, &name, &symbol, initial_share_value)
+                        .await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = factory
+                    .create_pool(&signer, trader, &name, &symbol, initial_share_value)
+                    .await?;
+                let mut output = sent(&web3, hash).await?;
+                output["pool"] = json!(factory.get_pool(trader, None).await?);
+                Ok(output)
+            }
+            FactoryCommand::List { factory } => {
+                let factory = StrategyPoolFactoryContract::new(web3, factory)?;
+                let pools: Vec<_> = factory
+                    .list_pools(block)
+                    .await?
+                    .into_iter()
+                    .map(|(trader, pool)| json!({"trader": trader, "pool": pool}))
+                    .collect();
+                Ok(json!({ "pools": pools }))
+            }
+        },
+        Command::Erc20(command) => match command {
+            Erc20Command::Balance { token, account } => {
+                let token = Erc20Token::new_with_client(network(chain_id)?, web3, token)?;
+                let balance = token.balance_of(account, block).await?;
+                let metadata = token.metadata().await?;
+                Ok(json!({
+                    "account": account,
+                    "balance": balance.to_string(),
+                    "amount": Amount::new(balance, metadata.decimals)?.to_string(),
+                    "symbol": metadata.symbol,
+                }))
+            }
+            Erc20Command::Approve {
+                token,
+                spender,
+                amount,
+            } => {
+                let token = Erc20Token::new_with_client(network(chain_id)?, web3.clone(), token)?;
+                let signer = key.signer()?;
+                if dry_run {
+                    let dry_run = token.dry_run_approve(&signer, spender, amount).await?;
+                    return Ok(dry_run_output(dry_run));
+                }
+                let hash = token.approve(&signer, spender, amount).await?;
+                sent(&web3, hash).await
+            }
+        },
+        Command::Deploy(command) => {
+            let (args, plan) = command.plan()?;
+            let mut deployments = Deployments::load(&args.deployments_dir, chain_id)?;
+            let (deployment, skipped) =
+                plan.deploy(&web3, &key.signer()?, &mut deployments).await?;
+            Ok(json!({
+                "label": plan.label,
+                "address": deployment.address,
+                "transaction": deployment.transaction,
+                "block": deployment.block,
+                "status": if skipped { "already deployed" } else { "deployed" },
+            }))
+        }
+        Command::Proxy(command) => match command {
+            ProxyCommand::Implementation { proxy } => {
+                let proxy = Erc1967Proxy::new(web3, proxy);
+                Ok(json!({ "implementation": proxy.implementation(block).await? }))
+            }
+            ProxyCommand::Admin { proxy } => {
+                let proxy = Erc1967Proxy::new(web3, proxy);
+                Ok(json!({ "admin": proxy.admin(block).await? }))
+            }
+            ProxyCommand::Upgrade {
+                proxy,
+                implementation,
+                call,
+                old_artifact,
+                new_artifact,
+            } => {
+                if let (Some(old), Some(new)) = (old_artifact, new_artifact) {
+                    storage_layout(&old)?.check_upgrade(&storage_layout(&new)?)?;
+                }
+                let proxy = Erc1967Proxy::new(web3.clone(), proxy);
+                let hash = proxy
+                    .upgrade(&key.signer()?, implementation, call.unwrap_or_default())
+                    .await?;
+                let mut output = sent(&web3, hash).await?;
+                let found = proxy.implementation(None).await?;
+                ensure!(
+                    found == implementation,
+                    "proxy still points at {:?} after the upgrade",
+                    found
+                );
+                output["implementation"] = json!(found);
+                Ok(output)
+            }
+            ProxyCommand::CheckLayout { old, new } => check_layout(&old, &new),
+        },
+        Command::Verify {
+            deployments_dir,
+            out,
+            artifacts,
+        } => {
+            let deployments = Deployments::load(&deployments_dir, chain_id)?;
+            let artifacts: HashMap<_, _> = artifacts.into_iter().collect();
+            let mut expected = BTreeMap::new();
+            for label in deployments.contracts.keys() {
+                let artifact = match artifacts.get(label) {
+                    Some(path) => Some(Artifact::load(path)?),
+                    None => find_artifact(label, &out)?,
+                };
+                if let Some(artifact) = artifact {
+                    let code = ExpectedCode::from_artifact(&artifact)
+                        .with_context(|| format!("artifact of {}", label))?;
+                    expected.insert(label.clone(), code);
+                }
+            }
+            let results = verify_deployments(&web3, &deployments, &expected, block).await?;
+            let contracts: serde_json::Map<_, _> = results
+                .iter()
+                .map(|(label, result)| {
+                    let mut output = json!(result);
+                    output["address"] = json!(deployments.contracts[label].address);
+                    (label.clone(), output)
+                })
+                .collect();
+            Ok(json!({
+                "verified": results.values().all(|x| x.is_match()),
+                "contracts": contracts,
+            }))
+        }
+    }
+}
+
+/// Text output is one `key: value` line per field, nested values as compact JSON
+pub fn print(format: OutputFormat, output: &Value) -> Result<()> {
+    match (format, output) {
+        (OutputFormat::Json, _) => println!("{}", serde_json::to_string_pretty(output)?),
+        (OutputFormat::Text, Value::Object(fields)) => {
+            for (key, value) in fields {
+                match value {
+                    Value::String(value) => println!("{}: {}", key, value),
+                    value => println!("{}: {}", key, value),
+                }
+            }
+        }
+        (OutputFormat::Text, value) => println!("{}", value),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() -> Result<()> {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from([
+            "contract-wrappers",
+            "pool",
+            "change-strategy",
+            "--pool",
+            "0x700b6A60ce7EaaEA56F065753d8dcB9653dbAD35",
+            "--asset",
+            "0xA15BB66138824a1c7167f5E85b957d04Dd34E468",
+            "--delta",
+            "-300",
+            "--output",
+            "json",
+            "--dry-run",
+        ])?;
+        assert_eq!(cli.output, OutputFormat::Json);
+        assert!(cli.dry_run);
+        match cli.command {
+            Command::Pool(PoolCommand::ChangeStrategy { deltas, .. }) => {
+                assert_eq!(deltas, vec![-300])
+            }
+            x => bail!("unexpected command {:?}", x),
+        }
+        assert_eq!(network(31337)?, EthereumNet::Local);
+        assert!(network(424242).is_err());
+        assert_eq!(parse_u256("100"), Ok(U256::from(100)));
+        assert_eq!(parse_u256("0x100"), Ok(U256::from(256)));
+        let (name, address) =
+            parse_library("src/Math.sol:Math=0x700b6A60ce7EaaEA56F065753d8dcB9653dbAD35")
+                .map_err(|e| eyre!(e))?;
+        assert_eq!(name, "src/Math.sol:Math");
+        assert_eq!(
+            address,
+            Address::from_str("700b6A60ce7EaaEA56F065753d8dcB9653dbAD35")?
+        );
+        Ok(())
+    }
+}
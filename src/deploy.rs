@@ -0,0 +1,205 @@
+use eth_sdk::artifact::{Artifact, Libraries};
+use eth_sdk::deployments::{Deployment, Deployments};
+use eth_sdk::signer::EthereumSigner;
+use eyre::*;
+use std::path::Path;
+use std::time::Duration;
+use web3::contract::tokens::Tokenize;
+use web3::ethabi::Token;
+use web3::types::{Address, H256, U256};
+use web3::{Transport, Web3};
+
+const STRATEGY_POOL_ARTIFACT: &[u8] = include_bytes!("../abi/internal/strategy_pool.json");
+const STRATEGY_POOL_FACTORY_ARTIFACT: &[u8] =
+    include_bytes!("../abi/internal/strategy_pool_factory.json");
+
+/// A contract to deploy with its constructor arguments
+#[derive(Debug, Clone)]
+pub enum DeployTarget {
+    Escrow {
+        owner: Address,
+    },
+    StrategyPoolFactory {
+        owner: Address,
+        pool_owner: Address,
+    },
+    StrategyPool {
+        name: String,
+        symbol: String,
+        owner: Address,
+        initial_deposit_share_value: U256,
+    },
+}
+
+impl DeployTarget {
+    /// Key in the deployments file when none is given. Pools are told apart by symbol
+    pub fn default_label(&self) -> String {
+        match self {
+            DeployTarget::Escrow { .. } => "Escrow".to_owned(),
+            DeployTarget::StrategyPoolFactory { .. } => "StrategyPoolFactory".to_owned(),
+            DeployTarget::StrategyPool { symbol, .. } => format!("StrategyPool:{}", symbol),
+        }
+    }
+    /// The Foundry, Hardhat or Truffle artifact at `path`, or the one bundled with this tool.
+    /// Escrow has none bundled
+    pub fn artifact(&self, path: Option<&Path>) -> Result<Artifact> {
+        if let Some(path) = path {
+            return Artifact::load(path);
+        }
+        let bundled = match self {
+            DeployTarget::Escrow { .. } => bail!("pass the Forge artifact of Escrow"),
+            DeployTarget::StrategyPoolFactory { .. } => STRATEGY_POOL_FACTORY_ARTIFACT,
+            DeployTarget::StrategyPool { .. } => STRATEGY_POOL_ARTIFACT,
+        };
+        Artifact::from_json(std::str::from_utf8(bundled)?)
+    }
+    fn constructor_args(&self) -> Vec<Token> {
+        match self.clone() {
+            DeployTarget::Escrow { owner } => owner.into_tokens(),
+            DeployTarget::StrategyPoolFactory { owner, pool_owner } => {
+                (owner, pool_owner).into_tokens()
+            }
+            DeployTarget::StrategyPool {
+                name,
+                symbol,
+                owner,
+                initial_deposit_share_value,
+            } => (name, symbol, owner, initial_deposit_share_value).into_tokens(),
+        }
+    }
+}
+
+/// Artifact of the contract deployed under `label`: `<out>/*/<Name>.json` from a Forge build,
+/// or the bundled one. The name is the label up to any `:`, as in `StrategyPool:USDC`
+pub fn find_artifact(label: &str, out: &Path) -> Result<Option<Artifact>> {
+    let name = label.split(':').next().unwrap_or(label);
+    if out.is_dir() {
+        for dir in std::fs::read_dir(out)? {
+            let path = dir?.path().join(format!("{}.json", name));
+            if path.is_file() {
+                return Artifact::load(path).map(Some);
+            }
+        }
+    }
+    let bundled = match name {
+        "StrategyPoolFactory" => STRATEGY_POOL_FACTORY_ARTIFACT,
+        "StrategyPool" => STRATEGY_POOL_ARTIFACT,
+        _ => return Ok(None),
+    };
+    Ok(Some(Artifact::from_json(std::str::from_utf8(bundled)?)?))
+}
+
+/// A contract to deploy, from its artifact with `libraries` linked
+#[derive(Debug, Clone)]
+pub struct DeployPlan {
+    pub target: DeployTarget,
+    pub artifact: Artifact,
+    pub libraries: Libraries,
+    /// Key in the deployments file
+    pub label: String,
+    /// Deploys through CREATE2 when set
+    pub salt: Option<H256>,
+}
+
+impl DeployPlan {
+    /// Where the CREATE2 deployment lands, the same on every chain
+    pub fn predict_address(&self) -> Result<Address> {
+        let salt = self
+            .salt
+            .context("only CREATE2 addresses can be predicted")?;
+        self.artifact.predict_create2_address(
+            &self.libraries,
+            &self.target.constructor_args()[..],
+            salt,
+        )
+    }
+    /// Deploys unless `label` is already deployed, recording it in `deployments`. Returns the
+    /// deployment and whether it was already there
+    pub async fn deploy<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        by: &EthereumSigner,
+        deployments: &mut Deployments,
+    ) -> Result<(Deployment, bool)> {
+        if let Some(deployment) = deployments.deployed(web3, &self.label).await? {
+            return Ok((deployment.clone(), true));
+        }
+        let deployer = self
+            .artifact
+            .deployer(web3.eth())?
+            .libraries(&self.libraries)
+            .poll_interval(Duration::from_secs(3))
+            .max_retries(40);
+        let params = self.target.constructor_args();
+        let (address, receipt) = match self.salt {
+            Some(salt) => {
+                let (contract, receipt) = deployer
+                    .sign_with_key_and_deploy_create2(&params[..], salt, by)
+                    .await?;
+                (contract.address(), receipt)
+            }
+            None => {
+                let (contract, receipt) =
+                    deployer.sign_with_key_and_deploy(&params[..], by).await?;
+                (contract.address(), Some(receipt))
+            }
+        };
+        let deployment = Deployment {
+            address,
+            transaction: receipt.as_ref().map(|x| x.transaction_hash),
+            block: receipt
+                .as_ref()
+                .and_then(|x| x.block_number)
+                .map(|x| x.as_u64()),
+            salt: self.salt,
+        };
+        deployments.insert(&self.label, deployment.clone());
+        deployments.save()?;
+        // deployed to the same address before, by someone else or without recording it
+        Ok((deployment, receipt.is_none()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_artifacts() -> Result<()> {
+        let factory = DeployTarget::StrategyPoolFactory {
+            owner: Address::zero(),
+            pool_owner: Address::zero(),
+        };
+        let artifact = factory.artifact(None)?;
+        assert!(artifact.bytecode.starts_with("0x6080"));
+        let escrow = DeployTarget::Escrow {
+            owner: Address::zero(),
+        };
+        assert!(escrow.artifact(None).is_err());
+        assert_eq!(escrow.default_label(), "Escrow");
+
+        // the address only depends on the salt, the code and the constructor arguments
+        let plan = DeployPlan {
+            label: factory.default_label(),
+            target: factory,
+            artifact,
+            libraries: Libraries::new(),
+            salt: Some(H256::repeat_byte(1)),
+        };
+        let address = plan.predict_address()?;
+        assert_eq!(address, plan.predict_address()?);
+        let other_salt = DeployPlan {
+            salt: Some(H256::zero()),
+            ..plan.clone()
+        };
+        assert_ne!(address, other_salt.predict_address()?);
+        let other_args = DeployPlan {
+            target: DeployTarget::StrategyPoolFactory {
+                owner: Address::zero(),
+                pool_owner: Address::repeat_byte(1),
+            },
+            ..plan.clone()
+        };
+        assert_ne!(address, other_args.predict_address()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! The contracts of this repo on a throwaway Anvil node, for integration tests
+use crate::deploy::DeployTarget;
+use crate::wrappers::escrow::EscrowContract;
+use crate::wrappers::strategy_pool_factory::StrategyPoolFactoryContract;
+use eth_sdk::artifact::Artifact;
+use eth_sdk::devnet::Devnet;
+use eth_sdk::erc20::Erc20Token;
+use eth_sdk::signer::EthereumSigner;
+use eyre::*;
+use std::path::PathBuf;
+use web3::transports::Http;
+use web3::types::U256;
+
+/// Escrow's source is not in this repo, so this is a hand-assembled stand-in with the same ABI
+/// and behaviour: `Ownable`, and `transferTokenTo` as a `SafeERC20` transfer. For devnets only
+const ESCROW_DEVNET_ARTIFACT: &[u8] = include_bytes!("../abi/internal/escrow_devnet.json");
+
+/// Everything is deployed and owned by account 0, which holds the whole token supply
+pub struct TestContracts {
+    pub devnet: Devnet,
+    pub owner: EthereumSigner,
+    pub token: Erc20Token,
+    pub factory: StrategyPoolFactoryContract<Http>,
+    /// From the Forge artifact at `ESCROW_ARTIFACT` or `out/Escrow.sol/Escrow.json` if there is
+    /// one, else the bundled stand-in
+    pub escrow: EscrowContract<Http>,
+}
+
+impl TestContracts {
+    pub async fn deploy() -> Result<Self> {
+        let devnet = Devnet::spawn().await?;
+        let owner = devnet.signer(0)?;
+        let token = devnet
+            .deploy_erc20(&owner, "Test USD", "TUSD", 6, U256::exp10(15))
+            .await?;
+
+        let factory = DeployTarget::StrategyPoolFactory {
+            owner: owner.address,
+            pool_owner: owner.address,
+        };
+        let address = devnet
+            .deploy(
+                &factory.artifact(None)?,
+                (owner.address, owner.address),
+                &owner,
+            )
+            .await?;
+        let factory = StrategyPoolFactoryContract::new(devnet.web3().clone(), address)?;
+
+        let artifact = std::env::var("ESCROW_ARTIFACT")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("out/Escrow.sol/Escrow.json"));
+        let escrow = match artifact.exists() {
+            true => DeployTarget::Escrow {
+                owner: owner.address,
+            }
+            .artifact(Some(&artifact))?,
+            false => Artifact::from_json(std::str::from_utf8(ESCROW_DEVNET_ARTIFACT)?)?,
+        };
+        let address = devnet.deploy(&escrow, owner.address, &owner).await?;
+        let escrow = EscrowContract::new(devnet.web3().clone(), address)?;
+        Ok(Self {
+            devnet,
+            owner,
+            token,
+            factory,
+            escrow,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eth_sdk::utils::wait_for_success;
+
+    #[test]
+    fn test_escrow_devnet_artifact() -> Result<()> {
+        let artifact = Artifact::from_json(std::str::from_utf8(ESCROW_DEVNET_ARTIFACT)?)?;
+        let abi: Vec<serde_json::Value> =
+            serde_json::from_slice(include_bytes!("../abi/internal/escrow.json"))?;
+        assert_eq!(artifact.abi, abi);
+        assert!(!artifact.deployed_bytecode.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_contracts() -> Result<()> {
+        let contracts = TestContracts::deploy().await?;
+        let web3 = contracts.devnet.web3();
+        let owner = &contracts.owner;
+        let trader = contracts.devnet.signer(1)?;
+        assert_eq!(
+            contracts.token.balance_of(owner.address, None).await?,
+            U256::exp10(15)
+        );
+
+        let snapshot = contracts.devnet.snapshot().await?;
+        let hash = contracts
+            .factory
+            .create_pool(owner, trader.address, "Pool", "POOL", U256::exp10(18))
+            .await?;
+        wait_for_success(&web3.eth(), hash).await?;
+        let pools = contracts.factory.list_pools(None).await?;
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].0, trader.address);
+        // the next test starts from a clean factory
+        contracts.devnet.revert(snapshot).await?;
+        assert!(contracts.factory.list_pools(None).await?.is_empty());
+
+        let escrow = &contracts.escrow;
+        assert_eq!(escrow.owner(None).await?, owner.address);
+        let hash = escrow.transfer_ownership(owner, trader.address).await?;
+        wait_for_success(&web3.eth(), hash).await?;
+        assert_eq!(escrow.owner(None).await?, trader.address);
+        assert!(escrow
+            .transfer_ownership(owner, owner.address)
+            .await
+            .is_err());
+
+        contracts
+            .token
+            .transfer_checked(owner, escrow.address(), 100.into())
+            .await?;
+        let hash = escrow
+            .transfer_token_to(&trader, contracts.token.address(), owner.address, 60.into())
+            .await?;
+        wait_for_success(&web3.eth(), hash).await?;
+        assert_eq!(
+            contracts.token.balance_of(escrow.address(), None).await?,
+            U256::from(40)
+        );
+        Ok(())
+    }
+}
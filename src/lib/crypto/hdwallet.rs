@@ -0,0 +1,166 @@
+//! BIP-39 mnemonics and BIP-32/BIP-44 key derivation for secp256k1 accounts
+use crate::secp256k1::Secp256k1Key;
+use crate::PublicExpontent;
+use bip32::{ChildNumber, DerivationPath, Prefix, XPrv, XPub};
+use bip39::Mnemonic;
+use eyre::*;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use rand_core::{OsRng, RngCore};
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// BIP-44 external chain of the first Ethereum account, `m/44'/60'/0'/0`
+pub const ETHEREUM_CHAIN_PATH: &str = "m/44'/60'/0'/0";
+
+pub struct HdWallet {
+    seed: [u8; 64],
+}
+impl HdWallet {
+    /// Restores a wallet from an english BIP-39 phrase and an optional passphrase ("" for none)
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self> {
+        let mnemonic = Mnemonic::parse_normalized(phrase.trim())
+            .map_err(|e| eyre!("invalid mnemonic: {}", e))?;
+        Ok(Self {
+            seed: mnemonic.to_seed(passphrase),
+        })
+    }
+    /// Generates a new 24 words wallet, returning the phrase so it can be backed up
+    pub fn generate(passphrase: &str) -> Result<(Self, String)> {
+        let mut entropy = [0u8; 32];
+        OsRng.fill_bytes(&mut entropy);
+        let phrase = Mnemonic::from_entropy(&entropy)
+            .map_err(|e| eyre!("generating mnemonic: {}", e))?
+            .to_string();
+        Ok((Self::from_mnemonic(&phrase, passphrase)?, phrase))
+    }
+    pub fn derive(&self, path: &str) -> Result<HdKey> {
+        let derivation_path =
+            DerivationPath::from_str(path).map_err(|e| eyre!("invalid path {}: {}", path, e))?;
+        let key = XPrv::derive_from_path(self.seed, &derivation_path)
+            .map_err(|e| eyre!("derive {}: {}", path, e))?;
+        Ok(HdKey {
+            path: path.to_owned(),
+            xpub: key.public_key().to_string(Prefix::XPUB),
+            key: key.private_key().clone().into(),
+        })
+    }
+    /// The account at `m/44'/60'/0'/0/{index}`, as used by Geth, Foundry and MetaMask
+    pub fn ethereum_account(&self, index: u32) -> Result<HdKey> {
+        self.derive(&format!("{}/{}", ETHEREUM_CHAIN_PATH, index))
+    }
+    /// Extended public key of `m/44'/60'/0'/0`, enough to derive every account address
+    pub fn ethereum_xpub(&self) -> Result<String> {
+        Ok(self.derive(ETHEREUM_CHAIN_PATH)?.xpub())
+    }
+}
+
+/// A derived key, which signs as a [`Secp256k1Key`]
+#[derive(Clone)]
+pub struct HdKey {
+    path: String,
+    xpub: String,
+    key: Secp256k1Key,
+}
+impl HdKey {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn xpub(&self) -> String {
+        self.xpub.clone()
+    }
+    pub fn into_key(self) -> Secp256k1Key {
+        self.key
+    }
+}
+impl Deref for HdKey {
+    type Target = Secp256k1Key;
+    fn deref(&self) -> &Secp256k1Key {
+        &self.key
+    }
+}
+
+/// Derives the public exponent of child `index` from an extended public key, e.g. the one
+/// returned by [`HdWallet::ethereum_xpub`], without access to any private key
+pub fn derive_public_exponent(xpub: &str, index: u32) -> Result<PublicExpontent> {
+    let xpub = XPub::from_str(xpub).map_err(|e| eyre!("invalid xpub: {}", e))?;
+    let child = xpub
+        .derive_child(ChildNumber::new(index, false).map_err(|e| eyre!("index {}: {}", index, e))?)
+        .map_err(|e| eyre!("derive child {}: {}", index, e))?;
+    Ok(child
+        .public_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec()
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PrivateKey, PublicKey, Signer};
+
+    // default Anvil/Hardhat accounts
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    #[test]
+    fn test_derive_ethereum_accounts() -> Result<()> {
+        let wallet = HdWallet::from_mnemonic(MNEMONIC, "")?;
+        let key = wallet.ethereum_account(0)?;
+        assert_eq!(key.path(), "m/44'/60'/0'/0/0");
+        assert_eq!(
+            hex::encode(key.private_exponent()?.content),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        let key = wallet.ethereum_account(1)?;
+        assert_eq!(
+            hex::encode(key.private_exponent()?.content),
+            "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let (wallet, phrase) = HdWallet::generate("")?;
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        let restored = HdWallet::from_mnemonic(&phrase, "")?;
+        assert!(
+            wallet.ethereum_account(0)?.private_exponent()?
+                == restored.ethereum_account(0)?.private_exponent()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xpub_derives_same_public_keys() -> Result<()> {
+        let wallet = HdWallet::from_mnemonic(MNEMONIC, "")?;
+        let xpub = wallet.ethereum_xpub()?;
+        for index in 0..3 {
+            let expected = wallet.ethereum_account(index)?.public_exponent()?;
+            assert!(derive_public_exponent(&xpub, index)? == expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_passphrase_changes_accounts() -> Result<()> {
+        let plain = HdWallet::from_mnemonic(MNEMONIC, "")?.ethereum_account(0)?;
+        let protected = HdWallet::from_mnemonic(MNEMONIC, "passphrase")?.ethereum_account(0)?;
+        assert!(plain.private_exponent()? != protected.private_exponent()?);
+        assert!(HdWallet::from_mnemonic("test test junk", "").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sign_and_verify() -> Result<()> {
+        let key = HdWallet::from_mnemonic(MNEMONIC, "")?.ethereum_account(0)?;
+        let digest = [7u8; 32];
+        let signature = key.sign(&digest).await?;
+        assert!(key.verify(&digest, &signature)?);
+        let compact = key.sign_compact(&digest).await?;
+        assert_eq!(compact.len(), 64);
+        assert!(key.verify(&digest, &compact)?);
+        assert!(!key.verify(&[8u8; 32], &compact)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+//! Web3 Secret Storage (keystore v3) as written by Geth, Foundry `cast wallet` and friends.
+//! https://ethereum.org/en/developers/docs/data-structures-and-encoding/web3-secret-storage/
+use crate::secp256k1::Secp256k1Key;
+use crate::{PrivateExpontent, PrivateKey};
+use aes::cipher::{KeyIvInit, StreamCipher};
+use eyre::*;
+use hmac::Hmac;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use std::path::{Path, PathBuf};
+use subtle::ConstantTimeEq;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+const CIPHER: &str = "aes-128-ctr";
+const KDF_SCRYPT: &str = "scrypt";
+const KDF_PBKDF2: &str = "pbkdf2";
+const PRF_HMAC_SHA256: &str = "hmac-sha256";
+const DKLEN: u32 = 32;
+// keystore files are untrusted input, so the memory and time they may cost is bounded
+const MAX_DKLEN: u32 = 64;
+/// Of each of the `128 * r * n` and `128 * r * p` bytes scrypt allocates, the first being
+/// 256 MiB with Geth's `StandardScryptN`
+const MAX_SCRYPT_MEMORY: u64 = 256 << 20;
+/// The time scrypt takes grows with `p` at a constant memory
+const MAX_SCRYPT_P: u32 = 16;
+const MAX_PBKDF2_C: u32 = 10_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CipherParams {
+    #[serde(with = "hex")]
+    pub iv: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum KdfParams {
+    Scrypt {
+        dklen: u32,
+        n: u32,
+        p: u32,
+        r: u32,
+        #[serde(with = "hex")]
+        salt: Vec<u8>,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: u32,
+        prf: String,
+        #[serde(with = "hex")]
+        salt: Vec<u8>,
+    },
+}
+impl KdfParams {
+    /// Geth's `StandardScryptN`/`StandardScryptP`
+    pub fn scrypt() -> Self {
+        Self::Scrypt {
+            dklen: DKLEN,
+            n: 1 << 18,
+            p: 1,
+            r: 8,
+            salt: random_bytes(32),
+        }
+    }
+    /// Geth's `LightScryptN`/`LightScryptP`, for tests and short lived keys
+    pub fn scrypt_light() -> Self {
+        Self::Scrypt {
+            dklen: DKLEN,
+            n: 1 << 12,
+            p: 6,
+            r: 8,
+            salt: random_bytes(32),
+        }
+    }
+    pub fn pbkdf2() -> Self {
+        Self::Pbkdf2 {
+            c: 262144,
+            dklen: DKLEN,
+            prf: PRF_HMAC_SHA256.to_owned(),
+            salt: random_bytes(32),
+        }
+    }
+    fn name(&self) -> &'static str {
+        match self {
+            KdfParams::Scrypt { .. } => KDF_SCRYPT,
+            KdfParams::Pbkdf2 { .. } => KDF_PBKDF2,
+        }
+    }
+    fn derive_key(&self, password: &str) -> Result<Vec<u8>> {
+        match self {
+            KdfParams::Scrypt {
+                dklen,
+                n,
+                p,
+                r,
+                salt,
+            } => {
+                ensure!(
+                    (DKLEN..=MAX_DKLEN).contains(dklen),
+                    "unsupported scrypt dklen: {}",
+                    dklen
+                );
+                check_scrypt_params(*n, *r, *p)?;
+                let log_n = n.trailing_zeros() as u8;
+                let mut key = vec![0u8; *dklen as usize];
+                match scrypt::Params::new(log_n, *r, *p) {
+                    Ok(params) => scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+                        .map_err(|e| eyre!("scrypt failed: {}", e))?,
+                    Err(_) => scrypt_unbounded(
+                        password.as_bytes(),
+                        salt,
+                        *n as usize,
+                        *r as usize,
+                        *p as usize,
+                        &mut key,
+                    ),
+                }
+                Ok(key)
+            }
+            KdfParams::Pbkdf2 {
+                c,
+                dklen,
+                prf,
+                salt,
+            } => {
+                ensure!(
+                    (DKLEN..=MAX_DKLEN).contains(dklen),
+                    "unsupported pbkdf2 dklen: {}",
+                    dklen
+                );
+                ensure!(*c <= MAX_PBKDF2_C, "pbkdf2 c too large: {}", c);
+                ensure!(prf == PRF_HMAC_SHA256, "unsupported pbkdf2 prf: {}", prf);
+                let mut key = vec![0u8; *dklen as usize];
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, *c, &mut key);
+                Ok(key)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    #[serde(with = "hex")]
+    pub ciphertext: Vec<u8>,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    #[serde(with = "hex")]
+    pub mac: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeystoreV3 {
+    /// Lowercase hex address without `0x`, as written by Geth. Not authenticated by the MAC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    // MyEtherWallet writes `Crypto`
+    #[serde(alias = "Crypto")]
+    pub crypto: KeystoreCrypto,
+    pub id: uuid::Uuid,
+    pub version: u32,
+}
+
+impl KeystoreV3 {
+    pub fn encrypt(secret: &PrivateExpontent, password: &str, kdf: KdfParams) -> Result<Self> {
+        let key = kdf.derive_key(password)?;
+        let iv = random_bytes(16);
+        let mut ciphertext = secret.content.clone();
+        Aes128Ctr::new(key[..16].into(), iv.as_slice().into()).apply_keystream(&mut ciphertext);
+        let mac = compute_mac(&key, &ciphertext);
+        Ok(Self {
+            address: None,
+            crypto: KeystoreCrypto {
+                cipher: CIPHER.to_owned(),
+                cipherparams: CipherParams { iv },
+                ciphertext,
+                kdf: kdf.name().to_owned(),
+                kdfparams: kdf,
+                mac,
+            },
+            id: uuid::Uuid::new_v4(),
+            version: 3,
+        })
+    }
+    pub fn with_address(mut self, address: &str) -> Self {
+        let address = address.trim_start_matches("0x").to_lowercase();
+        self.address = Some(address);
+        self
+    }
+    pub fn decrypt(&self, password: &str) -> Result<PrivateExpontent> {
+        ensure!(
+            self.version == 3,
+            "unsupported keystore version: {}",
+            self.version
+        );
+        let crypto = &self.crypto;
+        ensure!(
+            crypto.cipher == CIPHER,
+            "unsupported cipher: {}",
+            crypto.cipher
+        );
+        ensure!(
+            crypto.kdf == crypto.kdfparams.name(),
+            "kdf {} does not match kdfparams",
+            crypto.kdf
+        );
+        ensure!(
+            crypto.cipherparams.iv.len() == 16,
+            "invalid iv length: {}",
+            crypto.cipherparams.iv.len()
+        );
+        let key = crypto.kdfparams.derive_key(password)?;
+        let mac = compute_mac(&key, &crypto.ciphertext);
+        if !bool::from(mac.ct_eq(&crypto.mac)) {
+            bail!("keystore mac mismatch: wrong password or corrupted file")
+        }
+        let mut secret = crypto.ciphertext.clone();
+        Aes128Ctr::new(key[..16].into(), crypto.cipherparams.iv.as_slice().into())
+            .apply_keystream(&mut secret);
+        Ok(secret.into())
+    }
+    /// Encrypts the secp256k1 key of `key`
+    pub fn encrypt_key(key: &dyn PrivateKey, password: &str, kdf: KdfParams) -> Result<Self> {
+        Self::encrypt(&key.private_exponent()?, password, kdf)
+    }
+    /// Decrypts into a key that signs in memory
+    pub fn decrypt_key(&self, password: &str) -> Result<Secp256k1Key> {
+        Secp256k1Key::from_private_exponent(&self.decrypt(password)?)
+    }
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("parsing keystore")
+    }
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("reading keystore {}", path.display()))?;
+        Self::from_json(&json)
+    }
+    /// Writes the keystore into `dir` using Geth's `UTC--<timestamp>--<address>` file name
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let name = format!(
+            "UTC--{}--{}",
+            chrono::Utc::now().format("%Y-%m-%dT%H-%M-%S%.9fZ"),
+            self.address.clone().unwrap_or_else(|| self.id.to_string())
+        );
+        let path = dir.join(name);
+        std::fs::write(&path, self.to_json()?)?;
+        Ok(path)
+    }
+}
+
+fn compute_mac(key: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+    let mut hasher = Keccak256::new();
+    hasher.update(&key[16..32]);
+    hasher.update(ciphertext);
+    hasher.finalize().to_vec()
+}
+
+fn check_scrypt_params(n: u32, r: u32, p: u32) -> Result<()> {
+    ensure!(n.is_power_of_two(), "scrypt n is not a power of 2: {}", n);
+    ensure!(r > 0 && p > 0, "invalid scrypt params: r={} p={}", r, p);
+    ensure!(p <= MAX_SCRYPT_P, "scrypt p too large: {}", p);
+    let block = 128 * u64::from(r);
+    ensure!(
+        block.saturating_mul(u64::from(n.max(p))) <= MAX_SCRYPT_MEMORY,
+        "scrypt needs too much memory: n={} r={} p={}",
+        n,
+        r,
+        p
+    );
+    Ok(())
+}
+
+/// scrypt without the RFC 7914 `N < 2^(128 * r / 8)` bound. The `scrypt` crate enforces it but
+/// Geth does not, and the official test vector (`N = 2^18`, `r = 1`) violates it
+fn scrypt_unbounded(password: &[u8], salt: &[u8], n: usize, r: usize, p: usize, out: &mut [u8]) {
+    let words = 32 * r;
+    let mut b = vec![0u8; 128 * r * p];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password, salt, 1, &mut b);
+    let mut v = vec![0u32; n * words];
+    for chunk in b.chunks_mut(128 * r) {
+        let mut x: Vec<u32> = chunk
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        let mut tmp = vec![0u32; words];
+        for i in 0..n {
+            v[i * words..(i + 1) * words].copy_from_slice(&x);
+            block_mix(&x, &mut tmp, r);
+            std::mem::swap(&mut x, &mut tmp);
+        }
+        for _ in 0..n {
+            let j = x[words - 16] as usize & (n - 1);
+            for (x, v) in x.iter_mut().zip(&v[j * words..(j + 1) * words]) {
+                *x ^= v;
+            }
+            block_mix(&x, &mut tmp, r);
+            std::mem::swap(&mut x, &mut tmp);
+        }
+        for (c, x) in chunk.chunks_mut(4).zip(x) {
+            c.copy_from_slice(&x.to_le_bytes());
+        }
+    }
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password, &b, 1, out);
+}
+
+fn block_mix(input: &[u32], output: &mut [u32], r: usize) {
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&input[(2 * r - 1) * 16..]);
+    for i in 0..2 * r {
+        for (x, y) in x.iter_mut().zip(&input[i * 16..(i + 1) * 16]) {
+            *x ^= y;
+        }
+        salsa20_8(&mut x);
+        let dst = if i % 2 == 0 { i / 2 } else { r + i / 2 } * 16;
+        output[dst..dst + 16].copy_from_slice(&x);
+    }
+}
+
+fn salsa20_8(b: &mut [u32; 16]) {
+    fn quarter(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+    let mut x = *b;
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for (b, x) in b.iter_mut().zip(x) {
+        *b = b.wrapping_add(x);
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from the Web3 Secret Storage definition
+    const PASSWORD: &str = "testpassword";
+    const SECRET: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+    const PBKDF2_VECTOR: &str = r#"{
+        "crypto" : {
+            "cipher" : "aes-128-ctr",
+            "cipherparams" : {
+                "iv" : "6087dab2f9fdbbfaddc31a909735c1e6"
+            },
+            "ciphertext" : "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf" : "pbkdf2",
+            "kdfparams" : {
+                "c" : 262144,
+                "dklen" : 32,
+                "prf" : "hmac-sha256",
+                "salt" : "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac" : "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id" : "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version" : 3
+    }"#;
+    const SCRYPT_VECTOR: &str = r#"{
+        "crypto" : {
+            "cipher" : "aes-128-ctr",
+            "cipherparams" : {
+                "iv" : "83dbcc02d8ccb40e466191a123791e0e"
+            },
+            "ciphertext" : "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf" : "scrypt",
+            "kdfparams" : {
+                "dklen" : 32,
+                "n" : 262144,
+                "p" : 8,
+                "r" : 1,
+                "salt" : "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac" : "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id" : "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version" : 3
+    }"#;
+
+    #[test]
+    fn test_decrypt_pbkdf2_vector() -> Result<()> {
+        let keystore = KeystoreV3::from_json(PBKDF2_VECTOR)?;
+        let secret = keystore.decrypt(PASSWORD)?;
+        assert_eq!(hex::encode(secret.content), SECRET);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_scrypt_vector() -> Result<()> {
+        let keystore = KeystoreV3::from_json(SCRYPT_VECTOR)?;
+        let secret = keystore.decrypt(PASSWORD)?;
+        assert_eq!(hex::encode(secret.content), SECRET);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrypt_unbounded_matches_scrypt() -> Result<()> {
+        let params = scrypt::Params::new(10, 8, 2).unwrap();
+        let mut expected = [0u8; 32];
+        scrypt::scrypt(b"password", b"salt", &params, &mut expected).unwrap();
+        let mut key = [0u8; 32];
+        scrypt_unbounded(b"password", b"salt", 1 << 10, 8, 2, &mut key);
+        assert_eq!(key, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kdf_limits() -> Result<()> {
+        let mut keystore = KeystoreV3::from_json(SCRYPT_VECTOR)?;
+        let limits = [
+            (1 << 30, 1, 8, 32, "too much memory"),
+            // 16 GiB with a small r * p
+            (1 << 21, 64, 1, 32, "too much memory"),
+            (1 << 19, 8, 1, 32, "too much memory"),
+            (1 << 10, 1, 1 << 20, 32, "p too large"),
+            (1 << 18, 1, 8, 1 << 30, "unsupported scrypt dklen"),
+        ];
+        for (n, r, p, dklen, error) in limits {
+            keystore.crypto.kdfparams = KdfParams::Scrypt {
+                dklen,
+                n,
+                p,
+                r,
+                salt: vec![0; 32],
+            };
+            match keystore.decrypt(PASSWORD) {
+                Err(err) => assert!(err.to_string().contains(error), "{}", err),
+                Ok(_) => bail!("decrypted despite {}", error),
+            }
+        }
+        // Geth's standard and light parameters
+        check_scrypt_params(1 << 18, 8, 1)?;
+        check_scrypt_params(1 << 12, 8, 6)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_wrong_password() -> Result<()> {
+        let keystore = KeystoreV3::from_json(PBKDF2_VECTOR)?;
+        assert!(keystore.decrypt("wrongpassword").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypt_roundtrip() -> Result<()> {
+        let secret: PrivateExpontent = hex::decode(SECRET)?.into();
+        let keystore = KeystoreV3::encrypt(&secret, PASSWORD, KdfParams::scrypt_light())?
+            .with_address("0x008AEEDA4D805471DF9B2A5B0F38A0C3BCBA786B");
+        let json = keystore.to_json()?;
+        let keystore = KeystoreV3::from_json(&json)?;
+        assert_eq!(
+            keystore.address.as_deref(),
+            Some("008aeeda4d805471df9b2a5b0f38a0c3bcba786b")
+        );
+        assert_eq!(keystore.crypto.kdf, "scrypt");
+        assert!(keystore.decrypt(PASSWORD)? == secret);
+
+        let key = keystore.decrypt_key(PASSWORD)?;
+        let keystore = KeystoreV3::encrypt_key(&key, "other", KdfParams::scrypt_light())?;
+        assert!(keystore.decrypt_key("other")?.private_exponent()? == secret);
+        Ok(())
+    }
+}
@@ -1,11 +1,22 @@
+pub mod hdwallet;
+pub mod keystore;
 pub mod openssl;
+pub mod secp256k1;
 pub mod securosys;
+#[cfg(any(test, feature = "mock"))]
+pub mod securosys_mock;
 pub mod securosys_pkcs11;
 pub mod utils;
 
-use der::Reader;
+use der::{Encode, Reader};
 use eyre::*;
 
+// DER prefixes `openssl ... -outform DER` produces, followed by the raw key material
+pub(crate) const SECP256K1_SPKI_PREFIX: &str = "3056301006072a8648ce3d020106052b8104000a034200";
+pub(crate) const SECP256K1_PKCS8_PREFIX: &str =
+    "303e020100301006072a8648ce3d020106052b8104000a042730250201010420";
+pub(crate) const ED25519_SPKI_PREFIX: &str = "302a300506032b6570032100";
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct DerPublicKey {
     pub content: Vec<u8>,
@@ -89,3 +100,34 @@ pub fn decode_ans1_signature(result: &[u8]) -> Result<Vec<u8>> {
         .map_err(|x| eyre!("Failed to parse ansi message: {:?}", x))?;
     Ok(seq.map(|x| x.as_bytes()).concat())
 }
+
+/// Like `decode_ans1_signature`, but left pads `r` and `s` to `len` bytes each
+pub fn decode_ans1_signature_padded(result: &[u8], len: usize) -> Result<Vec<u8>> {
+    let seq: [der::asn1::UIntRef; 2] = der::SliceReader::new(result)
+        .unwrap() // safety: always success
+        .decode()
+        .map_err(|x| eyre!("Failed to parse ansi message: {:?}", x))?;
+    let mut raw = vec![0u8; 2 * len];
+    for (i, x) in seq.iter().enumerate() {
+        let bytes = x.as_bytes();
+        ensure!(bytes.len() <= len, "integer too long: {}", bytes.len());
+        raw[(i + 1) * len - bytes.len()..(i + 1) * len].copy_from_slice(bytes);
+    }
+    Ok(raw)
+}
+
+/// Inverse of `decode_ans1_signature`: encodes a raw `r || s` signature in ANS1 DER format
+pub fn encode_ans1_signature(raw: &[u8]) -> Result<Vec<u8>> {
+    let (r, s) = raw.split_at(raw.len() / 2);
+    ensure!(
+        r.len() == s.len(),
+        "odd raw signature length: {}",
+        raw.len()
+    );
+    let seq = [
+        der::asn1::UIntRef::new(r).map_err(|x| eyre!("Invalid r: {:?}", x))?,
+        der::asn1::UIntRef::new(s).map_err(|x| eyre!("Invalid s: {:?}", x))?,
+    ];
+    seq.to_vec()
+        .map_err(|x| eyre!("Failed to encode ansi message: {:?}", x))
+}
@@ -41,10 +41,7 @@ impl OpensslPrivateKey {
             let out = Command::new("openssl").args(args).output()?;
             ensure_success(&out).context("generating key")?;
         }
-        Ok(Self {
-            filename: filename,
-            keytype,
-        })
+        Ok(Self { filename, keytype })
     }
     pub fn rename(&mut self, name: &str) -> Result<()> {
         let new_name = format!("{}.pem", name);
@@ -0,0 +1,112 @@
+//! secp256k1 keys signing in memory, e.g. decrypted from a keystore or derived from a mnemonic
+use crate::{
+    DerPrivateKey, DerPublicKey, PrivateExpontent, PrivateKey, PublicExpontent, PublicKey, Signer,
+    SECP256K1_PKCS8_PREFIX, SECP256K1_SPKI_PREFIX,
+};
+use eyre::*;
+use k256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use k256::ecdsa::{Signature, SigningKey};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+#[derive(Clone)]
+pub struct Secp256k1Key {
+    key: SigningKey,
+}
+impl Secp256k1Key {
+    pub fn from_private_exponent(secret: &PrivateExpontent) -> Result<Self> {
+        let key = SigningKey::from_bytes(&secret.content)
+            .map_err(|e| eyre!("invalid secp256k1 key: {}", e))?;
+        Ok(Self { key })
+    }
+    /// 32 bytes in hex, with or without `0x`
+    pub fn from_hex(secret: &str) -> Result<Self> {
+        let secret = secret.trim();
+        let secret = hex::decode(secret.strip_prefix("0x").unwrap_or(secret))
+            .context("private key is not hex")?;
+        Self::from_private_exponent(&secret.into())
+    }
+}
+impl From<SigningKey> for Secp256k1Key {
+    fn from(key: SigningKey) -> Self {
+        Self { key }
+    }
+}
+impl PublicKey for Secp256k1Key {
+    fn public_key(&self) -> Result<DerPublicKey> {
+        let mut der = hex::decode(SECP256K1_SPKI_PREFIX)?;
+        der.extend_from_slice(&self.public_exponent()?.content);
+        Ok(der.into())
+    }
+    fn public_exponent(&self) -> Result<PublicExpontent> {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        Ok(point.as_bytes().to_vec().into())
+    }
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+        let signature = match signature.len() {
+            64 => Signature::try_from(signature),
+            _ => Signature::from_der(signature),
+        }
+        .map_err(|e| eyre!("malformed signature: {}", e))?;
+        Ok(self
+            .key
+            .verifying_key()
+            .verify_prehash(data, &signature)
+            .is_ok())
+    }
+}
+#[async_trait::async_trait]
+impl Signer for Secp256k1Key {
+    /// Signs `data` as a 32 bytes prehash, same as `Secp256k1None`
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature: Signature = self
+            .key
+            .sign_prehash(data)
+            .map_err(|e| eyre!("signing: {}", e))?;
+        Ok(signature.to_der().as_bytes().to_vec())
+    }
+    async fn sign_compact(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature: Signature = self
+            .key
+            .sign_prehash(data)
+            .map_err(|e| eyre!("signing: {}", e))?;
+        Ok(signature.as_ref().to_vec())
+    }
+}
+impl PrivateKey for Secp256k1Key {
+    fn private_key(&self) -> Result<DerPrivateKey> {
+        let mut der = hex::decode(SECP256K1_PKCS8_PREFIX)?;
+        der.extend_from_slice(&self.private_exponent()?.content);
+        Ok(der.into())
+    }
+    fn private_exponent(&self) -> Result<PrivateExpontent> {
+        Ok(self.key.to_bytes().to_vec().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sign_and_verify() -> Result<()> {
+        let secret =
+            hex::decode("7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d")?;
+        let key = Secp256k1Key::from_private_exponent(&secret.clone().into())?;
+        assert_eq!(key.private_exponent()?.content, secret);
+        assert!(key
+            .public_key()?
+            .content
+            .starts_with(&hex::decode(SECP256K1_SPKI_PREFIX)?));
+        let digest = [7u8; 32];
+        assert!(key.verify(&digest, &key.sign(&digest).await?)?);
+        let compact = key.sign_compact(&digest).await?;
+        assert_eq!(compact.len(), 64);
+        assert!(key.verify(&digest, &compact)?);
+        assert!(!key.verify(&[8u8; 32], &compact)?);
+        assert!(Secp256k1Key::from_private_exponent(&vec![0u8; 32].into()).is_err());
+        let parsed = Secp256k1Key::from_hex(&format!("0x{}", hex::encode(&secret)))?;
+        assert!(parsed.public_exponent()? == key.public_exponent()?);
+        assert!(Secp256k1Key::from_hex("0x1234").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,737 @@
+//! Client for a remote signer speaking the Securosys Primus TSB REST API.
+//!
+//! Keys may be guarded by an approval policy: signing then only submits a request, which is
+//! executed once enough approvers signed it. [`spawn_auto_approver`] approves every pending
+//! request for one approver key, and `securosys_mock`, with the `mock`
+//! feature, serves the API locally.
+use crate::{
+    decode_ans1_signature_padded, CryptoAlgorithm, DerPrivateKey, DerPublicKey, PublicExpontent,
+    PublicKey, Signer, SECP256K1_SPKI_PREFIX,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use eyre::*;
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::signature::Verifier;
+use k256::ecdsa::{Signature, VerifyingKey};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct SecurosysConfig {
+    /// Base url of the TSB, e.g. `https://sandbox.securosys.com`
+    pub url: String,
+    /// Bearer token, if the TSB requires one
+    pub access_token: Option<String>,
+    /// How often a pending sign request is polled
+    pub poll_interval: Duration,
+    /// How long to wait for a sign request to be approved
+    pub approval_timeout: Duration,
+}
+impl SecurosysConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            access_token: None,
+            poll_interval: Duration::from_secs(1),
+            approval_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Reads `SECUROSYS_URL` and the optional `SECUROSYS_TOKEN`
+pub fn get_securosys_token() -> Result<SecurosysConfig> {
+    let url = std::env::var("SECUROSYS_URL").context("SECUROSYS_URL is not set")?;
+    Ok(SecurosysConfig {
+        access_token: std::env::var("SECUROSYS_TOKEN").ok(),
+        ..SecurosysConfig::new(&url)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Policy {
+    pub rule_use: Rule,
+    pub key_status: KeyStatus,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub tokens: Vec<ApprovalToken>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalToken {
+    pub name: String,
+    pub timelock: u64,
+    pub timeout: u64,
+    pub groups: Vec<ApprovalGroup>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalGroup {
+    pub name: String,
+    pub quorum: usize,
+    pub approvals: Vec<Approver>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approver {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    /// Base64 of the approver's DER public key
+    pub value: String,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyStatus {
+    pub blocked: bool,
+}
+impl Policy {
+    /// A policy without approvers: signing is executed right away
+    pub fn none() -> Self {
+        Self {
+            rule_use: Rule { tokens: vec![] },
+            key_status: KeyStatus { blocked: false },
+        }
+    }
+    /// Whether every group reached its quorum, given the base64 public keys that approved
+    pub fn is_approved(&self, approved_by: &HashSet<String>) -> bool {
+        self.rule_use.tokens.iter().all(|token| {
+            token.groups.iter().all(|group| {
+                let approvals = group
+                    .approvals
+                    .iter()
+                    .filter(|x| approved_by.contains(&x.value))
+                    .count();
+                approvals >= group.quorum
+            })
+        })
+    }
+    pub fn approvers(&self) -> impl Iterator<Item = &Approver> {
+        self.rule_use
+            .tokens
+            .iter()
+            .flat_map(|x| x.groups.iter())
+            .flat_map(|x| x.approvals.iter())
+    }
+}
+
+pub fn make_single_approver_policy(name: String, public_key: DerPublicKey) -> Policy {
+    make_multi_approver_policy(vec![(name, public_key)], 1)
+}
+/// Any `quorum` of `approvers` must approve each signature
+pub fn make_multi_approver_policy(approvers: Vec<(String, DerPublicKey)>, quorum: usize) -> Policy {
+    let approvals = approvers
+        .into_iter()
+        .map(|(name, public_key)| Approver {
+            kind: "public_key".to_owned(),
+            name,
+            value: BASE64.encode(public_key.content),
+        })
+        .collect();
+    Policy {
+        rule_use: Rule {
+            tokens: vec![ApprovalToken {
+                name: "Main Token".to_owned(),
+                timelock: 0,
+                timeout: 0,
+                groups: vec![ApprovalGroup {
+                    name: "Main Group".to_owned(),
+                    quorum,
+                    approvals,
+                }],
+            }],
+        },
+        key_status: KeyStatus { blocked: false },
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImportKeyRequest {
+    pub label: String,
+    pub algorithm: String,
+    pub curve_oid: String,
+    pub private_key: String,
+    pub public_key: String,
+    pub policy: Policy,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct KeyLabel {
+    pub label: String,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KeyAttributes {
+    pub label: String,
+    pub algorithm: String,
+    pub public_key: String,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SignRequest {
+    pub sign_request: SignPayload,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SignPayload {
+    pub payload: String,
+    pub payload_type: String,
+    pub sign_key_name: String,
+    pub signature_algorithm: String,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SignResponse {
+    /// Set when the key has no policy
+    pub signature: Option<String>,
+    /// Set when the request awaits approval
+    pub sign_request_id: Option<String>,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RequestState {
+    Pending,
+    Executed,
+    Rejected,
+    Expired,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestStatus {
+    pub id: String,
+    pub status: RequestState,
+    /// Base64 signature, once executed
+    pub result: Option<String>,
+    #[serde(default)]
+    pub not_yet_approved_by: Vec<String>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FilteredRequests {
+    pub approver_public_key: String,
+    pub timestamp: String,
+    pub timestamp_signature: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApprovalTask {
+    pub id: String,
+    /// Base64 of the bytes the approver signs
+    pub approval_to_be_signed: String,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Approval {
+    pub approval_to_be_signed: String,
+    pub approver_public_key: String,
+    pub signature: String,
+}
+
+fn signature_algorithm(keytype: CryptoAlgorithm) -> &'static str {
+    match keytype {
+        CryptoAlgorithm::Secp256k1None => "NONE_WITH_ECDSA",
+        CryptoAlgorithm::Secp256k1Sha256 => "SHA256_WITH_ECDSA",
+        CryptoAlgorithm::Ed25519 => "EDDSA",
+    }
+}
+
+/// Everything but RFC 3986 unreserved characters is escaped
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Escapes a key label or request id, which may contain `/`, `?` or spaces
+fn path_segment(s: &str) -> String {
+    utf8_percent_encode(s, PATH_SEGMENT).to_string()
+}
+
+pub struct SecurosysSdk {
+    client: reqwest::Client,
+    config: SecurosysConfig,
+}
+impl SecurosysSdk {
+    pub fn new(config: SecurosysConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self { client, config })
+    }
+    pub fn config(&self) -> &SecurosysConfig {
+        &self.config
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&(impl Serialize + Sync)>,
+    ) -> Result<reqwest::Response> {
+        let url = format!("{}{}", self.config.url, path);
+        debug!("{} {}", method, url);
+        let mut request = self.client.request(method.clone(), &url);
+        if let Some(token) = &self.config.access_token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        request
+            .send()
+            .await
+            .with_context(|| format!("{} {}", method, url))
+    }
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&(impl Serialize + Sync)>,
+    ) -> Result<T> {
+        let response = self.send(method.clone(), path, body).await?;
+        let status = response.status();
+        let text = response.text().await?;
+        ensure!(
+            status.is_success(),
+            "{} {} failed with {}: {}",
+            method,
+            path,
+            status,
+            text
+        );
+        serde_json::from_str(&text).with_context(|| format!("parsing {}: {}", path, text))
+    }
+
+    pub async fn import_key_secp256k1(
+        &self,
+        label: &str,
+        policy: Policy,
+        private_key: DerPrivateKey,
+        public_key: DerPublicKey,
+    ) -> Result<()> {
+        let request = ImportKeyRequest {
+            label: label.to_owned(),
+            algorithm: "EC".to_owned(),
+            curve_oid: "1.3.132.0.10".to_owned(),
+            private_key: BASE64.encode(private_key.content),
+            public_key: BASE64.encode(public_key.content),
+            policy,
+        };
+        let _: serde_json::Value = self
+            .call(Method::POST, "/v1/key/import/plain", Some(&request))
+            .await?;
+        info!("imported key {}", label);
+        Ok(())
+    }
+    /// Deletes the key if it exists
+    pub async fn delete_key(&self, label: &str) -> Result<()> {
+        let path = format!("/v1/key/{}", path_segment(label));
+        let response = self.send(Method::DELETE, &path, None::<&()>).await?;
+        match response.status() {
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            status => bail!(
+                "DELETE {} failed with {}: {}",
+                path,
+                status,
+                response.text().await?
+            ),
+        }
+    }
+    pub async fn get_public_key(&self, label: &str) -> Result<DerPublicKey> {
+        let request = KeyLabel {
+            label: label.to_owned(),
+        };
+        let attributes: KeyAttributes = self
+            .call(Method::POST, "/v1/key/attributes", Some(&request))
+            .await?;
+        Ok(BASE64.decode(attributes.public_key)?.into())
+    }
+    pub async fn get_key(
+        self: &Arc<Self>,
+        label: &str,
+        keytype: CryptoAlgorithm,
+    ) -> Result<SecurosysKey> {
+        Ok(SecurosysKey {
+            sdk: self.clone(),
+            label: label.to_owned(),
+            keytype,
+            public_key: self.get_public_key(label).await?,
+        })
+    }
+
+    /// Signs `data`, waiting for the request to be approved if the key has a policy
+    pub async fn sign(
+        &self,
+        label: &str,
+        keytype: CryptoAlgorithm,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let request = SignRequest {
+            sign_request: SignPayload {
+                payload: BASE64.encode(data),
+                payload_type: "UNSPECIFIED".to_owned(),
+                sign_key_name: label.to_owned(),
+                signature_algorithm: signature_algorithm(keytype).to_owned(),
+            },
+        };
+        let response: SignResponse = self.call(Method::POST, "/v1/sign", Some(&request)).await?;
+        match response {
+            SignResponse {
+                signature: Some(signature),
+                ..
+            } => Ok(BASE64.decode(signature)?),
+            SignResponse {
+                sign_request_id: Some(id),
+                ..
+            } => self.wait_for_signature(&id).await,
+            _ => bail!("sign response has neither signature nor request id"),
+        }
+    }
+    pub async fn get_request(&self, id: &str) -> Result<RequestStatus> {
+        let path = format!("/v1/request/{}", path_segment(id));
+        self.call(Method::GET, &path, None::<&()>).await
+    }
+    /// Polls a sign request until it is executed, failing if it is rejected, expired or not
+    /// approved within `approval_timeout`
+    pub async fn wait_for_signature(&self, id: &str) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + self.config.approval_timeout;
+        loop {
+            let request = self.get_request(id).await?;
+            match request.status {
+                RequestState::Executed => {
+                    let result = request
+                        .result
+                        .with_context(|| format!("sign request {} has no result", id))?;
+                    return Ok(BASE64.decode(result)?);
+                }
+                RequestState::Pending => {}
+                state => bail!("sign request {} is {:?}", id, state),
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "timed out after {:?} waiting for approval of sign request {}, missing {:?}",
+                    self.config.approval_timeout,
+                    id,
+                    request.not_yet_approved_by
+                );
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Sign requests waiting for `approver`, authenticated by signing the current time
+    pub async fn pending_approvals(&self, approver: &dyn Signer) -> Result<Vec<ApprovalTask>> {
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        let request = FilteredRequests {
+            approver_public_key: BASE64.encode(approver.public_key()?.content),
+            timestamp_signature: BASE64.encode(approver.sign(timestamp.as_bytes()).await?),
+            timestamp,
+        };
+        self.call(Method::POST, "/v1/filteredRequests", Some(&request))
+            .await
+    }
+    pub async fn approve(&self, approver: &dyn Signer, task: &ApprovalTask) -> Result<()> {
+        let to_be_signed = BASE64.decode(&task.approval_to_be_signed)?;
+        let approval = Approval {
+            approval_to_be_signed: task.approval_to_be_signed.clone(),
+            approver_public_key: BASE64.encode(approver.public_key()?.content),
+            signature: BASE64.encode(approver.sign(&to_be_signed).await?),
+        };
+        let _: serde_json::Value = self
+            .call(Method::POST, "/v1/approval", Some(&approval))
+            .await?;
+        info!("approved sign request {}", task.id);
+        Ok(())
+    }
+}
+
+/// Approves every sign request pending for `approver` until the returned sender is used or
+/// dropped
+pub fn spawn_auto_approver(
+    sdk: Arc<SecurosysSdk>,
+    approver: impl Signer + 'static,
+) -> oneshot::Sender<()> {
+    let (tx, mut rx) = oneshot::channel();
+    tokio::spawn(async move {
+        loop {
+            match sdk.pending_approvals(&approver).await {
+                Ok(tasks) => {
+                    for task in tasks {
+                        if let Err(err) = sdk.approve(&approver, &task).await {
+                            warn!("approving {}: {:?}", task.id, err);
+                        }
+                    }
+                }
+                Err(err) => warn!("fetching pending approvals: {:?}", err),
+            }
+            tokio::select! {
+                _ = &mut rx => break,
+                _ = tokio::time::sleep(sdk.config.poll_interval) => {}
+            }
+        }
+    });
+    tx
+}
+
+pub(crate) fn secp256k1_verifying_key(public_key: &DerPublicKey) -> Result<VerifyingKey> {
+    let prefix = hex::decode(SECP256K1_SPKI_PREFIX)?;
+    let point = public_key
+        .content
+        .strip_prefix(prefix.as_slice())
+        .context("not a secp256k1 public key")?;
+    VerifyingKey::from_sec1_bytes(point).map_err(|e| eyre!("invalid public key: {}", e))
+}
+
+/// Verifies a DER or raw ECDSA signature, over the SHA-256 of `data` unless `prehashed`
+pub(crate) fn secp256k1_verify(
+    public_key: &DerPublicKey,
+    prehashed: bool,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool> {
+    let key = secp256k1_verifying_key(public_key)?;
+    let signature = match signature.len() {
+        64 => Signature::try_from(signature),
+        _ => Signature::from_der(signature),
+    }
+    .map_err(|e| eyre!("malformed signature: {}", e))?;
+    Ok(match prehashed {
+        true => key.verify_prehash(data, &signature).is_ok(),
+        false => key.verify(data, &signature).is_ok(),
+    })
+}
+
+/// A key held by the remote signer
+#[derive(Clone)]
+pub struct SecurosysKey {
+    sdk: Arc<SecurosysSdk>,
+    label: String,
+    keytype: CryptoAlgorithm,
+    public_key: DerPublicKey,
+}
+impl SecurosysKey {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+impl PublicKey for SecurosysKey {
+    fn public_key(&self) -> Result<DerPublicKey> {
+        Ok(self.public_key.clone())
+    }
+    fn public_exponent(&self) -> Result<PublicExpontent> {
+        let l = match self.keytype {
+            CryptoAlgorithm::Secp256k1Sha256 | CryptoAlgorithm::Secp256k1None => 65,
+            CryptoAlgorithm::Ed25519 => 32,
+        };
+        let content = &self.public_key.content;
+        ensure!(content.len() >= l, "public key too short");
+        Ok(content[content.len() - l..].to_vec().into())
+    }
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+        match self.keytype {
+            CryptoAlgorithm::Secp256k1None => {
+                secp256k1_verify(&self.public_key, true, data, signature)
+            }
+            CryptoAlgorithm::Secp256k1Sha256 => {
+                secp256k1_verify(&self.public_key, false, data, signature)
+            }
+            CryptoAlgorithm::Ed25519 => bail!("verifying Ed25519 signatures is not supported"),
+        }
+    }
+}
+#[async_trait::async_trait]
+impl Signer for SecurosysKey {
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.sdk.sign(&self.label, self.keytype, data).await
+    }
+    async fn sign_compact(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature = self.sign(data).await?;
+        match self.keytype {
+            CryptoAlgorithm::Ed25519 => Ok(signature),
+            _ => decode_ans1_signature_padded(&signature, 32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdwallet::HdWallet;
+    use crate::securosys_mock::MockSecurosys;
+    use crate::{PrivateKey, SECP256K1_PKCS8_PREFIX};
+    use k256::ecdsa::SigningKey;
+
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    /// Approvers sign with SHA-256, like `OpensslPrivateKey::new_secp256k1_sha256`
+    struct TestApprover(SigningKey);
+    impl TestApprover {
+        fn new(index: u32) -> Result<Self> {
+            let key = HdWallet::from_mnemonic(MNEMONIC, "approver")?.ethereum_account(index)?;
+            let key = SigningKey::from_bytes(&key.private_exponent()?.content)?;
+            Ok(Self(key))
+        }
+    }
+    impl PublicKey for TestApprover {
+        fn public_key(&self) -> Result<DerPublicKey> {
+            let mut der = hex::decode(SECP256K1_SPKI_PREFIX)?;
+            der.extend_from_slice(&self.public_exponent()?.content);
+            Ok(der.into())
+        }
+        fn public_exponent(&self) -> Result<PublicExpontent> {
+            use k256::elliptic_curve::sec1::ToEncodedPoint;
+            Ok(self
+                .0
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()
+                .to_vec()
+                .into())
+        }
+        fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+            secp256k1_verify(&self.public_key()?, false, data, signature)
+        }
+    }
+    #[async_trait::async_trait]
+    impl Signer for TestApprover {
+        async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+            use k256::ecdsa::signature::Signer as _;
+            let signature: Signature = self.0.sign(data);
+            Ok(signature.to_der().as_bytes().to_vec())
+        }
+    }
+
+    async fn import_test_key(
+        sdk: &Arc<SecurosysSdk>,
+        label: &str,
+        policy: Policy,
+    ) -> Result<SecurosysKey> {
+        let key = HdWallet::from_mnemonic(MNEMONIC, "")?.ethereum_account(0)?;
+        let mut private_key = hex::decode(SECP256K1_PKCS8_PREFIX)?;
+        private_key.extend_from_slice(&key.private_exponent()?.content);
+        sdk.delete_key(label).await?;
+        sdk.import_key_secp256k1(label, policy, private_key.into(), key.public_key()?)
+            .await?;
+        sdk.get_key(label, CryptoAlgorithm::Secp256k1None).await
+    }
+
+    fn test_sdk(mock: &MockSecurosys) -> Result<Arc<SecurosysSdk>> {
+        let config = SecurosysConfig {
+            poll_interval: Duration::from_millis(50),
+            approval_timeout: Duration::from_secs(5),
+            ..mock.config()
+        };
+        Ok(Arc::new(SecurosysSdk::new(config)?))
+    }
+
+    #[test]
+    fn test_policy_quorum() -> Result<()> {
+        let approvers = (0..3)
+            .map(|i| {
+                Ok((
+                    format!("approver{}", i),
+                    TestApprover::new(i)?.public_key()?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let policy = make_multi_approver_policy(approvers, 2);
+        let keys: Vec<String> = policy.approvers().map(|x| x.value.clone()).collect();
+        assert_eq!(keys.len(), 3);
+        assert!(!policy.is_approved(&HashSet::new()));
+        assert!(!policy.is_approved(&keys[..1].iter().cloned().collect()));
+        assert!(policy.is_approved(&keys[1..].iter().cloned().collect()));
+        assert!(Policy::none().is_approved(&HashSet::new()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sign_without_policy() -> Result<()> {
+        let mock = MockSecurosys::spawn().await?;
+        let sdk = test_sdk(&mock)?;
+        let key = import_test_key(&sdk, "no_policy", Policy::none()).await?;
+        let digest = [1u8; 32];
+        let signature = key.sign(&digest).await?;
+        assert!(key.verify(&digest, &signature)?);
+        assert_eq!(key.sign_compact(&digest).await?.len(), 64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_label_escaping() -> Result<()> {
+        let mock = MockSecurosys::spawn().await?;
+        let sdk = test_sdk(&mock)?;
+        let label = "ops/key 1?";
+        assert_eq!(path_segment(label), "ops%2Fkey%201%3F");
+        import_test_key(&sdk, label, Policy::none()).await?;
+        sdk.delete_key(label).await?;
+        assert!(sdk.get_public_key(label).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_single_approver() -> Result<()> {
+        let mock = MockSecurosys::spawn().await?;
+        let sdk = test_sdk(&mock)?;
+        let approver = TestApprover::new(0)?;
+        let policy = make_single_approver_policy("approver".to_owned(), approver.public_key()?);
+        let key = import_test_key(&sdk, "single", policy).await?;
+        let terminate_tx = spawn_auto_approver(sdk.clone(), approver);
+        let digest = [2u8; 32];
+        let signature = key.sign(&digest).await?;
+        assert!(key.verify(&digest, &signature)?);
+        drop(terminate_tx);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_approver_quorum() -> Result<()> {
+        let mock = MockSecurosys::spawn().await?;
+        let sdk = test_sdk(&mock)?;
+        let approvers = (0..3).map(TestApprover::new).collect::<Result<Vec<_>>>()?;
+        let policy = make_multi_approver_policy(
+            approvers
+                .iter()
+                .enumerate()
+                .map(|(i, x)| Ok((format!("approver{}", i), x.public_key()?)))
+                .collect::<Result<_>>()?,
+            2,
+        );
+        let key = import_test_key(&sdk, "multi", policy).await?;
+        let mut approvers = approvers.into_iter();
+        let _first = spawn_auto_approver(sdk.clone(), approvers.next().unwrap());
+
+        // one approval out of two is not enough
+        let sdk_short = Arc::new(SecurosysSdk::new(SecurosysConfig {
+            approval_timeout: Duration::from_millis(300),
+            ..sdk.config().clone()
+        })?);
+        let err = sdk_short
+            .sign("multi", CryptoAlgorithm::Secp256k1None, &[3u8; 32])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+
+        let _second = spawn_auto_approver(sdk.clone(), approvers.next().unwrap());
+        let digest = [4u8; 32];
+        let signature = key.sign(&digest).await?;
+        assert!(key.verify(&digest, &signature)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_approver_is_rejected() -> Result<()> {
+        let mock = MockSecurosys::spawn().await?;
+        let sdk = test_sdk(&mock)?;
+        let policy =
+            make_single_approver_policy("approver".to_owned(), TestApprover::new(0)?.public_key()?);
+        import_test_key(&sdk, "guarded", policy).await?;
+        let stranger = TestApprover::new(1)?;
+        assert!(sdk.pending_approvals(&stranger).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! In-process stand-in for the TSB endpoints used by [`crate::securosys::SecurosysSdk`], for
+//! local tests. Only secp256k1 keys are supported; keys and requests live in memory.
+use crate::securosys::{
+    secp256k1_verify, Approval, ApprovalTask, FilteredRequests, ImportKeyRequest, KeyAttributes,
+    KeyLabel, Policy, RequestState, RequestStatus, SecurosysConfig, SignRequest, SignResponse,
+};
+use crate::DerPublicKey;
+use axum::extract::{Extension, Path};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use eyre::*;
+use k256::ecdsa::signature::hazmat::PrehashSigner;
+use k256::ecdsa::signature::Signer as _;
+use k256::ecdsa::{Signature, SigningKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+struct MockKey {
+    signing_key: SigningKey,
+    public_key: String,
+    policy: Policy,
+}
+struct MockRequest {
+    label: String,
+    payload: Vec<u8>,
+    algorithm: String,
+    to_be_signed: String,
+    approved_by: HashSet<String>,
+    status: RequestState,
+    result: Option<String>,
+}
+#[derive(Default)]
+struct MockState {
+    keys: HashMap<String, MockKey>,
+    requests: HashMap<String, MockRequest>,
+}
+type SharedState = Arc<Mutex<MockState>>;
+type MockResult<T> = std::result::Result<Json<T>, (StatusCode, String)>;
+
+/// What approvers sign, so an approval cannot be replayed for another request
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApprovalToBeSigned {
+    request_id: String,
+    sign_key_name: String,
+    payload: String,
+}
+
+fn bad_request(err: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+fn not_found(what: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("{} not found", what))
+}
+
+fn sign_payload(
+    key: &SigningKey,
+    algorithm: &str,
+    payload: &[u8],
+) -> std::result::Result<String, (StatusCode, String)> {
+    let signature: Signature = match algorithm {
+        "NONE_WITH_ECDSA" => key.sign_prehash(payload).map_err(bad_request)?,
+        "SHA256_WITH_ECDSA" => key.sign(payload),
+        _ => return Err(bad_request(format!("unsupported algorithm {}", algorithm))),
+    };
+    Ok(BASE64.encode(signature.to_der().as_bytes()))
+}
+
+fn verify_approver(
+    public_key: &str,
+    data: &[u8],
+    signature: &str,
+) -> std::result::Result<(), (StatusCode, String)> {
+    let public_key = DerPublicKey::from(BASE64.decode(public_key).map_err(bad_request)?);
+    let signature = BASE64.decode(signature).map_err(bad_request)?;
+    match secp256k1_verify(&public_key, false, data, &signature) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::UNAUTHORIZED, "invalid signature".to_owned())),
+        Err(err) => Err(bad_request(err)),
+    }
+}
+
+async fn import_key(
+    Extension(state): Extension<SharedState>,
+    Json(request): Json<ImportKeyRequest>,
+) -> MockResult<serde_json::Value> {
+    if request.algorithm != "EC" || request.curve_oid != "1.3.132.0.10" {
+        return Err(bad_request("only secp256k1 keys are supported"));
+    }
+    let private_key = BASE64.decode(&request.private_key).map_err(bad_request)?;
+    if private_key.len() < 32 {
+        return Err(bad_request("private key too short"));
+    }
+    let signing_key =
+        SigningKey::from_bytes(&private_key[private_key.len() - 32..]).map_err(bad_request)?;
+    let mut state = state.lock().unwrap();
+    if state.keys.contains_key(&request.label) {
+        return Err((StatusCode::CONFLICT, format!("{} exists", request.label)));
+    }
+    state.keys.insert(
+        request.label.clone(),
+        MockKey {
+            signing_key,
+            public_key: request.public_key,
+            policy: request.policy,
+        },
+    );
+    Ok(Json(serde_json::json!({ "label": request.label })))
+}
+
+async fn delete_key(
+    Extension(state): Extension<SharedState>,
+    Path(label): Path<String>,
+) -> MockResult<serde_json::Value> {
+    match state.lock().unwrap().keys.remove(&label) {
+        Some(_) => Ok(Json(serde_json::json!({}))),
+        None => Err(not_found(&label)),
+    }
+}
+
+async fn key_attributes(
+    Extension(state): Extension<SharedState>,
+    Json(request): Json<KeyLabel>,
+) -> MockResult<KeyAttributes> {
+    let state = state.lock().unwrap();
+    let key = state
+        .keys
+        .get(&request.label)
+        .ok_or_else(|| not_found(&request.label))?;
+    Ok(Json(KeyAttributes {
+        label: request.label.clone(),
+        algorithm: "EC".to_owned(),
+        public_key: key.public_key.clone(),
+    }))
+}
+
+async fn sign(
+    Extension(state): Extension<SharedState>,
+    Json(request): Json<SignRequest>,
+) -> MockResult<SignResponse> {
+    let request = request.sign_request;
+    let payload = BASE64.decode(&request.payload).map_err(bad_request)?;
+    let mut state = state.lock().unwrap();
+    let key = state
+        .keys
+        .get(&request.sign_key_name)
+        .ok_or_else(|| not_found(&request.sign_key_name))?;
+    if key.policy.key_status.blocked {
+        return Err((StatusCode::FORBIDDEN, "key is blocked".to_owned()));
+    }
+    if key.policy.is_approved(&HashSet::new()) {
+        let signature = sign_payload(&key.signing_key, &request.signature_algorithm, &payload)?;
+        return Ok(Json(SignResponse {
+            signature: Some(signature),
+            sign_request_id: None,
+        }));
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    let to_be_signed = serde_json::to_vec(&ApprovalToBeSigned {
+        request_id: id.clone(),
+        sign_key_name: request.sign_key_name.clone(),
+        payload: request.payload.clone(),
+    })
+    .map_err(bad_request)?;
+    state.requests.insert(
+        id.clone(),
+        MockRequest {
+            label: request.sign_key_name,
+            payload,
+            algorithm: request.signature_algorithm,
+            to_be_signed: BASE64.encode(to_be_signed),
+            approved_by: HashSet::new(),
+            status: RequestState::Pending,
+            result: None,
+        },
+    );
+    Ok(Json(SignResponse {
+        signature: None,
+        sign_request_id: Some(id),
+    }))
+}
+
+async fn get_request(
+    Extension(state): Extension<SharedState>,
+    Path(id): Path<String>,
+) -> MockResult<RequestStatus> {
+    let state = state.lock().unwrap();
+    let request = state.requests.get(&id).ok_or_else(|| not_found(&id))?;
+    let not_yet_approved_by = state
+        .keys
+        .get(&request.label)
+        .map(|key| {
+            key.policy
+                .approvers()
+                .filter(|x| !request.approved_by.contains(&x.value))
+                .map(|x| x.name.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(Json(RequestStatus {
+        id,
+        status: request.status,
+        result: request.result.clone(),
+        not_yet_approved_by,
+    }))
+}
+
+async fn filtered_requests(
+    Extension(state): Extension<SharedState>,
+    Json(request): Json<FilteredRequests>,
+) -> MockResult<Vec<ApprovalTask>> {
+    verify_approver(
+        &request.approver_public_key,
+        request.timestamp.as_bytes(),
+        &request.timestamp_signature,
+    )?;
+    let state = state.lock().unwrap();
+    let tasks = state
+        .requests
+        .iter()
+        .filter(|(_, x)| {
+            x.status == RequestState::Pending
+                && !x.approved_by.contains(&request.approver_public_key)
+        })
+        .filter(|(_, x)| match state.keys.get(&x.label) {
+            Some(key) => key
+                .policy
+                .approvers()
+                .any(|a| a.value == request.approver_public_key),
+            None => false,
+        })
+        .map(|(id, x)| ApprovalTask {
+            id: id.clone(),
+            approval_to_be_signed: x.to_be_signed.clone(),
+        })
+        .collect();
+    Ok(Json(tasks))
+}
+
+async fn approve(
+    Extension(state): Extension<SharedState>,
+    Json(approval): Json<Approval>,
+) -> MockResult<serde_json::Value> {
+    let to_be_signed = BASE64
+        .decode(&approval.approval_to_be_signed)
+        .map_err(bad_request)?;
+    verify_approver(
+        &approval.approver_public_key,
+        &to_be_signed,
+        &approval.signature,
+    )?;
+    let decoded: ApprovalToBeSigned = serde_json::from_slice(&to_be_signed).map_err(bad_request)?;
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    let request = state
+        .requests
+        .get_mut(&decoded.request_id)
+        .ok_or_else(|| not_found(&decoded.request_id))?;
+    let key = state
+        .keys
+        .get(&request.label)
+        .ok_or_else(|| not_found(&request.label))?;
+    if request.to_be_signed != approval.approval_to_be_signed
+        || request.status != RequestState::Pending
+    {
+        return Err(bad_request("request is not pending"));
+    }
+    if !key
+        .policy
+        .approvers()
+        .any(|x| x.value == approval.approver_public_key)
+    {
+        return Err((StatusCode::FORBIDDEN, "not an approver".to_owned()));
+    }
+    request.approved_by.insert(approval.approver_public_key);
+    if key.policy.is_approved(&request.approved_by) {
+        request.result = Some(sign_payload(
+            &key.signing_key,
+            &request.algorithm,
+            &request.payload,
+        )?);
+        request.status = RequestState::Executed;
+    }
+    Ok(Json(serde_json::json!({})))
+}
+
+/// Serves the mock on a random local port until dropped
+pub struct MockSecurosys {
+    url: String,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+impl MockSecurosys {
+    pub async fn spawn() -> Result<Self> {
+        let state = SharedState::default();
+        let app = Router::new()
+            .route("/v1/key/import/plain", post(import_key))
+            .route("/v1/key/:label", delete(delete_key))
+            .route("/v1/key/attributes", post(key_attributes))
+            .route("/v1/sign", post(sign))
+            .route("/v1/request/:id", get(get_request))
+            .route("/v1/filteredRequests", post(filtered_requests))
+            .route("/v1/approval", post(approve))
+            .layer(Extension(state));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let (shutdown, rx) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                rx.await.ok();
+            });
+        tokio::spawn(server);
+        Ok(Self {
+            url,
+            shutdown: Some(shutdown),
+        })
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn config(&self) -> SecurosysConfig {
+        SecurosysConfig::new(&self.url)
+    }
+}
+impl Drop for MockSecurosys {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Keys held in a PKCS#11 token, e.g. a Securosys Primus HSM or SoftHSM2.
+//!
+//! To test locally against SoftHSM2:
+//! ```sh
+//! softhsm2-util --init-token --free --label test --pin 1234 --so-pin 1234
+//! export PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so PKCS11_TOKEN_LABEL=test PKCS11_PIN=1234
+//! ```
+use crate::{
+    decode_ans1_signature_padded, encode_ans1_signature, CryptoAlgorithm, DerPublicKey,
+    PublicExpontent, PublicKey, Signer, ED25519_SPKI_PREFIX, SECP256K1_SPKI_PREFIX,
+};
+use eyre::*;
+use pkcs11::types::*;
+use pkcs11::Ctx;
+use std::sync::{Arc, Mutex};
+
+// PKCS#11 v3.0 identifiers, not defined by the `pkcs11` crate
+const CKK_EC_EDWARDS: CK_KEY_TYPE = 0x40;
+const CKM_EC_EDWARDS_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x1055;
+const CKM_EDDSA: CK_MECHANISM_TYPE = 0x1057;
+
+// DER encoded curve OIDs for CKA_EC_PARAMS
+const SECP256K1_PARAMS: [u8; 7] = [0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a];
+const ED25519_PARAMS: [u8; 5] = [0x06, 0x03, 0x2b, 0x65, 0x70];
+
+#[derive(Debug, Clone)]
+pub struct Pkcs11Config {
+    /// Path to the PKCS#11 module, e.g. `/usr/lib/softhsm/libsofthsm2.so`
+    pub module: String,
+    /// Label of the token to use. The first token found is used if not set
+    pub token_label: Option<String>,
+    pub pin: String,
+}
+impl Pkcs11Config {
+    /// Reads `PKCS11_MODULE`, `PKCS11_TOKEN_LABEL` and `PKCS11_PIN`
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            module: std::env::var("PKCS11_MODULE").context("PKCS11_MODULE is not set")?,
+            token_label: std::env::var("PKCS11_TOKEN_LABEL").ok(),
+            pin: std::env::var("PKCS11_PIN").context("PKCS11_PIN is not set")?,
+        })
+    }
+}
+
+struct Session {
+    ctx: Ctx,
+    handle: CK_SESSION_HANDLE,
+}
+// SAFETY: the session is only used behind the mutex in `Pkcs11Session`
+unsafe impl Send for Session {}
+
+fn p11<T>(result: Result<T, pkcs11::errors::Error>, what: &str) -> Result<T> {
+    result.map_err(|e| eyre!("pkcs11 {} failed: {:?}", what, e))
+}
+
+/// A logged in read/write session on a token. PKCS#11 sessions are not reentrant, so every
+/// operation holds the lock for its whole duration
+pub struct Pkcs11Session {
+    inner: Mutex<Session>,
+}
+impl Pkcs11Session {
+    pub fn open(config: &Pkcs11Config) -> Result<Arc<Self>> {
+        let ctx = p11(Ctx::new_and_initialize(&config.module), "initialize")
+            .with_context(|| format!("loading {}", config.module))?;
+        let slots = p11(ctx.get_slot_list(true), "get_slot_list")?;
+        let mut found = None;
+        for slot in slots {
+            let info = p11(ctx.get_token_info(slot), "get_token_info")?;
+            let label = String::from(info.label);
+            match &config.token_label {
+                Some(expected) if expected != &label => continue,
+                _ => {
+                    found = Some(slot);
+                    break;
+                }
+            }
+        }
+        let slot = found.with_context(|| format!("token {:?} not found", config.token_label))?;
+        let handle = p11(
+            ctx.open_session(slot, CKF_SERIAL_SESSION | CKF_RW_SESSION, None, None),
+            "open_session",
+        )?;
+        p11(
+            ctx.login(handle, CKU_USER, Some(config.pin.as_str())),
+            "login",
+        )?;
+        Ok(Arc::new(Self {
+            inner: Mutex::new(Session { ctx, handle }),
+        }))
+    }
+
+    fn with_session<T>(&self, f: impl FnOnce(&Ctx, CK_SESSION_HANDLE) -> Result<T>) -> Result<T> {
+        let session = self
+            .inner
+            .lock()
+            .map_err(|_| eyre!("pkcs11 session poisoned"))?;
+        f(&session.ctx, session.handle)
+    }
+
+    fn find_object(
+        ctx: &Ctx,
+        session: CK_SESSION_HANDLE,
+        class: CK_OBJECT_CLASS,
+        label: &str,
+    ) -> Result<Option<CK_OBJECT_HANDLE>> {
+        let label = label.to_owned();
+        let template = vec![
+            CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&class),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label),
+        ];
+        p11(
+            ctx.find_objects_init(session, &template),
+            "find_objects_init",
+        )?;
+        let objects = ctx.find_objects(session, 2);
+        p11(ctx.find_objects_final(session), "find_objects_final")?;
+        let objects = p11(objects, "find_objects")?;
+        match objects.len() {
+            0 => Ok(None),
+            1 => Ok(Some(objects[0])),
+            _ => bail!("more than one object labeled {}", label),
+        }
+    }
+
+    fn read_attribute(
+        ctx: &Ctx,
+        session: CK_SESSION_HANDLE,
+        object: CK_OBJECT_HANDLE,
+        attribute: CK_ATTRIBUTE_TYPE,
+    ) -> Result<Vec<u8>> {
+        let mut template = vec![CK_ATTRIBUTE::new(attribute)];
+        p11(
+            ctx.get_attribute_value(session, object, &mut template),
+            "get_attribute_value",
+        )?;
+        let mut value = vec![0u8; template[0].ulValueLen as usize];
+        // the token writes through `pValue`, so it must come from a mutable borrow
+        let mut template = vec![CK_ATTRIBUTE {
+            attrType: attribute,
+            pValue: value.as_mut_slice().as_mut_ptr() as CK_VOID_PTR,
+            ulValueLen: value.len() as CK_ULONG,
+        }];
+        p11(
+            ctx.get_attribute_value(session, object, &mut template),
+            "get_attribute_value",
+        )?;
+        value.truncate(template[0].ulValueLen as usize);
+        Ok(value)
+    }
+
+    /// Looks up a key pair by the label of its private key
+    pub fn find_key(
+        self: &Arc<Self>,
+        label: &str,
+        keytype: CryptoAlgorithm,
+    ) -> Result<Option<Pkcs11Key>> {
+        let handles = self.with_session(|ctx, session| {
+            let private = Self::find_object(ctx, session, CKO_PRIVATE_KEY, label)?;
+            let public = Self::find_object(ctx, session, CKO_PUBLIC_KEY, label)?;
+            match (private, public) {
+                (Some(private), Some(public)) => {
+                    let point = Self::read_attribute(ctx, session, public, CKA_EC_POINT)?;
+                    Ok(Some((private, public, point)))
+                }
+                (None, None) => Ok(None),
+                _ => bail!("key pair {} is incomplete", label),
+            }
+        })?;
+        handles
+            .map(|(private, public, point)| {
+                Pkcs11Key::new(self.clone(), label, keytype, private, public, &point)
+            })
+            .transpose()
+    }
+
+    /// Generates a non extractable key pair inside the token
+    pub fn generate_key(
+        self: &Arc<Self>,
+        label: &str,
+        keytype: CryptoAlgorithm,
+    ) -> Result<Pkcs11Key> {
+        let (mechanism, params) = match keytype {
+            CryptoAlgorithm::Secp256k1Sha256 | CryptoAlgorithm::Secp256k1None => {
+                (CKM_EC_KEY_PAIR_GEN, &SECP256K1_PARAMS[..])
+            }
+            CryptoAlgorithm::Ed25519 => (CKM_EC_EDWARDS_KEY_PAIR_GEN, &ED25519_PARAMS[..]),
+        };
+        let label_str = label.to_owned();
+        let (private, public, point) = self.with_session(|ctx, session| {
+            let public_template = vec![
+                CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_VERIFY).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label_str),
+                CK_ATTRIBUTE::new(CKA_EC_PARAMS).with_bytes(params),
+            ];
+            let private_template = vec![
+                CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_PRIVATE).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_SENSITIVE).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_EXTRACTABLE).with_bool(&CK_FALSE),
+                CK_ATTRIBUTE::new(CKA_SIGN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label_str),
+            ];
+            let mechanism = CK_MECHANISM {
+                mechanism,
+                pParameter: std::ptr::null_mut(),
+                ulParameterLen: 0,
+            };
+            let (public, private) = p11(
+                ctx.generate_key_pair(session, &mechanism, &public_template, &private_template),
+                "generate_key_pair",
+            )?;
+            let point = Self::read_attribute(ctx, session, public, CKA_EC_POINT)?;
+            Ok((private, public, point))
+        })?;
+        Pkcs11Key::new(self.clone(), label, keytype, private, public, &point)
+    }
+
+    /// Imports an existing key pair. `private` is the raw 32 bytes scalar (secp256k1) or seed
+    /// (Ed25519), `public` the matching public exponent
+    pub fn import_key(
+        self: &Arc<Self>,
+        label: &str,
+        keytype: CryptoAlgorithm,
+        private: &crate::PrivateExpontent,
+        public: &PublicExpontent,
+    ) -> Result<Pkcs11Key> {
+        let (key_type, params) = match keytype {
+            CryptoAlgorithm::Secp256k1Sha256 | CryptoAlgorithm::Secp256k1None => {
+                (CKK_EC, &SECP256K1_PARAMS[..])
+            }
+            CryptoAlgorithm::Ed25519 => (CKK_EC_EDWARDS, &ED25519_PARAMS[..]),
+        };
+        let point = wrap_octet_string(&public.content)?;
+        let label_str = label.to_owned();
+        let (private, public) = self.with_session(|ctx, session| {
+            let public_template = vec![
+                CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&CKO_PUBLIC_KEY),
+                CK_ATTRIBUTE::new(CKA_KEY_TYPE).with_ck_ulong(&key_type),
+                CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_VERIFY).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label_str),
+                CK_ATTRIBUTE::new(CKA_EC_PARAMS).with_bytes(params),
+                CK_ATTRIBUTE::new(CKA_EC_POINT).with_bytes(&point),
+            ];
+            let private_template = vec![
+                CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&CKO_PRIVATE_KEY),
+                CK_ATTRIBUTE::new(CKA_KEY_TYPE).with_ck_ulong(&key_type),
+                CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_PRIVATE).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_SENSITIVE).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_SIGN).with_bool(&CK_TRUE),
+                CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label_str),
+                CK_ATTRIBUTE::new(CKA_EC_PARAMS).with_bytes(params),
+                CK_ATTRIBUTE::new(CKA_VALUE).with_bytes(&private.content),
+            ];
+            let public = p11(
+                ctx.create_object(session, &public_template),
+                "create_object",
+            )?;
+            let private = p11(
+                ctx.create_object(session, &private_template),
+                "create_object",
+            )?;
+            Ok((private, public))
+        })?;
+        Pkcs11Key::new(self.clone(), label, keytype, private, public, &point)
+    }
+
+    /// Deletes both halves of a key pair, if present
+    pub fn delete_key(&self, label: &str) -> Result<()> {
+        self.with_session(|ctx, session| {
+            for class in [CKO_PRIVATE_KEY, CKO_PUBLIC_KEY] {
+                if let Some(object) = Self::find_object(ctx, session, class, label)? {
+                    p11(ctx.destroy_object(session, object), "destroy_object")?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A key pair inside a token, usable wherever a `crypto::Signer` is expected
+pub struct Pkcs11Key {
+    session: Arc<Pkcs11Session>,
+    label: String,
+    keytype: CryptoAlgorithm,
+    private: CK_OBJECT_HANDLE,
+    public: CK_OBJECT_HANDLE,
+    public_exponent: PublicExpontent,
+}
+impl Pkcs11Key {
+    fn new(
+        session: Arc<Pkcs11Session>,
+        label: &str,
+        keytype: CryptoAlgorithm,
+        private: CK_OBJECT_HANDLE,
+        public: CK_OBJECT_HANDLE,
+        ec_point: &[u8],
+    ) -> Result<Self> {
+        let len = match keytype {
+            CryptoAlgorithm::Secp256k1Sha256 | CryptoAlgorithm::Secp256k1None => 65,
+            CryptoAlgorithm::Ed25519 => 32,
+        };
+        Ok(Self {
+            session,
+            label: label.to_owned(),
+            keytype,
+            private,
+            public,
+            public_exponent: unwrap_octet_string(ec_point, len)?.into(),
+        })
+    }
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+    fn mechanism(&self) -> CK_MECHANISM {
+        let mechanism = match self.keytype {
+            CryptoAlgorithm::Secp256k1Sha256 => CKM_ECDSA_SHA256,
+            CryptoAlgorithm::Secp256k1None => CKM_ECDSA,
+            CryptoAlgorithm::Ed25519 => CKM_EDDSA,
+        };
+        CK_MECHANISM {
+            mechanism,
+            pParameter: std::ptr::null_mut(),
+            ulParameterLen: 0,
+        }
+    }
+}
+impl PublicKey for Pkcs11Key {
+    fn public_key(&self) -> Result<DerPublicKey> {
+        let prefix = match self.keytype {
+            CryptoAlgorithm::Secp256k1Sha256 | CryptoAlgorithm::Secp256k1None => {
+                SECP256K1_SPKI_PREFIX
+            }
+            CryptoAlgorithm::Ed25519 => ED25519_SPKI_PREFIX,
+        };
+        let mut der = hex::decode(prefix)?;
+        der.extend_from_slice(&self.public_exponent.content);
+        Ok(der.into())
+    }
+    fn public_exponent(&self) -> Result<PublicExpontent> {
+        Ok(self.public_exponent.clone())
+    }
+    fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+        let signature = match self.keytype {
+            CryptoAlgorithm::Ed25519 => signature.to_vec(),
+            _ if signature.len() == 64 => signature.to_vec(),
+            _ => decode_ans1_signature_padded(signature, 32)?,
+        };
+        let mechanism = self.mechanism();
+        self.session.with_session(|ctx, session| {
+            p11(
+                ctx.verify_init(session, &mechanism, self.public),
+                "verify_init",
+            )?;
+            Ok(ctx.verify(session, data, &signature).is_ok())
+        })
+    }
+}
+#[async_trait::async_trait]
+impl Signer for Pkcs11Key {
+    /// ECDSA signatures are returned in ANS1 DER format like `OpensslPrivateKey`,
+    /// Ed25519 signatures raw
+    async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mechanism = self.mechanism();
+        let signature = self.session.with_session(|ctx, session| {
+            p11(
+                ctx.sign_init(session, &mechanism, self.private),
+                "sign_init",
+            )?;
+            p11(ctx.sign(session, data), "sign")
+        })?;
+        match self.keytype {
+            CryptoAlgorithm::Ed25519 => Ok(signature),
+            _ => encode_ans1_signature(&signature),
+        }
+    }
+    async fn sign_compact(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature = self.sign(data).await?;
+        match self.keytype {
+            CryptoAlgorithm::Ed25519 => Ok(signature),
+            _ => decode_ans1_signature_padded(&signature, 32),
+        }
+    }
+}
+
+/// CKA_EC_POINT holds the point wrapped in a DER OCTET STRING
+fn wrap_octet_string(point: &[u8]) -> Result<Vec<u8>> {
+    ensure!(point.len() < 128, "point too long: {}", point.len());
+    let mut der = vec![0x04, point.len() as u8];
+    der.extend_from_slice(point);
+    Ok(der)
+}
+
+/// Some tokens return the raw point instead of the DER OCTET STRING the spec requires
+fn unwrap_octet_string(der: &[u8], len: usize) -> Result<Vec<u8>> {
+    if der.len() == len + 2 && der[0] == 0x04 && der[1] as usize == len {
+        Ok(der[2..].to_vec())
+    } else if der.len() == len {
+        Ok(der.to_vec())
+    } else {
+        bail!("unexpected CKA_EC_POINT: {}", hex::encode(der))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_encoding() -> Result<()> {
+        let mut raw = [0x11u8; 64];
+        raw[0] = 0;
+        raw[32] = 0x80;
+        let der = encode_ans1_signature(&raw)?;
+        assert_eq!(decode_ans1_signature_padded(&der, 32)?, raw.to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ec_point_encoding() -> Result<()> {
+        let point = [4u8; 65];
+        let der = wrap_octet_string(&point)?;
+        assert_eq!(&der[..2], &[0x04, 0x41]);
+        assert_eq!(unwrap_octet_string(&der, 65)?, point.to_vec());
+        assert_eq!(unwrap_octet_string(&point, 65)?, point.to_vec());
+        assert!(unwrap_octet_string(&der, 32).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a SoftHSM token configured through PKCS11_*"]
+    async fn test_softhsm_secp256k1() -> Result<()> {
+        let session = Pkcs11Session::open(&Pkcs11Config::from_env()?)?;
+        let label = "test_pkcs11_secp256k1";
+        session.delete_key(label)?;
+        let key = session.generate_key(label, CryptoAlgorithm::Secp256k1None)?;
+        assert_eq!(key.public_exponent()?.content.len(), 65);
+        let digest = [7u8; 32];
+        let signature = key.sign(&digest).await?;
+        assert!(key.verify(&digest, &signature)?);
+        assert!(!key.verify(&[8u8; 32], &signature)?);
+        let found = session
+            .find_key(label, CryptoAlgorithm::Secp256k1None)?
+            .context("key not found")?;
+        assert!(found.public_exponent()? == key.public_exponent()?);
+        session.delete_key(label)?;
+        assert!(session
+            .find_key(label, CryptoAlgorithm::Secp256k1None)?
+            .is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a SoftHSM token configured through PKCS11_*"]
+    async fn test_softhsm_ed25519() -> Result<()> {
+        let session = Pkcs11Session::open(&Pkcs11Config::from_env()?)?;
+        let label = "test_pkcs11_ed25519";
+        session.delete_key(label)?;
+        let key = session.generate_key(label, CryptoAlgorithm::Ed25519)?;
+        assert_eq!(key.public_exponent()?.content.len(), 32);
+        let signature = key.sign(b"hello world").await?;
+        assert_eq!(signature.len(), 64);
+        assert!(key.verify(b"hello world", &signature)?);
+        session.delete_key(label)?;
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use std::process::Output;
 pub fn append_to_file(path: &str, data: impl AsRef<[u8]>) -> Result<()> {
     let mut file = OpenOptions::new().create(true).append(true).open(path)?;
     file.write_all(data.as_ref())?;
-    file.write(b"\n")?;
+    file.write_all(b"\n")?;
     Ok(())
 }
 
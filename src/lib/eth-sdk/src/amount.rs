@@ -0,0 +1,196 @@
+use eyre::*;
+use std::fmt::{Display, Formatter};
+use web3::types::U256;
+
+pub const ETH_DECIMALS: u8 = 18;
+/// 10^77 is the largest power of ten that fits in a `U256`
+pub const MAX_DECIMALS: u8 = 77;
+
+/// An exact fixed-point amount: `value` base units with `decimals` fractional digits,
+/// e.g. wei with 18 decimals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Amount {
+    value: U256,
+    decimals: u8,
+}
+impl Amount {
+    pub fn new(value: U256, decimals: u8) -> Result<Self> {
+        ensure!(
+            decimals <= MAX_DECIMALS,
+            "{} decimals is more than {}",
+            decimals,
+            MAX_DECIMALS
+        );
+        Ok(Self { value, decimals })
+    }
+    pub fn from_wei(wei: U256) -> Self {
+        Self {
+            value: wei,
+            decimals: ETH_DECIMALS,
+        }
+    }
+    /// Parses a display amount such as `1`, `0.5` or `1234.000001`. Fails rather than rounding
+    /// if it has more fractional digits than `decimals` or does not fit in a `U256`
+    pub fn parse(amount: &str, decimals: u8) -> Result<Self> {
+        Self::new(U256::zero(), decimals)?;
+        let amount = amount.trim();
+        let (integer, fraction) = match amount.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (amount, ""),
+        };
+        ensure!(
+            !(integer.is_empty() && fraction.is_empty())
+                && integer
+                    .chars()
+                    .chain(fraction.chars())
+                    .all(|c| c.is_ascii_digit()),
+            "invalid amount: {:?}",
+            amount
+        );
+        let fraction = fraction.trim_end_matches('0');
+        ensure!(
+            fraction.len() <= decimals as usize,
+            "{} has more than {} decimals",
+            amount,
+            decimals
+        );
+        let parse = |digits: &str| match digits {
+            "" => Ok(U256::zero()),
+            _ => U256::from_dec_str(digits).map_err(|e| eyre!("{}: {:?}", amount, e)),
+        };
+        let scale = U256::exp10(decimals as usize);
+        let fraction_scale = U256::exp10(decimals as usize - fraction.len());
+        let fraction = parse(fraction)? * fraction_scale;
+        let value = parse(integer)?
+            .checked_mul(scale)
+            .and_then(|x| x.checked_add(fraction))
+            .with_context(|| format!("{} is too large", amount))?;
+        Ok(Self { value, decimals })
+    }
+    pub fn parse_eth(amount: &str) -> Result<Self> {
+        Self::parse(amount, ETH_DECIMALS)
+    }
+    /// Amount in base units
+    pub fn value(&self) -> U256 {
+        self.value
+    }
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+}
+/// Formats without trailing zeros, e.g. `1.5` or `10`
+impl Display for Amount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let scale = U256::exp10(self.decimals as usize);
+        let (integer, fraction) = self.value.div_mod(scale);
+        if fraction.is_zero() {
+            return write!(f, "{}", integer);
+        }
+        let fraction = format!(
+            "{:0>width$}",
+            fraction.to_string(),
+            width = self.decimals as usize
+        );
+        write!(f, "{}.{}", integer, fraction.trim_end_matches('0'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+    #[derive(Debug, Clone)]
+    struct AnyAmount(Amount);
+    impl Arbitrary for AnyAmount {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let words = [
+                u64::arbitrary(g),
+                u64::arbitrary(g),
+                u64::arbitrary(g),
+                u64::arbitrary(g),
+            ];
+            // small values too, so that short fractions are covered
+            let value = match u8::arbitrary(g) % 3 {
+                0 => U256::from(words[0] % 1_000_000),
+                1 => U256::from(words[0]) * U256::from(words[1]),
+                _ => U256(words),
+            };
+            let decimals = u8::arbitrary(g) % (MAX_DECIMALS + 1);
+            AnyAmount(Amount::new(value, decimals).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_parse_and_format() -> Result<()> {
+        assert_eq!(Amount::parse_eth("1")?.value(), U256::exp10(18));
+        assert_eq!(Amount::parse_eth("0.5")?.value(), U256::exp10(17) * 5);
+        assert_eq!(Amount::parse_eth(".5")?.value(), U256::exp10(17) * 5);
+        assert_eq!(Amount::parse_eth("10.0")?.to_string(), "10");
+        // f64 loses the last digits of this one
+        let amount = Amount::parse_eth("123456789.123456789123456789")?;
+        assert_eq!(amount.value().to_string(), "123456789123456789123456789");
+        assert_eq!(amount.to_string(), "123456789.123456789123456789");
+        assert_eq!(Amount::parse("1.230", 2)?.to_string(), "1.23");
+        assert_eq!(
+            Amount::from_wei(1.into()).to_string(),
+            "0.000000000000000001"
+        );
+        assert_eq!(
+            Amount::new(U256::MAX, 0)?.to_string(),
+            U256::MAX.to_string()
+        );
+
+        assert!(Amount::parse("1.234", 2).is_err());
+        assert!(Amount::parse_eth("").is_err());
+        assert!(Amount::parse_eth(".").is_err());
+        assert!(Amount::parse_eth("-1").is_err());
+        assert!(Amount::parse_eth("1e18").is_err());
+        assert!(Amount::parse_eth("1.2.3").is_err());
+        assert!(Amount::parse(&format!("{}0", U256::MAX), 0).is_err());
+        assert!(Amount::parse("1", 78).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_then_parse_is_lossless() {
+        fn prop(amount: AnyAmount) -> bool {
+            let amount = amount.0;
+            Amount::parse(&amount.to_string(), amount.decimals()).ok() == Some(amount)
+        }
+        QuickCheck::new()
+            .tests(2000)
+            .quickcheck(prop as fn(AnyAmount) -> bool);
+    }
+
+    #[test]
+    fn test_parse_then_format_is_lossless() {
+        fn prop(integer: u64, fraction: u64, decimals: u8) -> TestResult {
+            let decimals = decimals % (MAX_DECIMALS + 1);
+            let fraction = fraction.to_string();
+            if fraction.len() > decimals as usize {
+                return TestResult::discard();
+            }
+            let text = format!("{}.{}", integer, fraction);
+            let expected = match fraction.trim_end_matches('0') {
+                "" => integer.to_string(),
+                fraction => format!("{}.{}", integer, fraction),
+            };
+            match Amount::parse(&text, decimals) {
+                Ok(amount) => TestResult::from_bool(amount.to_string() == expected),
+                // too large for a U256 with that many decimals
+                Err(_)
+                    if (U256::from(integer) + 1)
+                        .checked_mul(U256::exp10(decimals as usize))
+                        .is_none() =>
+                {
+                    TestResult::discard()
+                }
+                Err(_) => TestResult::failed(),
+            }
+        }
+        QuickCheck::new()
+            .tests(2000)
+            .quickcheck(prop as fn(u64, u64, u8) -> TestResult);
+    }
+}
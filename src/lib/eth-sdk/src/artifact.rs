@@ -0,0 +1,301 @@
+//! Compiled contracts from Foundry, Hardhat or Truffle, and library linking
+use crate::contract::{create2_address, encode_constructor, ContractDeployer, CREATE2_DEPLOYER};
+use crate::storage_layout::StorageLayout;
+use eyre::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use web3::api::Eth;
+use web3::contract::tokens::Tokenize;
+use web3::ethabi;
+use web3::signing::keccak256;
+use web3::types::{Address, H256};
+use web3::Transport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkOffset {
+    pub start: usize,
+    pub length: usize,
+}
+/// Where library addresses go in the bytecode, by source file then library name. The same
+/// layout in all of solc, Foundry and Hardhat output
+pub type LinkReferences = BTreeMap<String, BTreeMap<String, Vec<LinkOffset>>>;
+/// Where immutables are written into the deployed code, by AST id of the variable
+pub type ImmutableReferences = BTreeMap<String, Vec<LinkOffset>>;
+/// Library addresses by name, either `Math` or fully qualified `src/Math.sol:Math`
+pub type Libraries = HashMap<String, Address>;
+
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub contract_name: Option<String>,
+    pub abi: Vec<Value>,
+    /// Creation code in hex, with placeholders for unlinked libraries
+    pub bytecode: String,
+    pub link_references: LinkReferences,
+    pub deployed_bytecode: String,
+    pub deployed_link_references: LinkReferences,
+    /// Only in Forge artifacts, Hardhat keeps them in its build info
+    pub deployed_immutable_references: ImmutableReferences,
+    /// Only in Forge artifacts built with `extra_output = ["storageLayout"]`
+    pub storage_layout: Option<StorageLayout>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundryCode {
+    object: String,
+    #[serde(default)]
+    link_references: LinkReferences,
+    #[serde(default)]
+    immutable_references: ImmutableReferences,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FoundryArtifact {
+    abi: Vec<Value>,
+    bytecode: FoundryCode,
+    deployed_bytecode: Option<FoundryCode>,
+    #[serde(default)]
+    storage_layout: Option<StorageLayout>,
+}
+/// Also covers Truffle, which has no link references and is linked by placeholder instead
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HardhatArtifact {
+    contract_name: Option<String>,
+    abi: Vec<Value>,
+    bytecode: String,
+    #[serde(default)]
+    deployed_bytecode: String,
+    #[serde(default)]
+    link_references: LinkReferences,
+    #[serde(default)]
+    deployed_link_references: LinkReferences,
+}
+
+impl Artifact {
+    /// Tells the format apart by shape: Foundry nests `bytecode.object`, Hardhat and Truffle
+    /// have `bytecode` as a string
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)?;
+        if value["bytecode"].is_object() {
+            let artifact: FoundryArtifact = serde_json::from_value(value)?;
+            let deployed = artifact.deployed_bytecode.unwrap_or(FoundryCode {
+                object: String::new(),
+                link_references: LinkReferences::new(),
+                immutable_references: ImmutableReferences::new(),
+            });
+            return Ok(Self {
+                contract_name: None,
+                abi: artifact.abi,
+                bytecode: artifact.bytecode.object,
+                link_references: artifact.bytecode.link_references,
+                deployed_bytecode: deployed.object,
+                deployed_link_references: deployed.link_references,
+                deployed_immutable_references: deployed.immutable_references,
+                storage_layout: artifact.storage_layout,
+            });
+        }
+        let artifact: HardhatArtifact = serde_json::from_value(value)?;
+        Ok(Self {
+            contract_name: artifact.contract_name,
+            abi: artifact.abi,
+            bytecode: artifact.bytecode,
+            link_references: artifact.link_references,
+            deployed_bytecode: artifact.deployed_bytecode,
+            deployed_link_references: artifact.deployed_link_references,
+            deployed_immutable_references: ImmutableReferences::new(),
+            storage_layout: None,
+        })
+    }
+    /// Reads e.g. `out/Escrow.sol/Escrow.json`, `artifacts/contracts/Escrow.sol/Escrow.json`
+    /// or `build/contracts/Escrow.json`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("parsing {}", path.display()))
+    }
+    pub fn abi(&self) -> Result<ethabi::Contract> {
+        Ok(serde_json::from_value(Value::Array(self.abi.clone()))?)
+    }
+    /// Creation code with `libraries` linked in
+    pub fn link(&self, libraries: &Libraries) -> Result<Vec<u8>> {
+        link(&self.bytecode, &self.link_references, libraries)
+    }
+    /// Linked creation code with the constructor arguments appended
+    pub fn init_code<P: Tokenize>(&self, libraries: &Libraries, params: P) -> Result<Vec<u8>> {
+        encode_constructor(&self.abi()?, self.link(libraries)?, params)
+    }
+    /// Address a CREATE2 deployment through `CREATE2_DEPLOYER` lands at, computed offline
+    pub fn predict_create2_address<P: Tokenize>(
+        &self,
+        libraries: &Libraries,
+        params: P,
+        salt: H256,
+    ) -> Result<Address> {
+        Ok(create2_address(
+            CREATE2_DEPLOYER,
+            salt,
+            &self.init_code(libraries, params)?,
+        ))
+    }
+    /// A deployer of the creation code, libraries are added with `ContractDeployer::library`
+    pub fn deployer<T: Transport>(&self, eth: Eth<T>) -> Result<ContractDeployer<T>> {
+        Ok(ContractDeployer::new(eth, Value::Array(self.abi.clone()))?
+            .code(self.bytecode.clone())
+            .link_references(self.link_references.clone()))
+    }
+}
+
+/// The `__$<hash>$__` placeholder solc >= 0.5 emits for a fully qualified library name
+pub fn placeholder(name: &str) -> String {
+    format!("__${}$__", hex::encode(&keccak256(name.as_bytes())[..17]))
+}
+/// The `__Name_____` placeholder of solc < 0.5: the name cut to 36 characters, padded with `_`
+pub fn legacy_placeholder(name: &str) -> String {
+    let name: String = name.chars().take(36).collect();
+    format!("__{:_<38}", name)
+}
+
+fn library_address(libraries: &Libraries, file: &str, name: &str) -> Option<Address> {
+    libraries
+        .get(&format!("{}:{}", file, name))
+        .or_else(|| libraries.get(name))
+        .copied()
+}
+
+/// Links `code` at the offsets of `references`, then resolves any placeholder left by name,
+/// which is all Truffle artifacts have. Fails if a library is missing
+pub fn link(code: &str, references: &LinkReferences, libraries: &Libraries) -> Result<Vec<u8>> {
+    let code = code.trim().trim_matches('"');
+    let mut code = code.strip_prefix("0x").unwrap_or(code).to_owned();
+    let mut missing = vec![];
+    for (file, names) in references {
+        for (name, offsets) in names {
+            let address = match library_address(libraries, file, name) {
+                Some(address) => hex::encode(address),
+                None => {
+                    missing.push(format!("{}:{}", file, name));
+                    continue;
+                }
+            };
+            for offset in offsets {
+                let range = offset.start * 2..(offset.start + offset.length) * 2;
+                ensure!(
+                    offset.length == 20 && range.end <= code.len(),
+                    "bad link reference for {} at {:?}",
+                    name,
+                    offset
+                );
+                code.replace_range(range, &address);
+            }
+        }
+    }
+    ensure!(
+        missing.is_empty(),
+        "unlinked libraries: {}",
+        missing.join(", ")
+    );
+    while let Some(start) = code.find("__") {
+        let placeholder = code
+            .get(start..start + 40)
+            .with_context(|| format!("truncated placeholder at byte {}", start / 2))?
+            .to_owned();
+        let address = libraries
+            .iter()
+            .find(|(name, _)| {
+                placeholder == self::placeholder(name) || placeholder == legacy_placeholder(name)
+            })
+            .map(|(_, address)| hex::encode(address))
+            .with_context(|| {
+                format!(
+                    "no library for placeholder {}, hashed placeholders need the fully \
+                     qualified name",
+                    placeholder
+                )
+            })?;
+        code.replace_range(start..start + 40, &address);
+    }
+    hex::decode(&code).context("bytecode is not hex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn libraries() -> Libraries {
+        [("src/Math.sol:Math".to_owned(), Address::repeat_byte(0xaa))]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_link_by_offset() -> Result<()> {
+        // 2 bytes of code, a 20 bytes placeholder, 1 byte of code
+        let code = format!("0x6080{}00", placeholder("src/Math.sol:Math"));
+        let json = serde_json::json!({
+            "abi": [],
+            "bytecode": {
+                "object": code,
+                "sourceMap": "",
+                "linkReferences": {"src/Math.sol": {"Math": [{"start": 2, "length": 20}]}}
+            },
+            "deployedBytecode": {"object": "0x", "sourceMap": "", "linkReferences": {}}
+        });
+        let artifact = Artifact::from_json(&json.to_string())?;
+        assert_eq!(artifact.link_references["src/Math.sol"]["Math"].len(), 1);
+        let linked = artifact.link(&libraries())?;
+        assert_eq!(hex::encode(linked), format!("6080{}00", "aa".repeat(20)));
+        // short names work too
+        let short = [("Math".to_owned(), Address::repeat_byte(0xaa))]
+            .into_iter()
+            .collect();
+        assert!(artifact.link(&short).is_ok());
+        let error = artifact.link(&Libraries::new()).unwrap_err();
+        assert!(error.to_string().contains("src/Math.sol:Math"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_by_placeholder() -> Result<()> {
+        let hardhat = serde_json::json!({
+            "_format": "hh-sol-artifact-1",
+            "contractName": "Pool",
+            "abi": [],
+            "bytecode": format!("0x60{}", placeholder("src/Math.sol:Math")),
+            "deployedBytecode": "0x",
+            "linkReferences": {},
+            "deployedLinkReferences": {}
+        });
+        let artifact = Artifact::from_json(&hardhat.to_string())?;
+        assert_eq!(artifact.contract_name.as_deref(), Some("Pool"));
+        assert_eq!(artifact.link(&libraries())?[1..], [0xaa; 20]);
+
+        // old Truffle artifacts
+        let truffle = serde_json::json!({
+            "contractName": "Pool",
+            "abi": [],
+            "bytecode": format!("0x60{}", legacy_placeholder("Math")),
+            "networks": {}
+        });
+        let artifact = Artifact::from_json(&truffle.to_string())?;
+        let short = [("Math".to_owned(), Address::repeat_byte(0xbb))]
+            .into_iter()
+            .collect();
+        assert_eq!(artifact.link(&short)?[1..], [0xbb; 20]);
+        assert!(artifact.link(&Libraries::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_placeholder() {
+        assert_eq!(placeholder("a").len(), 40);
+        assert_eq!(
+            legacy_placeholder("Math"),
+            "__Math__________________________________"
+        );
+        assert_eq!(legacy_placeholder(&"x".repeat(50)).len(), 40);
+    }
+}
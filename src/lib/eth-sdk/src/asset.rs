@@ -0,0 +1,201 @@
+//! One entry point from asset identifiers to `CryptoToken`s:
+//!
+//! - `ETH@<net>`, e.g. `ETH@sepolia`
+//! - `ERC20@<net>:<address>`
+//! - `POOL@<net>:<address>` for StrategyPool share tokens
+//! - `<SYMBOL>@<net>`, e.g. `USDC@mainnet`, looked up in a [`TokenRegistry`]
+//!
+//! Tokens created by the same [`AssetFactory`] on the same network share one client.
+use crate::erc20::Erc20Token;
+use crate::registry::TokenRegistry;
+use crate::{EthereumNet, EthereumToken};
+use eyre::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use token::CryptoToken;
+use web3::transports::Http;
+use web3::types::Address;
+use web3::Web3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetId {
+    Eth(EthereumNet),
+    Erc20(EthereumNet, Address),
+    Pool(EthereumNet, Address),
+    Symbol(EthereumNet, String),
+}
+impl AssetId {
+    pub fn net(&self) -> EthereumNet {
+        match self {
+            AssetId::Eth(net)
+            | AssetId::Erc20(net, _)
+            | AssetId::Pool(net, _)
+            | AssetId::Symbol(net, _) => *net,
+        }
+    }
+}
+impl FromStr for AssetId {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, location) = s
+            .split_once('@')
+            .with_context(|| format!("asset {} is not of the form <kind>@<net>", s))?;
+        let (net, address) = match location.split_once(':') {
+            Some((net, address)) => {
+                let address = Address::from_str(address)
+                    .with_context(|| format!("address {} of asset {}", address, s))?;
+                (net, Some(address))
+            }
+            None => (location, None),
+        };
+        let net = EthereumNet::from_str(net)?;
+        match (kind, address) {
+            ("ETH", None) => Ok(AssetId::Eth(net)),
+            ("ERC20", Some(address)) => Ok(AssetId::Erc20(net, address)),
+            ("POOL", Some(address)) => Ok(AssetId::Pool(net, address)),
+            ("ETH", Some(_)) => bail!("ETH takes no contract address: {}", s),
+            ("ERC20" | "POOL", None) => bail!("{} needs a contract address: {}", kind, s),
+            (symbol, None) if !symbol.is_empty() => Ok(AssetId::Symbol(net, symbol.to_owned())),
+            _ => bail!("invalid asset {}", s),
+        }
+    }
+}
+impl Display for AssetId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetId::Eth(net) => write!(f, "ETH@{}", net.name()),
+            AssetId::Erc20(net, address) => write!(f, "ERC20@{}:{:?}", net.name(), address),
+            AssetId::Pool(net, address) => write!(f, "POOL@{}:{:?}", net.name(), address),
+            AssetId::Symbol(net, symbol) => write!(f, "{}@{}", symbol, net.name()),
+        }
+    }
+}
+
+/// Creates tokens from asset identifiers. Each factory keeps one client per network, so should
+/// live on the tokio runtime its tokens are used on
+#[derive(Debug, Clone, Default)]
+pub struct AssetFactory {
+    registry: TokenRegistry,
+    clients: Arc<Mutex<HashMap<EthereumNet, Web3<Http>>>>,
+}
+impl AssetFactory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Resolves `<SYMBOL>@<net>` assets through `registry`
+    pub fn with_registry(self, registry: TokenRegistry) -> Self {
+        Self { registry, ..self }
+    }
+    pub fn registry(&self) -> &TokenRegistry {
+        &self.registry
+    }
+    /// The client of this factory for `net`, created on first use
+    pub fn client(&self, net: EthereumNet) -> Result<Web3<Http>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&net) {
+            return Ok(client.clone());
+        }
+        let client = net.client()?;
+        clients.insert(net, client.clone());
+        Ok(client)
+    }
+    pub fn create(&self, asset: &AssetId) -> Result<Box<dyn CryptoToken>> {
+        let client = self.client(asset.net())?;
+        let token: Box<dyn CryptoToken> = match asset {
+            AssetId::Eth(net) => Box::new(EthereumToken::new_with_client(*net, client)),
+            AssetId::Erc20(net, address) => {
+                Box::new(Erc20Token::new_with_client(*net, client, *address)?)
+            }
+            AssetId::Pool(net, address) => {
+                Box::new(Erc20Token::new_with_client(*net, client, *address)?.as_strategy_pool())
+            }
+            AssetId::Symbol(..) => Box::new(
+                self.registry
+                    .lookup(&asset.to_string())?
+                    .with_context(|| format!("{} is not in the token registry", asset))?
+                    .token(client)?,
+            ),
+        };
+        Ok(token)
+    }
+    pub fn parse(&self, asset: &str) -> Result<Box<dyn CryptoToken>> {
+        self.create(&AssetId::from_str(asset)?)
+    }
+}
+
+/// Parses an asset without a token registry
+pub fn parse_asset(asset: &str) -> Result<Box<dyn CryptoToken>> {
+    AssetFactory::new().parse(asset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{TokenInfo, TokenKind};
+
+    #[test]
+    fn test_asset_id() -> Result<()> {
+        let address = Address::from_low_u64_be(1);
+        for (s, expected) in [
+            ("ETH@sepolia", AssetId::Eth(EthereumNet::Sepolia)),
+            (
+                "ERC20@base:0x0000000000000000000000000000000000000001",
+                AssetId::Erc20(EthereumNet::Base, address),
+            ),
+            (
+                "POOL@local:0x0000000000000000000000000000000000000001",
+                AssetId::Pool(EthereumNet::Local, address),
+            ),
+            (
+                "USDC@mainnet",
+                AssetId::Symbol(EthereumNet::Mainnet, "USDC".to_owned()),
+            ),
+        ] {
+            let asset = AssetId::from_str(s)?;
+            assert_eq!(asset, expected);
+            assert_eq!(asset.to_string(), s);
+        }
+        for s in [
+            "ETH",
+            "ETH@nowhere",
+            "ETH@local:0x0000000000000000000000000000000000000001",
+            "ERC20@local",
+            "ERC20@local:0x01",
+            "@local",
+        ] {
+            assert!(AssetId::from_str(s).is_err(), "{}", s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_factory() -> Result<()> {
+        let mut registry = TokenRegistry::new();
+        registry.insert(TokenInfo {
+            net: EthereumNet::Mainnet,
+            address: Address::from_low_u64_be(2),
+            name: "USD Coin".to_owned(),
+            symbol: "USDC".to_owned(),
+            decimals: 6,
+            kind: TokenKind::Erc20,
+        })?;
+        let factory = AssetFactory::new().with_registry(registry);
+
+        assert_eq!(
+            factory.parse("ETH@sepolia")?.get_network_type(),
+            "ETH@sepolia"
+        );
+        let pool = factory.parse("POOL@local:0x0000000000000000000000000000000000000001")?;
+        assert_eq!(pool.get_network_type(), "POOL@local");
+        let usdc = factory.parse("USDC@mainnet")?;
+        let usdc = usdc.as_any().downcast_ref::<Erc20Token>().unwrap();
+        assert_eq!(usdc.address(), Address::from_low_u64_be(2));
+        assert_eq!(usdc.convert_internal_unit_to_display_unit("1000000")?, "1");
+        assert!(factory.parse("DAI@mainnet").is_err());
+        assert_eq!(factory.clients.lock().unwrap().len(), 3);
+        assert!(parse_asset("USDC@mainnet").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! Reads pinned to a block, for results that can be reproduced later. web3's `BlockId` has no
+//! `safe` and `finalized` tags and cannot ask for `requireCanonical`, so the block parameter is
+//! built here and the reads are sent as raw RPC calls
+use crate::dry_run::revert_reason;
+use crate::state_override::StateOverride;
+use eyre::*;
+use serde_json::{json, Value};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use web3::contract::tokens::{Detokenize, Tokenize};
+use web3::contract::Contract;
+use web3::types::{Address, Block, Bytes, CallRequest, H256, U256};
+use web3::{Transport, Web3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockRef {
+    Latest,
+    Safe,
+    Finalized,
+    Pending,
+    Earliest,
+    Number(u64),
+    Hash(H256),
+}
+impl BlockRef {
+    /// The block parameter of `eth_call`, `eth_getBalance` and the like. Hashes use EIP-1898
+    /// with `requireCanonical`, so reading at a block that was reorged out fails instead of
+    /// returning the state of the abandoned fork
+    pub fn to_param(&self) -> Value {
+        match self {
+            BlockRef::Latest => json!("latest"),
+            BlockRef::Safe => json!("safe"),
+            BlockRef::Finalized => json!("finalized"),
+            BlockRef::Pending => json!("pending"),
+            BlockRef::Earliest => json!("earliest"),
+            BlockRef::Number(number) => json!(format!("0x{:x}", number)),
+            BlockRef::Hash(hash) => json!({
+                "blockHash": format!("{:?}", hash),
+                "requireCanonical": true,
+            }),
+        }
+    }
+}
+/// Accepts the tags, a decimal or `0x` block number, or a 32 byte block hash
+impl FromStr for BlockRef {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let block = match s {
+            "latest" => BlockRef::Latest,
+            "safe" => BlockRef::Safe,
+            "finalized" => BlockRef::Finalized,
+            "pending" => BlockRef::Pending,
+            "earliest" => BlockRef::Earliest,
+            _ if s.starts_with("0x") && s.len() == 66 => BlockRef::Hash(H256::from_str(s)?),
+            _ if s.starts_with("0x") => BlockRef::Number(
+                u64::from_str_radix(&s[2..], 16).with_context(|| format!("block {}", s))?,
+            ),
+            _ => BlockRef::Number(s.parse().with_context(|| format!("block {}", s))?),
+        };
+        Ok(block)
+    }
+}
+impl Display for BlockRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockRef::Latest => write!(f, "latest"),
+            BlockRef::Safe => write!(f, "safe"),
+            BlockRef::Finalized => write!(f, "finalized"),
+            BlockRef::Pending => write!(f, "pending"),
+            BlockRef::Earliest => write!(f, "earliest"),
+            BlockRef::Number(number) => write!(f, "{}", number),
+            BlockRef::Hash(hash) => write!(f, "{:?}", hash),
+        }
+    }
+}
+
+fn block_param(block: Option<BlockRef>) -> Value {
+    block.unwrap_or(BlockRef::Latest).to_param()
+}
+
+/// `eth_call` at `block`, `latest` if `None`. Keeps the web3 error so reverts can be told apart
+pub async fn call<T: Transport>(
+    web3: &Web3<T>,
+    request: CallRequest,
+    block: Option<BlockRef>,
+) -> web3::Result<Bytes> {
+    call_with_overrides(web3, request, block, &StateOverride::new()).await
+}
+
+/// `eth_call` against state changed by `overrides`, which geth, Erigon, Anvil and most
+/// providers support
+pub async fn call_with_overrides<T: Transport>(
+    web3: &Web3<T>,
+    request: CallRequest,
+    block: Option<BlockRef>,
+    overrides: &StateOverride,
+) -> web3::Result<Bytes> {
+    let decoder = |e: serde_json::Error| web3::Error::Decoder(e.to_string());
+    let mut params = vec![
+        serde_json::to_value(&request).map_err(decoder)?,
+        block_param(block),
+    ];
+    if !overrides.is_empty() {
+        params.push(serde_json::to_value(overrides).map_err(decoder)?);
+    }
+    let output = web3.transport().execute("eth_call", params).await?;
+    serde_json::from_value(output).map_err(decoder)
+}
+
+pub async fn balance<T: Transport>(
+    web3: &Web3<T>,
+    address: Address,
+    block: Option<BlockRef>,
+) -> Result<U256> {
+    let address = json!(format!("{:?}", address));
+    let balance = web3
+        .transport()
+        .execute("eth_getBalance", vec![address, block_param(block)])
+        .await?;
+    Ok(serde_json::from_value(balance)?)
+}
+
+pub async fn code<T: Transport>(
+    web3: &Web3<T>,
+    address: Address,
+    block: Option<BlockRef>,
+) -> Result<Vec<u8>> {
+    let address = json!(format!("{:?}", address));
+    let code = web3
+        .transport()
+        .execute("eth_getCode", vec![address, block_param(block)])
+        .await?;
+    let code: Bytes = serde_json::from_value(code)?;
+    Ok(code.0)
+}
+
+/// `eth_getStorageAt` of `slot`. Some nodes leave out the leading zeros
+pub async fn storage<T: Transport>(
+    web3: &Web3<T>,
+    address: Address,
+    slot: H256,
+    block: Option<BlockRef>,
+) -> Result<H256> {
+    let params = vec![
+        json!(format!("{:?}", address)),
+        json!(format!("{:?}", slot)),
+        block_param(block),
+    ];
+    let value = web3.transport().execute("eth_getStorageAt", params).await?;
+    let value: U256 = serde_json::from_value(value)?;
+    let mut slot = H256::zero();
+    value.to_big_endian(slot.as_bytes_mut());
+    Ok(slot)
+}
+
+/// Like `Contract::query`, at any `BlockRef`
+pub async fn query<T: Transport, R: Detokenize>(
+    web3: &Web3<T>,
+    contract: &Contract<T>,
+    func: &str,
+    params: impl Tokenize,
+    block: Option<BlockRef>,
+) -> Result<R> {
+    let overrides = StateOverride::new();
+    query_with_overrides(web3, contract, None, func, params, block, &overrides).await
+}
+
+/// `query` as `from`, against state changed by `overrides`
+pub async fn query_with_overrides<T: Transport, R: Detokenize>(
+    web3: &Web3<T>,
+    contract: &Contract<T>,
+    from: Option<Address>,
+    func: &str,
+    params: impl Tokenize,
+    block: Option<BlockRef>,
+    overrides: &StateOverride,
+) -> Result<R> {
+    let function = contract.abi().function(func)?;
+    let request = CallRequest {
+        from,
+        to: Some(contract.address()),
+        data: Some(function.encode_input(&params.into_tokens())?.into()),
+        ..Default::default()
+    };
+    let output = match call_with_overrides(web3, request, block, overrides).await {
+        Ok(output) => output,
+        Err(err) => match revert_reason(&err) {
+            Some(reason) => bail!("{} on {:?} reverts: {}", func, contract.address(), reason),
+            None => return Err(err).context(format!("{} on {:?}", func, contract.address())),
+        },
+    };
+    let tokens = function.decode_output(&output.0)?;
+    Ok(R::from_tokens(tokens)?)
+}
+
+/// Number and hash of `block`, to pin several reads to the same block
+pub async fn resolve<T: Transport>(web3: &Web3<T>, block: BlockRef) -> Result<(u64, H256)> {
+    let (method, param) = match block {
+        BlockRef::Hash(hash) => ("eth_getBlockByHash", json!(format!("{:?}", hash))),
+        _ => ("eth_getBlockByNumber", block.to_param()),
+    };
+    let found = web3
+        .transport()
+        .execute(method, vec![param, json!(false)])
+        .await?;
+    let found: Option<Block<H256>> = serde_json::from_value(found)?;
+    let found = found.with_context(|| format!("block {} not found", block))?;
+    match (found.number, found.hash) {
+        (Some(number), Some(hash)) => Ok((number.as_u64(), hash)),
+        _ => bail!("block {} is pending", block),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_ref() -> Result<()> {
+        let hash = H256::repeat_byte(0xab);
+        for (s, block) in [
+            ("latest", BlockRef::Latest),
+            ("safe", BlockRef::Safe),
+            ("finalized", BlockRef::Finalized),
+            ("17000000", BlockRef::Number(17_000_000)),
+            (&format!("{:?}", hash), BlockRef::Hash(hash)),
+        ] {
+            assert_eq!(BlockRef::from_str(s)?, block);
+            assert_eq!(block.to_string(), s);
+        }
+        assert_eq!(BlockRef::from_str("0x10")?, BlockRef::Number(16));
+        assert!(BlockRef::from_str("tomorrow").is_err());
+
+        assert_eq!(BlockRef::Number(16).to_param(), json!("0x10"));
+        assert_eq!(
+            BlockRef::Hash(hash).to_param(),
+            json!({"blockHash": format!("{:?}", hash), "requireCanonical": true})
+        );
+        Ok(())
+    }
+}
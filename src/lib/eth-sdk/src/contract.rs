@@ -1,16 +1,20 @@
-use eyre::ContextCompat;
+use eyre::{ensure, ContextCompat, WrapErr};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::future::Future;
+use std::path::Path;
 use std::{collections::HashMap, time};
-use web3::api::{Accounts, Eth, Namespace};
+use web3::api::{Eth, Namespace};
 use web3::contract::deploy::Error;
 use web3::contract::tokens::Tokenize;
 use web3::contract::{Contract, Options};
-use web3::signing::Key;
-use web3::types::{Address, TransactionParameters, TransactionReceipt, TransactionRequest};
-use web3::{ethabi, Transport};
+use web3::signing::keccak256;
+use web3::types::{
+    Address, CallRequest, TransactionParameters, TransactionReceipt, TransactionRequest, H160, H256,
+};
+use web3::{ethabi, Transport, Web3};
 
+use crate::artifact::{self, ImmutableReferences, Libraries, LinkReferences};
+use crate::signer::EthereumSigner;
 use crate::utils::wait_for_confirmations_simple;
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -18,13 +22,33 @@ pub struct ForgeJsonOutputCode {
     pub object: String,
     pub source_map: String,
     pub link_references: Value,
+    /// Only in `deployedBytecode`
+    #[serde(default)]
+    pub immutable_references: ImmutableReferences,
 }
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct ForgeJsonOutput {
     pub abi: Vec<Value>,
     pub bytecode: ForgeJsonOutputCode,
     pub deployed_bytecode: ForgeJsonOutputCode,
 }
+impl ForgeJsonOutput {
+    /// Reads an artifact such as `out/Escrow.sol/Escrow.json`
+    pub fn load(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("parsing {}", path.display()))
+    }
+    /// A deployer of the artifact's creation code
+    pub fn deployer<T: Transport>(&self, eth: Eth<T>) -> eyre::Result<ContractDeployer<T>> {
+        Ok(ContractDeployer::new(eth, Value::Array(self.abi.clone()))?
+            .code(self.bytecode.object.clone())
+            .link_references(serde_json::from_value(
+                self.bytecode.link_references.clone(),
+            )?))
+    }
+}
 /// A configuration builder for contract deployment.
 #[derive(Debug)]
 pub struct ContractDeployer<T: Transport> {
@@ -33,7 +57,8 @@ pub struct ContractDeployer<T: Transport> {
     pub(crate) options: Options,
     pub(crate) max_retries: usize,
     pub(crate) poll_interval: time::Duration,
-    pub(crate) linker: HashMap<String, Address>,
+    pub(crate) linker: Libraries,
+    pub(crate) link_references: LinkReferences,
     pub(crate) code: Option<String>,
 }
 
@@ -47,6 +72,7 @@ impl<T: Transport> ContractDeployer<T> {
             max_retries: 3,
             poll_interval: time::Duration::from_secs(7),
             linker: HashMap::default(),
+            link_references: LinkReferences::new(),
             code: None,
         })
     }
@@ -54,6 +80,24 @@ impl<T: Transport> ContractDeployer<T> {
         self.code = Some(code);
         self
     }
+    /// Links `name`, either `Math` or `src/Math.sol:Math`, to `address`
+    pub fn library(mut self, name: impl Into<String>, address: Address) -> Self {
+        self.linker.insert(name.into(), address);
+        self
+    }
+    pub fn libraries(mut self, libraries: &Libraries) -> Self {
+        self.linker.extend(
+            libraries
+                .iter()
+                .map(|(name, address)| (name.clone(), *address)),
+        );
+        self
+    }
+    /// Offsets of the library placeholders in the code, see `Artifact`
+    pub fn link_references(mut self, link_references: LinkReferences) -> Self {
+        self.link_references = link_references;
+        self
+    }
     /// Number of confirmations required after code deployment.
     pub fn max_retries(mut self, max_retries: usize) -> Self {
         self.max_retries = max_retries;
@@ -75,118 +119,110 @@ impl<T: Transport> ContractDeployer<T> {
     /// Execute deployment passing code and constructor parameters.
     ///
     /// Unlike the above `sign_and_execute`, this method allows the
-    /// caller to pass in a private key to sign the transaction with
+    /// caller to pass in a signer to sign the transaction with
     /// and therefore allows deploying from an account that the
     /// ethereum node doesn't need to know the private key for.
     ///
-    /// An optional `chain_id` parameter can be passed to provide
-    /// replay protection for transaction signatures. Passing `None`
-    /// would create a transaction WITHOUT replay protection and
-    /// should be avoided.
-    /// You can obtain `chain_id` of the network you are connected
-    /// to using `web3.eth().chain_id()` method.
-    pub async fn sign_with_key_and_execute<P, K>(
+    /// The transaction is signed through `EthereumSigner::sign_transaction`,
+    /// so the policy of the signer, if any, applies.
+    pub async fn sign_with_key_and_execute<P>(
         &self,
         params: P,
-        signer: K,
+        signer: &EthereumSigner,
     ) -> eyre::Result<Contract<T>>
     where
         P: Tokenize,
-        K: Key,
     {
-        let transport = self.eth.transport().clone();
-        let poll_interval = self.poll_interval;
-        let max_retries = self.max_retries;
-        let chain_id = Some(self.eth.chain_id().await?.as_u64());
-        let x = self
-            .do_execute(
-                self.code.as_deref().context("Code is not provided")?,
-                params,
-                signer.address(),
-                move |tx| async move {
-                    let tx = TransactionParameters {
-                        nonce: tx.nonce,
-                        to: tx.to,
-                        gas: tx.gas.unwrap_or_else(|| 1_000_000.into()),
-                        gas_price: tx.gas_price,
-                        value: tx.value.unwrap_or_else(|| 0.into()),
-                        data: tx
-                            .data
-                            .expect("Tried to deploy a contract but transaction data wasn't set"),
-                        chain_id,
-                        transaction_type: tx.transaction_type,
-                        access_list: tx.access_list,
-                        max_fee_per_gas: tx.max_fee_per_gas,
-                        max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
-                    };
-                    let signed_tx = Accounts::new(transport.clone())
-                        .sign_transaction(tx, signer)
-                        .await?;
-                    // TODO: buggy here
-                    let tx_hash = Eth::new(transport.clone())
-                        .send_raw_transaction(signed_tx.raw_transaction)
-                        .await?;
-                    wait_for_confirmations_simple(
-                        &Eth::new(transport),
-                        tx_hash,
-                        poll_interval,
-                        max_retries,
-                    )
-                    .await
-                },
-            )
-            .await?;
-        Ok(x)
+        let (contract, _) = self.sign_with_key_and_deploy(params, signer).await?;
+        Ok(contract)
     }
 
-    async fn do_execute<P, V, Ft>(
+    /// Same as `sign_with_key_and_execute`, also returning the receipt of the deployment
+    pub async fn sign_with_key_and_deploy<P>(
         &self,
-        code: V,
         params: P,
-        from: Address,
-        send: impl FnOnce(TransactionRequest) -> Ft,
-    ) -> eyre::Result<Contract<T>>
+        signer: &EthereumSigner,
+    ) -> eyre::Result<(Contract<T>, TransactionReceipt)>
     where
         P: Tokenize,
-        V: AsRef<str>,
-        Ft: Future<Output = eyre::Result<TransactionReceipt>>,
     {
-        let options = &self.options;
-        let eth = &self.eth;
-        let abi = &self.abi;
-
-        let mut code_hex = code.as_ref().to_string();
-
-        for (lib, address) in &self.linker {
-            if lib.len() > 38 {
-                return Err(Error::Abi(ethabi::Error::InvalidName(
-                    "The library name should be under 39 characters.".into(),
-                ))
-                .into());
+        let init_code = self.init_code(params)?;
+        let tx = self.transaction(signer.address, None, init_code);
+        let receipt = self.send_signed(tx, signer).await?;
+        match receipt.status {
+            Some(status) if status == 0.into() => {
+                Err(Error::ContractDeploymentFailure(receipt.transaction_hash).into())
             }
-            let replace = format!("__{:_<38}", lib); // This makes the required width 38 characters and will pad with `_` to match it.
-            let address: String = hex::encode(address);
-            code_hex = code_hex.replacen(&replace, &address, 1);
+            // If the `status` field is not present we use the presence of `contract_address` to
+            // determine if deployment was successfull.
+            _ => match receipt.contract_address {
+                Some(address) => Ok((
+                    Contract::new(self.eth.clone(), address, self.abi.clone()),
+                    receipt,
+                )),
+                None => Err(Error::ContractDeploymentFailure(receipt.transaction_hash).into()),
+            },
         }
-        code_hex = code_hex.replace("\"", "").replace("0x", ""); // This is to fix truffle + serde_json redundant `"` and `0x`
-        let code = hex::decode(&code_hex)
-            .map_err(|e| ethabi::Error::InvalidName(format!("hex decode error: {}", e)))?;
-
-        let params = params.into_tokens();
-        let data = match (abi.constructor(), params.is_empty()) {
-            (None, false) => {
-                return Err(Error::Abi(ethabi::Error::InvalidName(
-                    "Constructor is not defined in the ABI.".into(),
-                ))
-                .into());
-            }
-            (None, true) => code,
-            (Some(constructor), _) => constructor.encode_input(code, &params)?,
-        };
+    }
+
+    /// Deploys through the deterministic deployment proxy, so that the address only depends on
+    /// `salt` and the init code, see `predict_create2_address`. The receipt is `None` if the
+    /// contract was already there
+    pub async fn sign_with_key_and_deploy_create2<P>(
+        &self,
+        params: P,
+        salt: H256,
+        signer: &EthereumSigner,
+    ) -> eyre::Result<(Contract<T>, Option<TransactionReceipt>)>
+    where
+        P: Tokenize,
+    {
+        let init_code = self.init_code(params)?;
+        let address = create2_address(CREATE2_DEPLOYER, salt, &init_code);
+        let contract = Contract::new(self.eth.clone(), address, self.abi.clone());
+        if !self.eth.code(address, None).await?.0.is_empty() {
+            return Ok((contract, None));
+        }
+        ensure!(
+            !self.eth.code(CREATE2_DEPLOYER, None).await?.0.is_empty(),
+            "the deterministic deployment proxy {:?} is not on this chain",
+            CREATE2_DEPLOYER
+        );
+        let mut data = salt.as_bytes().to_vec();
+        data.extend(init_code);
+        let tx = self.transaction(signer.address, Some(CREATE2_DEPLOYER), data);
+        let receipt = self.send_signed(tx, signer).await?;
+        if receipt.status == Some(0.into()) || self.eth.code(address, None).await?.0.is_empty() {
+            return Err(Error::ContractDeploymentFailure(receipt.transaction_hash).into());
+        }
+        Ok((contract, Some(receipt)))
+    }
 
-        let tx = TransactionRequest {
+    /// Address `sign_with_key_and_deploy_create2` deploys to, computed offline
+    pub fn predict_create2_address<P: Tokenize>(
+        &self,
+        params: P,
+        salt: H256,
+    ) -> eyre::Result<Address> {
+        Ok(create2_address(
+            CREATE2_DEPLOYER,
+            salt,
+            &self.init_code(params)?,
+        ))
+    }
+
+    /// Creation code with the libraries linked and the constructor arguments appended
+    pub fn init_code<P: Tokenize>(&self, params: P) -> eyre::Result<Vec<u8>> {
+        let code = self.code.as_deref().context("Code is not provided")?;
+        let code = artifact::link(code, &self.link_references, &self.linker)?;
+        encode_constructor(&self.abi, code, params)
+    }
+
+    fn transaction(&self, from: Address, to: Option<Address>, data: Vec<u8>) -> TransactionRequest {
+        let options = &self.options;
+        TransactionRequest {
             from,
-            to: None,
+            to,
             gas: options.gas,
             gas_price: options.gas_price,
             value: options.value,
@@ -197,18 +233,105 @@ impl<T: Transport> ContractDeployer<T> {
             access_list: options.access_list.clone(),
             max_fee_per_gas: options.max_fee_per_gas,
             max_priority_fee_per_gas: options.max_priority_fee_per_gas,
-        };
-        let receipt = send(tx).await?;
-        match receipt.status {
-            Some(status) if status == 0.into() => {
-                Err(Error::ContractDeploymentFailure(receipt.transaction_hash).into())
-            }
-            // If the `status` field is not present we use the presence of `contract_address` to
-            // determine if deployment was successfull.
-            _ => match receipt.contract_address {
-                Some(address) => Ok(Contract::new(eth.clone(), address, abi.clone())),
-                None => Err(Error::ContractDeploymentFailure(receipt.transaction_hash).into()),
-            },
         }
     }
+
+    async fn send_signed(
+        &self,
+        tx: TransactionRequest,
+        signer: &EthereumSigner,
+    ) -> eyre::Result<TransactionReceipt> {
+        let gas = match tx.gas {
+            Some(gas) => gas,
+            None => {
+                let call = CallRequest {
+                    from: Some(tx.from),
+                    to: tx.to,
+                    value: tx.value,
+                    data: tx.data.clone(),
+                    ..Default::default()
+                };
+                self.eth
+                    .estimate_gas(call, None)
+                    .await
+                    .context("estimating deployment gas")?
+            }
+        };
+        let tx = TransactionParameters {
+            nonce: tx.nonce,
+            to: tx.to,
+            gas,
+            gas_price: tx.gas_price,
+            value: tx.value.unwrap_or_else(|| 0.into()),
+            data: tx
+                .data
+                .expect("Tried to deploy a contract but transaction data wasn't set"),
+            chain_id: None,
+            transaction_type: tx.transaction_type,
+            access_list: tx.access_list,
+            max_fee_per_gas: tx.max_fee_per_gas,
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+        };
+        let web3 = Web3::new(self.eth.transport().clone());
+        let tx_hash = signer.send_transaction(&web3, tx).await?;
+        wait_for_confirmations_simple(&self.eth, tx_hash, self.poll_interval, self.max_retries)
+            .await
+    }
+}
+
+/// The deterministic deployment proxy, at the same address on every chain it is deployed to.
+/// It CREATE2s the calldata after its first 32 bytes, with those as the salt
+pub const CREATE2_DEPLOYER: Address = H160([
+    0x4e, 0x59, 0xb4, 0x48, 0x47, 0xb3, 0x79, 0x57, 0x85, 0x88, 0x92, 0x0c, 0xa7, 0x8f, 0xbf, 0x26,
+    0xc0, 0xb4, 0x95, 0x6c,
+]);
+
+/// Appends the constructor arguments to `code`
+pub fn encode_constructor<P: Tokenize>(
+    abi: &ethabi::Contract,
+    code: Vec<u8>,
+    params: P,
+) -> eyre::Result<Vec<u8>> {
+    let params = params.into_tokens();
+    match (abi.constructor(), params.is_empty()) {
+        (None, false) => Err(Error::Abi(ethabi::Error::InvalidName(
+            "Constructor is not defined in the ABI.".into(),
+        ))
+        .into()),
+        (None, true) => Ok(code),
+        (Some(constructor), _) => Ok(constructor.encode_input(code, &params)?),
+    }
+}
+
+/// EIP-1014: `keccak256(0xff ++ deployer ++ salt ++ keccak256(init_code))[12..]`
+pub fn create2_address(deployer: Address, salt: H256, init_code: &[u8]) -> Address {
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(deployer.as_bytes());
+    preimage.extend_from_slice(salt.as_bytes());
+    preimage.extend_from_slice(&keccak256(init_code));
+    Address::from_slice(&keccak256(&preimage)[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_create2_address() -> eyre::Result<()> {
+        // examples from EIP-1014
+        assert_eq!(
+            create2_address(Address::zero(), H256::zero(), &[0]),
+            Address::from_str("4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38")?
+        );
+        assert_eq!(
+            create2_address(
+                Address::from_str("00000000000000000000000000000000deadbeef")?,
+                H256::from_low_u64_be(0xcafebabe),
+                &hex::decode("deadbeef")?
+            ),
+            Address::from_str("60f3f640a8508fC6a86d45DF051962668E1e8AC7")?
+        );
+        Ok(())
+    }
 }
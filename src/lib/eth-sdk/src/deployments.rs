@@ -0,0 +1,132 @@
+//! Addresses of deployed contracts, one JSON file per network
+use crate::EthereumNet;
+use eyre::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use web3::types::{Address, H256};
+use web3::{Transport, Web3};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deployment {
+    pub address: Address,
+    pub transaction: Option<H256>,
+    pub block: Option<u64>,
+    /// Set for CREATE2 deployments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<H256>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Deployments {
+    pub chain_id: u64,
+    pub contracts: BTreeMap<String, Deployment>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Deployments {
+    /// `<dir>/<network>.json`, e.g. `deployments/sepolia.json`. Chains without an `EthereumNet`
+    /// are named by id, e.g. `deployments/chain-10.json`
+    pub fn path(dir: impl AsRef<Path>, chain_id: u64) -> PathBuf {
+        let name = match EthereumNet::from_chain_id(chain_id) {
+            Some(net) => net.name().to_owned(),
+            None => format!("chain-{}", chain_id),
+        };
+        dir.as_ref().join(format!("{}.json", name))
+    }
+    /// Loads the file of `chain_id` under `dir`, empty if there is none yet
+    pub fn load(dir: impl AsRef<Path>, chain_id: u64) -> Result<Self> {
+        let path = Self::path(dir, chain_id);
+        if !path.exists() {
+            return Ok(Self {
+                chain_id,
+                contracts: BTreeMap::new(),
+                path,
+            });
+        }
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let mut this: Self =
+            serde_json::from_str(&json).with_context(|| format!("parsing {}", path.display()))?;
+        ensure!(
+            this.chain_id == chain_id,
+            "{} is for chain {}, not {}",
+            path.display(),
+            this.chain_id,
+            chain_id
+        );
+        this.path = path;
+        Ok(this)
+    }
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("writing {}", self.path.display()))
+    }
+    pub fn get(&self, name: &str) -> Option<&Deployment> {
+        self.contracts.get(name)
+    }
+    pub fn insert(&mut self, name: &str, deployment: Deployment) {
+        self.contracts.insert(name.to_owned(), deployment);
+    }
+    /// The recorded deployment of `name`, if there is still code at its address. A reset local
+    /// chain keeps the file but loses the contracts
+    pub async fn deployed<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        name: &str,
+    ) -> Result<Option<&Deployment>> {
+        let deployment = match self.get(name) {
+            Some(deployment) => deployment,
+            None => return Ok(None),
+        };
+        let code = web3.eth().code(deployment.address, None).await?;
+        Ok(if code.0.is_empty() {
+            None
+        } else {
+            Some(deployment)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(
+            Deployments::path(dir.path(), 11155111),
+            dir.path().join("sepolia.json")
+        );
+        assert_eq!(
+            Deployments::path(dir.path(), 10),
+            dir.path().join("chain-10.json")
+        );
+
+        let mut deployments = Deployments::load(dir.path(), 31337)?;
+        assert!(deployments.get("Escrow").is_none());
+        let escrow = Deployment {
+            address: Address::repeat_byte(1),
+            transaction: Some(H256::repeat_byte(2)),
+            block: Some(3),
+            salt: None,
+        };
+        deployments.insert("Escrow", escrow.clone());
+        deployments.save()?;
+
+        let deployments = Deployments::load(dir.path(), 31337)?;
+        assert_eq!(deployments.get("Escrow"), Some(&escrow));
+        // copied to another chain by mistake
+        std::fs::copy(
+            dir.path().join("local.json"),
+            dir.path().join("sepolia.json"),
+        )?;
+        assert!(Deployments::load(dir.path(), 11155111).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! A throwaway Anvil node for integration tests, see <https://book.getfoundry.sh/anvil/>
+use crate::artifact::Artifact;
+use crate::erc20::Erc20Token;
+use crate::signer::EthereumSigner;
+use crate::utils::{test_signer, TEST_MNEMONIC};
+use crate::EthereumNet;
+use eyre::*;
+use serde_json::json;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use web3::contract::tokens::Tokenize;
+use web3::transports::Http;
+use web3::types::{Address, U256};
+use web3::{Transport, Web3};
+
+/// HumanStandardToken built with solc 0.4, the whole supply goes to the deployer
+const TEST_ERC20_ARTIFACT: &str = include_str!("test_erc20.json");
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How to start Anvil. Accounts are those of `TEST_MNEMONIC`, so `test_signer(i)` is funded
+#[derive(Debug, Clone)]
+pub struct Anvil {
+    program: PathBuf,
+    chain_id: u64,
+    accounts: u32,
+    balance: u64,
+    args: Vec<String>,
+}
+
+impl Default for Anvil {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::from("anvil"),
+            chain_id: EthereumNet::Local.chain_id(),
+            accounts: 10,
+            balance: 10_000,
+            args: vec![],
+        }
+    }
+}
+
+impl Anvil {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Path of the binary, `anvil` on the `PATH` by default
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+    pub fn accounts(mut self, accounts: u32) -> Self {
+        self.accounts = accounts;
+        self
+    }
+    /// ETH each account starts with
+    pub fn balance(mut self, eth: u64) -> Self {
+        self.balance = eth;
+        self
+    }
+    /// Any other flag, e.g. `--fork-url`
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+    /// Starts Anvil on a free port and waits until it answers
+    pub async fn spawn(self) -> Result<Devnet> {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let child = Command::new(&self.program)
+            .arg("--port")
+            .arg(port.to_string())
+            .arg("--chain-id")
+            .arg(self.chain_id.to_string())
+            .arg("--accounts")
+            .arg(self.accounts.to_string())
+            .arg("--balance")
+            .arg(self.balance.to_string())
+            .arg("--mnemonic")
+            .arg(TEST_MNEMONIC)
+            .args(&self.args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| {
+                format!(
+                    "cannot run {}, install Foundry from https://getfoundry.sh",
+                    self.program.display()
+                )
+            })?;
+        let url = format!("http://127.0.0.1:{}", port);
+        // killed on drop from here on
+        let mut devnet = Devnet {
+            web3: Web3::new(Http::new(&url)?),
+            child,
+            url,
+            chain_id: self.chain_id,
+            accounts: self.accounts,
+        };
+        let started = Instant::now();
+        loop {
+            if let Some(status) = devnet.child.try_wait()? {
+                bail!("anvil exited with {}", status);
+            }
+            if devnet.web3.eth().chain_id().await.is_ok() {
+                return Ok(devnet);
+            }
+            ensure!(
+                started.elapsed() < STARTUP_TIMEOUT,
+                "anvil did not answer on {} within {:?}",
+                devnet.url,
+                STARTUP_TIMEOUT
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// A running Anvil node, killed on drop
+#[derive(Debug)]
+pub struct Devnet {
+    child: Child,
+    url: String,
+    web3: Web3<Http>,
+    chain_id: u64,
+    accounts: u32,
+}
+
+impl Devnet {
+    /// Anvil with the defaults of `Anvil`
+    pub async fn spawn() -> Result<Self> {
+        Anvil::new().spawn().await
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn web3(&self) -> &Web3<Http> {
+        &self.web3
+    }
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+    pub fn net(&self) -> EthereumNet {
+        EthereumNet::from_chain_id(self.chain_id).unwrap_or(EthereumNet::Local)
+    }
+    /// One of the funded accounts
+    pub fn signer(&self, index: u32) -> Result<EthereumSigner> {
+        ensure!(
+            index < self.accounts,
+            "only {} accounts are funded",
+            self.accounts
+        );
+        test_signer(index)
+    }
+    /// Sets the ETH balance of any address, e.g. of a key on an HSM
+    pub async fn fund(&self, address: Address, wei: U256) -> Result<()> {
+        self.web3
+            .transport()
+            .execute(
+                "anvil_setBalance",
+                vec![json!(address), json!(format!("{:#x}", wei))],
+            )
+            .await?;
+        Ok(())
+    }
+    /// Id to `revert` to. Each id can be reverted to once
+    pub async fn snapshot(&self) -> Result<U256> {
+        let id = self
+            .web3
+            .transport()
+            .execute("evm_snapshot", vec![])
+            .await?;
+        Ok(serde_json::from_value(id)?)
+    }
+    /// Undoes everything since `snapshot`, so that tests sharing a node don't see each other
+    pub async fn revert(&self, snapshot: U256) -> Result<()> {
+        let reverted = self
+            .web3
+            .transport()
+            .execute("evm_revert", vec![json!(format!("{:#x}", snapshot))])
+            .await?;
+        ensure!(
+            reverted == json!(true),
+            "no snapshot {:#x} to revert to",
+            snapshot
+        );
+        Ok(())
+    }
+    /// Deploys `artifact`, waiting for the receipt
+    pub async fn deploy(
+        &self,
+        artifact: &Artifact,
+        params: impl Tokenize,
+        by: &EthereumSigner,
+    ) -> Result<Address> {
+        let (contract, _) = artifact
+            .deployer(self.web3.eth())?
+            .poll_interval(Duration::from_millis(100))
+            .sign_with_key_and_deploy(params, by)
+            .await?;
+        Ok(contract.address())
+    }
+    /// Deploys the bundled ERC-20, minting all of `supply` to `by`
+    pub async fn deploy_erc20(
+        &self,
+        by: &EthereumSigner,
+        name: &str,
+        symbol: &str,
+        decimals: u8,
+        supply: U256,
+    ) -> Result<Erc20Token> {
+        let artifact = Artifact::from_json(TEST_ERC20_ARTIFACT)?;
+        let params = (
+            supply,
+            name.to_owned(),
+            U256::from(decimals),
+            symbol.to_owned(),
+        );
+        let address = self.deploy(&artifact, params, by).await?;
+        Erc20Token::new_with_client(self.net(), self.web3.clone(), address)
+    }
+}
+
+impl Drop for Devnet {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block;
+    use crate::utils::eth_to_wei;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_devnet() -> Result<()> {
+        let devnet = Anvil::new().accounts(2).balance(100).spawn().await?;
+        let owner = devnet.signer(0)?;
+        let user = devnet.signer(1)?;
+        assert!(devnet.signer(2).is_err());
+        let balance = block::balance(devnet.web3(), user.address, None).await?;
+        assert_eq!(balance, eth_to_wei("100")?);
+
+        let snapshot = devnet.snapshot().await?;
+        let supply = U256::exp10(24);
+        let token = devnet
+            .deploy_erc20(&owner, "Test", "TEST", 18, supply)
+            .await?;
+        assert_eq!(token.metadata().await?.symbol, "TEST");
+        assert_eq!(token.balance_of(owner.address, None).await?, supply);
+        token
+            .transfer_checked(&owner, user.address, U256::exp10(18))
+            .await?;
+        assert_eq!(token.balance_of(user.address, None).await?, U256::exp10(18));
+
+        let hsm = Address::repeat_byte(0x11);
+        devnet.fund(hsm, U256::exp10(18)).await?;
+        assert_eq!(
+            block::balance(devnet.web3(), hsm, None).await?,
+            U256::exp10(18)
+        );
+
+        devnet.revert(snapshot).await?;
+        assert!(block::code(devnet.web3(), token.address(), None)
+            .await?
+            .is_empty());
+        assert!(block::balance(devnet.web3(), hsm, None).await?.is_zero());
+        assert!(devnet.revert(snapshot).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! What a transaction would do, found with `eth_call` without signing or sending it
+use eyre::*;
+use std::fmt::{Display, Formatter};
+use web3::ethabi::{self, ParamType, Token};
+use web3::types::{Address, TransactionParameters, U256};
+
+/// Outcome of `EthereumSigner::dry_run`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRun {
+    /// Decoded return values, none when the call returns nothing, or why it fails
+    pub result: std::result::Result<Vec<Token>, DryRunError>,
+    /// Gas estimate, `None` when the call reverts
+    pub gas: Option<U256>,
+    /// The transaction that would be signed, nonce and fees included
+    pub transaction: TransactionParameters,
+}
+
+impl DryRun {
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+    /// The return values, failing with the revert reason
+    pub fn into_output(self) -> Result<Vec<Token>> {
+        self.result.map_err(|err| eyre!("{}", err))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DryRunError {
+    Revert(String),
+    /// Calls to an account without code succeed without doing anything
+    NoCode(Address),
+}
+
+impl Display for DryRunError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DryRunError::Revert(reason) => write!(f, "reverts: {}", reason),
+            DryRunError::NoCode(address) => write!(f, "no code at {:?}", address),
+        }
+    }
+}
+
+/// Why a call reverted, from the error a node answers `eth_call` or `eth_estimateGas` with.
+/// `None` for other errors, e.g. a node that is down or a sender without enough ETH
+pub fn revert_reason(error: &web3::Error) -> Option<String> {
+    let error = match error {
+        web3::Error::Rpc(error) => error,
+        _ => return None,
+    };
+    let data = error
+        .data
+        .as_ref()
+        .and_then(|x| x.as_str())
+        .and_then(|x| hex::decode(x.trim_start_matches("0x")).ok());
+    match data {
+        Some(data) => Some(decode_revert(&data)),
+        None if error.message.contains("revert") => Some(error.message.clone()),
+        None => None,
+    }
+}
+
+/// Decodes `Error(string)` and `Panic(uint256)`, custom errors are left as hex
+pub fn decode_revert(data: &[u8]) -> String {
+    let decoded = |types: &[ParamType]| ethabi::decode(types, &data[4..]).ok();
+    match data.get(..4) {
+        None => "reverted without a reason".to_owned(),
+        Some([0x08, 0xc3, 0x79, 0xa0]) => match decoded(&[ParamType::String]).as_deref() {
+            Some([Token::String(reason)]) => reason.clone(),
+            _ => format!("malformed Error(string) 0x{}", hex::encode(data)),
+        },
+        Some([0x4e, 0x48, 0x7b, 0x71]) => match decoded(&[ParamType::Uint(256)]).as_deref() {
+            Some([Token::Uint(code)]) => format!("panic {:#04x}", code),
+            _ => format!("malformed Panic(uint256) 0x{}", hex::encode(data)),
+        },
+        Some(_) => format!("custom error 0x{}", hex::encode(data)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::utils::{function_selector, test_signer};
+    use serde_json::json;
+    use web3::contract::Contract;
+
+    const ERC20_ABI: &str = include_str!("erc20.abi.json");
+
+    #[test]
+    fn test_decode_revert() {
+        let mut error = function_selector("Error(string)").to_vec();
+        error.extend(ethabi::encode(&[Token::String("too low".to_owned())]));
+        assert_eq!(decode_revert(&error), "too low");
+        let mut panic = function_selector("Panic(uint256)").to_vec();
+        panic.extend(ethabi::encode(&[Token::Uint(0x11.into())]));
+        assert_eq!(decode_revert(&panic), "panic 0x11");
+        assert_eq!(decode_revert(&[]), "reverted without a reason");
+        assert_eq!(decode_revert(&[1, 2, 3, 4]), "custom error 0x01020304");
+        assert!(decode_revert(&error[..10]).starts_with("malformed"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dry_run() -> Result<()> {
+        let mock = MockTransport::new();
+        let web3 = mock.web3();
+        let token = Address::repeat_byte(1);
+        let contract = Contract::from_json(web3.eth(), token, ERC20_ABI.as_bytes())?;
+        let signer = test_signer(0)?;
+        let to = Address::repeat_byte(2);
+
+        mock.expect_call(
+            function_selector("transfer(address,uint256)"),
+            &[Token::Bool(true)],
+        );
+        let dry_run = signer
+            .dry_run_call(&web3, &contract, "transfer", (to, U256::from(5)), 0.into())
+            .await?;
+        assert_eq!(dry_run.result, Ok(vec![Token::Bool(true)]));
+        assert_eq!(dry_run.gas, Some(100_000.into()));
+        let tx = &dry_run.transaction;
+        assert_eq!(tx.to, Some(token));
+        assert_eq!(tx.gas, 100_000.into());
+        assert_eq!(tx.nonce, Some(0.into()));
+        assert_eq!(tx.chain_id, Some(31337));
+        let (method, params) = mock.requests().pop().unwrap();
+        assert_eq!(method, "eth_call");
+        assert_eq!(params[0]["from"], json!(signer.address));
+        assert_eq!(params[0]["gas"], json!("0x186a0"));
+        assert_eq!(params[1], json!("pending"));
+
+        // USDT's transfer returns nothing
+        mock.expect("eth_call", json!("0x"));
+        mock.expect("eth_getCode", json!("0x6080"));
+        let dry_run = signer
+            .dry_run_call(&web3, &contract, "transfer", (to, U256::from(5)), 0.into())
+            .await?;
+        assert_eq!(dry_run.result, Ok(vec![]));
+        let (method, params) = mock.requests().pop().unwrap();
+        assert_eq!(method, "eth_getCode");
+        assert_eq!(params, [json!(token), json!("pending")]);
+        // neither does an account without code
+        mock.expect("eth_call", json!("0x"));
+        mock.expect("eth_getCode", json!("0x"));
+        let dry_run = signer
+            .dry_run_call(&web3, &contract, "transfer", (to, U256::from(5)), 0.into())
+            .await?;
+        assert_eq!(dry_run.result, Err(DryRunError::NoCode(token)));
+        assert_eq!(
+            dry_run.into_output().unwrap_err().to_string(),
+            format!("no code at {:?}", token)
+        );
+        // plain transfers of ETH call nothing
+        mock.expect("eth_call", json!("0x"));
+        let transfer = TransactionParameters {
+            to: Some(to),
+            value: 5.into(),
+            ..Default::default()
+        };
+        let dry_run = signer.dry_run(&web3, transfer, None, true).await?;
+        assert_eq!(dry_run.result, Ok(vec![]));
+
+        mock.expect_revert(
+            "eth_call",
+            function_selector("transfer(address,uint256)"),
+            "ERC20: transfer amount exceeds balance",
+        );
+        let dry_run = signer
+            .dry_run_call(&web3, &contract, "transfer", (to, U256::MAX), 0.into())
+            .await?;
+        assert!(!dry_run.is_success());
+        assert_eq!(
+            dry_run.into_output().unwrap_err().to_string(),
+            "reverts: ERC20: transfer amount exceeds balance"
+        );
+
+        // a revert while estimating gas needs no eth_call
+        mock.expect_revert(
+            "eth_estimateGas",
+            function_selector("approve(address,uint256)"),
+            "paused",
+        );
+        let dry_run = signer
+            .dry_run_call(&web3, &contract, "approve", (to, U256::one()), 0.into())
+            .await?;
+        assert_eq!(
+            dry_run.result,
+            Err(DryRunError::Revert("paused".to_owned()))
+        );
+        assert_eq!(dry_run.gas, None);
+
+        mock.expect_error(
+            "eth_call",
+            web3::Error::Rpc(jsonrpc_core::Error::internal_error()),
+        );
+        assert!(signer
+            .dry_run_call(&web3, &contract, "transfer", (to, U256::one()), 0.into())
+            .await
+            .is_err());
+        assert!(mock.sent().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::block::{self, BlockRef};
+use crate::erc165::{self, ERC1155};
+use crate::signer::EthereumSigner;
+use crate::EthereumNet;
+use eyre::*;
+use std::fmt::{Debug, Formatter};
+use web3::api::Web3;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::transports::http::Http;
+use web3::types::{Address, H256, U256};
+
+const ERC1155_ABI: &str = include_str!("erc1155.abi.json");
+
+/// An ERC-1155 multi-token contract. Writes are signed like `Erc20Token`'s, through
+/// `EthereumSigner::sign_transaction`
+pub struct Erc1155Token {
+    client: Web3<Http>,
+    net: EthereumNet,
+    address: Address,
+    contract: Contract<Http>,
+}
+
+impl Erc1155Token {
+    pub fn new(net: EthereumNet, address: Address) -> Result<Self> {
+        let client = net.client()?;
+        let contract = Contract::from_json(client.eth(), address, ERC1155_ABI.as_bytes())?;
+        Ok(Self {
+            client,
+            net,
+            address,
+            contract,
+        })
+    }
+    pub fn net(&self) -> EthereumNet {
+        self.net
+    }
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    async fn send_call(
+        &self,
+        by: &EthereumSigner,
+        func: &str,
+        params: impl Tokenize,
+    ) -> Result<H256> {
+        by.send_call(&self.client, &self.contract, func, params, U256::zero())
+            .await
+    }
+
+    pub async fn supports_interface(
+        &self,
+        interface_id: [u8; 4],
+        block: Option<BlockRef>,
+    ) -> Result<bool> {
+        erc165::supports_interface(&self.client, self.address, interface_id, block).await
+    }
+    /// Whether the contract reports ERC-1155 through ERC-165
+    pub async fn is_erc1155(&self, block: Option<BlockRef>) -> Result<bool> {
+        self.supports_interface(ERC1155, block).await
+    }
+    /// Metadata URI of token `id`, with the `{id}` placeholder already substituted
+    pub async fn uri(&self, id: U256, block: Option<BlockRef>) -> Result<String> {
+        let uri: String = block::query(&self.client, &self.contract, "uri", id, block).await?;
+        Ok(substitute_id(&uri, id))
+    }
+    pub async fn balance_of(
+        &self,
+        account: Address,
+        id: U256,
+        block: Option<BlockRef>,
+    ) -> Result<U256> {
+        block::query(
+            &self.client,
+            &self.contract,
+            "balanceOf",
+            (account, id),
+            block,
+        )
+        .await
+    }
+    /// Balance of `accounts[i]` in token `ids[i]`
+    pub async fn balance_of_batch(
+        &self,
+        accounts: Vec<Address>,
+        ids: Vec<U256>,
+        block: Option<BlockRef>,
+    ) -> Result<Vec<U256>> {
+        ensure!(
+            accounts.len() == ids.len(),
+            "{} accounts for {} ids",
+            accounts.len(),
+            ids.len()
+        );
+        block::query(
+            &self.client,
+            &self.contract,
+            "balanceOfBatch",
+            (accounts, ids),
+            block,
+        )
+        .await
+    }
+    pub async fn is_approved_for_all(
+        &self,
+        account: Address,
+        operator: Address,
+        block: Option<BlockRef>,
+    ) -> Result<bool> {
+        block::query(
+            &self.client,
+            &self.contract,
+            "isApprovedForAll",
+            (account, operator),
+            block,
+        )
+        .await
+    }
+
+    pub async fn safe_transfer_from(
+        &self,
+        by: &EthereumSigner,
+        from: Address,
+        to: Address,
+        id: U256,
+        amount: U256,
+        data: Vec<u8>,
+    ) -> Result<H256> {
+        self.send_call(by, "safeTransferFrom", (from, to, id, amount, data))
+            .await
+    }
+    /// Transfers `amounts[i]` of token `ids[i]` in one transaction
+    pub async fn safe_batch_transfer_from(
+        &self,
+        by: &EthereumSigner,
+        from: Address,
+        to: Address,
+        ids: Vec<U256>,
+        amounts: Vec<U256>,
+        data: Vec<u8>,
+    ) -> Result<H256> {
+        ensure!(
+            ids.len() == amounts.len(),
+            "{} ids for {} amounts",
+            ids.len(),
+            amounts.len()
+        );
+        self.send_call(by, "safeBatchTransferFrom", (from, to, ids, amounts, data))
+            .await
+    }
+    pub async fn set_approval_for_all(
+        &self,
+        by: &EthereumSigner,
+        operator: Address,
+        approved: bool,
+    ) -> Result<H256> {
+        self.send_call(by, "setApprovalForAll", (operator, approved))
+            .await
+    }
+}
+
+/// Replaces `{id}` with the id as 64 lowercase hex digits, as ERC-1155 specifies for `uri`
+pub fn substitute_id(uri: &str, id: U256) -> String {
+    let mut bytes = [0u8; 32];
+    id.to_big_endian(&mut bytes);
+    uri.replace("{id}", &hex::encode(bytes))
+}
+
+impl Debug for Erc1155Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ERC1155Token")
+            .field("net", &self.net)
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::encode_call;
+
+    #[test]
+    fn test_substitute_id() {
+        assert_eq!(
+            substitute_id("https://token-cdn-domain/{id}.json", 314592.into()),
+            "https://token-cdn-domain/000000000000000000000000000000000000000000000000000000000004cce0.json"
+        );
+        assert_eq!(
+            substitute_id("ipfs://QmHash/1.json", 1.into()),
+            "ipfs://QmHash/1.json"
+        );
+    }
+
+    #[test]
+    fn test_encode_transfers() -> Result<()> {
+        let token = Erc1155Token::new(EthereumNet::Local, Address::from_low_u64_be(1))?;
+        let from = Address::from_low_u64_be(2);
+        let to = Address::from_low_u64_be(3);
+        let single = encode_call(
+            &token.contract,
+            "safeTransferFrom",
+            (from, to, U256::from(1), U256::from(5), Vec::<u8>::new()),
+        )?;
+        assert_eq!(hex::encode(&single[..4]), "f242432a");
+        let batch = encode_call(
+            &token.contract,
+            "safeBatchTransferFrom",
+            (
+                from,
+                to,
+                vec![U256::from(1), U256::from(2)],
+                vec![U256::from(5), U256::from(6)],
+                Vec::<u8>::new(),
+            ),
+        )?;
+        assert_eq!(hex::encode(&batch[..4]), "2eb2c2d6");
+        Ok(())
+    }
+}
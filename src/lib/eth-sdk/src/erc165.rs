@@ -0,0 +1,164 @@
+//! ERC-165 interface detection, see <https://eips.ethereum.org/EIPS/eip-165>
+use crate::block::{self, BlockRef};
+use crate::dry_run::revert_reason;
+use crate::utils::function_selector;
+use eyre::*;
+use web3::types::{Address, CallRequest};
+use web3::{Transport, Web3};
+
+pub const ERC165: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+pub const ERC721: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+pub const ERC721_METADATA: [u8; 4] = [0x5b, 0x5e, 0x13, 0x9f];
+pub const ERC1155: [u8; 4] = [0xd9, 0xb6, 0x7a, 0x26];
+pub const ERC1155_METADATA_URI: [u8; 4] = [0x0e, 0x89, 0x34, 0x1c];
+const INVALID: [u8; 4] = [0xff; 4];
+
+/// XOR of the selectors of an interface's functions
+pub fn interface_id(signatures: &[&str]) -> [u8; 4] {
+    signatures.iter().fold([0; 4], |mut id, signature| {
+        for (x, y) in id.iter_mut().zip(function_selector(signature)) {
+            *x ^= y;
+        }
+        id
+    })
+}
+
+/// `None` if the call reverted or did not return a bool
+async fn call_supports_interface<T: Transport>(
+    web3: &Web3<T>,
+    address: Address,
+    interface_id: [u8; 4],
+    block: Option<BlockRef>,
+) -> Result<Option<bool>> {
+    let mut data = function_selector("supportsInterface(bytes4)").to_vec();
+    data.extend_from_slice(&interface_id);
+    data.resize(4 + 32, 0);
+    let call = CallRequest {
+        to: Some(address),
+        // the limit EIP-165 sets for supportsInterface
+        gas: Some(30_000.into()),
+        data: Some(data.into()),
+        ..Default::default()
+    };
+    let output = match block::call(web3, call, block).await {
+        Ok(output) => output.0,
+        Err(err) if revert_reason(&err).is_some() => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if output.len() != 32 || output[..31].iter().any(|&x| x != 0) {
+        return Ok(None);
+    }
+    match output[31] {
+        0 => Ok(Some(false)),
+        1 => Ok(Some(true)),
+        _ => Ok(None),
+    }
+}
+
+/// Whether the contract at `address` implements ERC-165 and reports `interface_id`.
+/// Contracts without ERC-165 report nothing
+pub async fn supports_interface<T: Transport>(
+    web3: &Web3<T>,
+    address: Address,
+    interface_id: [u8; 4],
+    block: Option<BlockRef>,
+) -> Result<bool> {
+    if call_supports_interface(web3, address, ERC165, block).await? != Some(true)
+        || call_supports_interface(web3, address, INVALID, block).await? != Some(false)
+    {
+        return Ok(false);
+    }
+    Ok(call_supports_interface(web3, address, interface_id, block).await? == Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use serde_json::json;
+    use web3::ethabi::Token;
+
+    #[test]
+    fn test_interface_ids() {
+        assert_eq!(interface_id(&["supportsInterface(bytes4)"]), ERC165);
+        assert_eq!(
+            interface_id(&[
+                "balanceOf(address)",
+                "ownerOf(uint256)",
+                "safeTransferFrom(address,address,uint256,bytes)",
+                "safeTransferFrom(address,address,uint256)",
+                "transferFrom(address,address,uint256)",
+                "approve(address,uint256)",
+                "setApprovalForAll(address,bool)",
+                "getApproved(uint256)",
+                "isApprovedForAll(address,address)",
+            ]),
+            ERC721
+        );
+        assert_eq!(
+            interface_id(&["name()", "symbol()", "tokenURI(uint256)"]),
+            ERC721_METADATA
+        );
+        assert_eq!(
+            interface_id(&[
+                "safeTransferFrom(address,address,uint256,uint256,bytes)",
+                "safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)",
+                "balanceOf(address,uint256)",
+                "balanceOfBatch(address[],uint256[])",
+                "setApprovalForAll(address,bool)",
+                "isApprovedForAll(address,address)",
+            ]),
+            ERC1155
+        );
+        assert_eq!(interface_id(&["uri(uint256)"]), ERC1155_METADATA_URI);
+    }
+
+    #[tokio::test]
+    async fn test_supports_interface() -> Result<()> {
+        let mock = MockTransport::new();
+        let web3 = mock.web3();
+        let address = Address::repeat_byte(1);
+        let selector = function_selector("supportsInterface(bytes4)");
+        let reports = |mock: &MockTransport, answers: &[bool]| {
+            for &answer in answers {
+                mock.expect_call(selector, &[Token::Bool(answer)]);
+            }
+        };
+
+        reports(&mock, &[true, false, true]);
+        assert!(supports_interface(&web3, address, ERC721, None).await?);
+        let asked: Vec<String> = mock
+            .requests()
+            .iter()
+            .map(|(_, params)| params[0]["data"].as_str().unwrap()[10..18].to_owned())
+            .collect();
+        assert_eq!(asked, ["01ffc9a7", "ffffffff", "80ac58cd"]);
+
+        reports(&mock, &[true, false, false]);
+        assert!(!supports_interface(&web3, address, ERC1155, None).await?);
+        // answers true to everything, so it does not implement ERC-165 properly
+        reports(&mock, &[true, true]);
+        assert!(!supports_interface(&web3, address, ERC721, None).await?);
+        // no code at the address
+        mock.expect("eth_call", json!("0x"));
+        assert!(!supports_interface(&web3, address, ERC721, None).await?);
+        // no supportsInterface at all
+        mock.expect_revert("eth_call", selector, "");
+        assert!(!supports_interface(&web3, address, ERC721, None).await?);
+
+        // errors other than reverts are not taken for a missing interface
+        mock.expect_error(
+            "eth_call",
+            web3::Error::Rpc(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(-32000),
+                message: "header not found".to_owned(),
+                data: None,
+            }),
+        );
+        assert!(supports_interface(&web3, address, ERC721, None)
+            .await
+            .is_err());
+        mock.assert_done();
+        Ok(())
+    }
+}
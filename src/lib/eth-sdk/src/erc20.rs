@@ -1,5 +1,11 @@
+use crate::amount::Amount;
+use crate::block::{self, BlockRef};
+use crate::dry_run::DryRun;
 use crate::signer::EthereumSigner;
-use crate::utils::{eth_public_exponent_to_address, wait_for_confirmations_simple, wei_to_eth};
+use crate::utils::{
+    eth_public_exponent_to_address, function_selector, simulate_call,
+    wait_for_confirmations_simple, wait_for_success_with,
+};
 use crate::EthereumNet;
 use crypto::Signer;
 use eyre::*;
@@ -9,65 +15,471 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use token::CryptoToken;
+use tokio::sync::OnceCell;
 use web3::api::Web3;
-use web3::contract::{Contract, Options};
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::ethabi::{decode, encode, ParamType, Token};
+use web3::signing::{keccak256, Key};
 use web3::transports::http::Http;
-use web3::types::{Address, H256, U256};
+use web3::types::{Address, CallRequest, TransactionReceipt, H256, U256};
 
-const ERC20_ABI: &'static str = include_str!("erc20.abi.json");
+const ERC20_ABI: &str = include_str!("erc20.abi.json");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Erc20Metadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
 
 pub struct Erc20Token {
     client: Web3<Http>,
     net: EthereumNet,
     address: Address,
     contract: Contract<Http>,
+    metadata: OnceCell<Erc20Metadata>,
+    strategy_pool: bool,
+    poll_interval: Duration,
+    max_retries: usize,
 }
 
 impl Erc20Token {
     pub fn new(net: EthereumNet, address: Address) -> Result<Self> {
-        // I don't know whose token are these. Copilot gave me these
-        let url = match net {
-            EthereumNet::Mainnet => "https://mainnet.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Ropsten => "https://ropsten.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Rinkeby => "https://rinkeby.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Goerli => "https://goerli.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Kovan => "https://kovan.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Local => "http://localhost:8545",
-        };
-        let transport = Http::new(url).unwrap();
-        let client = Web3::new(transport);
-        let contract = Contract::from_json(client.eth(), address, ERC20_ABI.as_bytes()).unwrap();
+        Self::new_with_client(net, net.client()?, address)
+    }
+    /// Through `client` rather than the default RPC endpoint of `net`
+    pub fn new_with_client(net: EthereumNet, client: Web3<Http>, address: Address) -> Result<Self> {
+        let contract = Contract::from_json(client.eth(), address, ERC20_ABI.as_bytes())?;
         Ok(Erc20Token {
             client,
             net,
             address,
             contract,
+            metadata: OnceCell::new(),
+            strategy_pool: false,
+            poll_interval: Duration::from_secs(3),
+            max_retries: 10,
         })
     }
+    /// Marks this as the share token of a StrategyPool, reported as `POOL@<net>`
+    pub fn as_strategy_pool(self) -> Self {
+        Self {
+            strategy_pool: true,
+            ..self
+        }
+    }
+    /// Skips querying the contract for metadata that is already known
+    pub fn with_metadata(self, metadata: Erc20Metadata) -> Self {
+        Self {
+            metadata: OnceCell::new_with(Some(metadata)),
+            ..self
+        }
+    }
+    /// How often the receipts this waits for are polled, 3s by default
+    pub fn poll_interval(self, poll_interval: Duration) -> Self {
+        Self {
+            poll_interval,
+            ..self
+        }
+    }
+    /// How many times the receipts this waits for are polled, 10 by default
+    pub fn max_retries(self, max_retries: usize) -> Self {
+        Self {
+            max_retries,
+            ..self
+        }
+    }
+    pub fn address(&self) -> Address {
+        self.address
+    }
 
     pub fn try_from_str(s: &str, contract_address: &str) -> Result<Option<Self>> {
-        let address = || {
-            Address::from_str(contract_address)
-                .with_context(|| format!("address {}", contract_address))
+        let net = match s.strip_prefix("ERC20@").map(EthereumNet::from_str) {
+            Some(Ok(net)) => net,
+            _ => return Ok(None),
         };
-        let x = match s {
-            "ERC20@mainnet" => Some(Erc20Token::new(EthereumNet::Mainnet, address()?)?),
-            "ERC20@ropsten" => Some(Erc20Token::new(EthereumNet::Ropsten, address()?)?),
-            "ERC20@rinkeby" => Some(Erc20Token::new(EthereumNet::Rinkeby, address()?)?),
-            "ERC20@goerli" => Some(Erc20Token::new(EthereumNet::Goerli, address()?)?),
-            "ERC20@kovan" => Some(Erc20Token::new(EthereumNet::Kovan, address()?)?),
-            "ERC20@local" => Some(Erc20Token::new(EthereumNet::Local, address()?)?),
-            _ => None,
+        let address = Address::from_str(contract_address)
+            .with_context(|| format!("address {}", contract_address))?;
+        Ok(Some(Erc20Token::new(net, address)?))
+    }
+}
+
+impl Erc20Token {
+    async fn call_raw(&self, signature: &str) -> Result<Vec<u8>> {
+        let request = CallRequest {
+            to: Some(self.address),
+            data: Some(function_selector(signature).to_vec().into()),
+            ..Default::default()
         };
-        Ok(x)
+        let result = self.client.eth().call(request, None).await?;
+        Ok(result.0)
+    }
+    /// `name`, `symbol` and `decimals`, queried once and cached
+    pub async fn metadata(&self) -> Result<&Erc20Metadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let decimals = self.call_raw("decimals()").await?;
+                ensure!(
+                    decimals.len() == 32,
+                    "{:?} decimals() returned 0x{}",
+                    self.address,
+                    hex::encode(&decimals)
+                );
+                let decimals = U256::from_big_endian(&decimals);
+                ensure!(decimals <= U256::from(u8::MAX), "{} decimals", decimals);
+                Ok(Erc20Metadata {
+                    name: decode_string_or_bytes32(&self.call_raw("name()").await?)
+                        .context("name()")?,
+                    symbol: decode_string_or_bytes32(&self.call_raw("symbol()").await?)
+                        .context("symbol()")?,
+                    decimals: decimals.as_u32() as u8,
+                })
+            })
+            .await
+    }
+    pub async fn decimals(&self) -> Result<u8> {
+        Ok(self.metadata().await?.decimals)
+    }
+    /// For the synchronous `CryptoToken` conversions, which need the metadata to be known
+    /// already, from `with_metadata` or an earlier `metadata().await`
+    fn decimals_sync(&self) -> Result<u8> {
+        match self.metadata.get() {
+            Some(metadata) => Ok(metadata.decimals),
+            None => bail!(
+                "decimals of {:?} are unknown, query metadata() first",
+                self.address
+            ),
+        }
     }
 }
 
+/// A signed EIP-2612 approval, which anyone can submit with `Erc20Token::permit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permit {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub deadline: U256,
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+/// EIP-712 digest of an EIP-2612 `Permit` message
+pub fn permit_digest(
+    domain_separator: H256,
+    owner: Address,
+    spender: Address,
+    value: U256,
+    nonce: U256,
+    deadline: U256,
+) -> H256 {
+    let type_hash = keccak256(
+        b"Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)",
+    );
+    let struct_hash = keccak256(&encode(&[
+        Token::FixedBytes(type_hash.to_vec()),
+        Token::Address(owner),
+        Token::Address(spender),
+        Token::Uint(value),
+        Token::Uint(nonce),
+        Token::Uint(deadline),
+    ]));
+    let mut message = vec![0x19, 0x01];
+    message.extend_from_slice(domain_separator.as_bytes());
+    message.extend_from_slice(&struct_hash);
+    keccak256(&message).into()
+}
+
+impl Erc20Token {
+    pub async fn total_supply(&self, block: Option<BlockRef>) -> Result<U256> {
+        block::query(&self.client, &self.contract, "totalSupply", (), block).await
+    }
+    pub async fn allowance(
+        &self,
+        owner: Address,
+        spender: Address,
+        block: Option<BlockRef>,
+    ) -> Result<U256> {
+        block::query(
+            &self.client,
+            &self.contract,
+            "allowance",
+            (owner, spender),
+            block,
+        )
+        .await
+    }
+    async fn simulate_call(
+        &self,
+        from: Address,
+        func: &str,
+        params: impl Tokenize,
+    ) -> Result<Vec<u8>> {
+        simulate_call(&self.client, &self.contract, from, func, params).await
+    }
+    async fn send_call(
+        &self,
+        by: &EthereumSigner,
+        func: &str,
+        params: impl Tokenize,
+    ) -> Result<H256> {
+        by.send_call(&self.client, &self.contract, func, params, U256::zero())
+            .await
+    }
+    async fn wait_for_success(&self, hash: H256) -> Result<TransactionReceipt> {
+        wait_for_success_with(
+            &self.client.eth(),
+            hash,
+            self.poll_interval,
+            self.max_retries,
+        )
+        .await
+    }
+    pub async fn balance_of(&self, owner: Address, block: Option<BlockRef>) -> Result<U256> {
+        block::query(&self.client, &self.contract, "balanceOf", owner, block).await
+    }
+    /// Checks the balance and simulates the transfer before sending it
+    async fn send_transfer(&self, by: &EthereumSigner, to: Address, amount: U256) -> Result<H256> {
+        let balance = self.balance_of(by.address, None).await?;
+        ensure!(
+            balance >= amount,
+            "{:?} holds {} of {:?}, less than {}",
+            by.address,
+            balance,
+            self.address,
+            amount
+        );
+        let output = self
+            .simulate_call(by.address, "transfer", (to, amount))
+            .await?;
+        check_bool_return(&output).context("transfer")?;
+        self.send_call(by, "transfer", (to, amount)).await
+    }
+    /// What `CryptoToken::transfer` would do, without sending anything. An insufficient
+    /// balance shows as a revert rather than an error
+    pub async fn dry_run_transfer(
+        &self,
+        by: Arc<dyn Signer>,
+        from: &str,
+        to: &str,
+        amount: &str,
+    ) -> Result<DryRun> {
+        let amount = U256::from_str_radix(amount, 10)?;
+        let by = EthereumSigner::new(by)?;
+        ensure!(
+            by.address == Address::from_str(from)?,
+            "from address {} does not match signer {:?}",
+            from,
+            by.address
+        );
+        let to = Address::from_str(to)?;
+        by.dry_run_call(
+            &self.client,
+            &self.contract,
+            "transfer",
+            (to, amount),
+            U256::zero(),
+        )
+        .await
+    }
+    /// Transfers `amount` and waits for the receipt, failing unless its `Transfer` events show
+    /// `to` received exactly `amount`, which catches fee-on-transfer tokens
+    pub async fn transfer_checked(
+        &self,
+        by: &EthereumSigner,
+        to: Address,
+        amount: U256,
+    ) -> Result<TransactionReceipt> {
+        let hash = self.send_transfer(by, to, amount).await?;
+        let receipt = self.wait_for_success(hash).await?;
+        let moved = transferred_amount(&receipt, self.address, by.address, to);
+        ensure!(
+            moved == amount,
+            "transaction {:?} moved {} instead of {}",
+            hash,
+            moved,
+            amount
+        );
+        Ok(receipt)
+    }
+    pub async fn approve(
+        &self,
+        by: &EthereumSigner,
+        spender: Address,
+        amount: U256,
+    ) -> Result<H256> {
+        self.send_call(by, "approve", (spender, amount)).await
+    }
+    /// What `approve` would do, without sending anything
+    pub async fn dry_run_approve(
+        &self,
+        by: &EthereumSigner,
+        spender: Address,
+        amount: U256,
+    ) -> Result<DryRun> {
+        by.dry_run_call(
+            &self.client,
+            &self.contract,
+            "approve",
+            (spender, amount),
+            U256::zero(),
+        )
+        .await
+    }
+    /// Tokens like USDT revert when changing a non zero allowance to another non zero value,
+    /// so the allowance is reset to zero first, waiting for that to be mined as configured by
+    /// `poll_interval` and `max_retries`. If it is not, the error names the pending reset, and
+    /// calling again once it is mined goes on with the approval
+    pub async fn safe_approve(
+        &self,
+        by: &EthereumSigner,
+        spender: Address,
+        amount: U256,
+    ) -> Result<H256> {
+        let current = self.allowance(by.address, spender, None).await?;
+        if !current.is_zero() && !amount.is_zero() {
+            let hash = self.approve(by, spender, U256::zero()).await?;
+            self.wait_for_success(hash)
+                .await
+                .with_context(|| format!("resetting the allowance in {:?}", hash))?;
+        }
+        self.approve(by, spender, amount).await
+    }
+    /// Makes sure `spender` may move at least `amount` of the owner's tokens, e.g. before a
+    /// deposit. Returns the approval transaction once mined, if one was needed
+    pub async fn ensure_allowance(
+        &self,
+        owner: &EthereumSigner,
+        spender: Address,
+        amount: U256,
+    ) -> Result<Option<H256>> {
+        if self.allowance(owner.address, spender, None).await? >= amount {
+            return Ok(None);
+        }
+        let hash = self.safe_approve(owner, spender, amount).await?;
+        self.wait_for_success(hash).await?;
+        Ok(Some(hash))
+    }
+    pub async fn transfer_from(
+        &self,
+        by: &EthereumSigner,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<H256> {
+        self.send_call(by, "transferFrom", (from, to, amount)).await
+    }
+
+    pub async fn domain_separator(&self, block: Option<BlockRef>) -> Result<H256> {
+        block::query(&self.client, &self.contract, "DOMAIN_SEPARATOR", (), block).await
+    }
+    pub async fn nonces(&self, owner: Address, block: Option<BlockRef>) -> Result<U256> {
+        block::query(&self.client, &self.contract, "nonces", owner, block).await
+    }
+    /// Signs an EIP-2612 permit for `spender`, valid until the `deadline` timestamp
+    pub async fn sign_permit(
+        &self,
+        owner: &EthereumSigner,
+        spender: Address,
+        value: U256,
+        deadline: U256,
+    ) -> Result<Permit> {
+        let digest = permit_digest(
+            self.domain_separator(None).await?,
+            owner.address,
+            spender,
+            value,
+            self.nonces(owner.address, None).await?,
+            deadline,
+        );
+        let signature = owner
+            .sign_message(digest.as_bytes())
+            .map_err(|e| eyre!("signing permit: {:?}", e))?;
+        Ok(Permit {
+            owner: owner.address,
+            spender,
+            value,
+            deadline,
+            v: signature.v as u8 + 27,
+            r: signature.r,
+            s: signature.s,
+        })
+    }
+    pub async fn permit(&self, by: &EthereumSigner, permit: &Permit) -> Result<H256> {
+        let params = (
+            permit.owner,
+            permit.spender,
+            permit.value,
+            permit.deadline,
+            permit.v,
+            permit.r,
+            permit.s,
+        );
+        self.send_call(by, "permit", params).await
+    }
+}
+
+/// Tokens such as USDT return nothing from `transfer`, and some return `false` instead of
+/// reverting
+fn check_bool_return(output: &[u8]) -> Result<()> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    match decode(&[ParamType::Bool], output) {
+        Ok(tokens) if tokens == [Token::Bool(true)] => Ok(()),
+        Ok(_) => bail!("returned false"),
+        Err(_) => bail!("returned 0x{} instead of a bool", hex::encode(output)),
+    }
+}
+
+/// Sum of the `Transfer(from, to, value)` events `token` emitted in `receipt`
+fn transferred_amount(
+    receipt: &TransactionReceipt,
+    token: Address,
+    from: Address,
+    to: Address,
+) -> U256 {
+    let topic = H256::from(keccak256(b"Transfer(address,address,uint256)"));
+    receipt
+        .logs
+        .iter()
+        .filter(|log| {
+            log.address == token
+                && log.topics.len() == 3
+                && log.topics[0] == topic
+                && log.topics[1] == H256::from(from)
+                && log.topics[2] == H256::from(to)
+                && log.data.0.len() == 32
+        })
+        .fold(U256::zero(), |sum, log| {
+            sum.saturating_add(U256::from_big_endian(&log.data.0))
+        })
+}
+
+/// Some tokens such as MKR predate the standard and return `bytes32` instead of `string`
+fn decode_string_or_bytes32(data: &[u8]) -> Result<String> {
+    if let Ok(tokens) = decode(&[ParamType::String], data) {
+        if let [Token::String(value)] = tokens.as_slice() {
+            return Ok(value.clone());
+        }
+    }
+    ensure!(
+        data.len() == 32,
+        "neither string nor bytes32: 0x{}",
+        hex::encode(data)
+    );
+    let end = data.iter().position(|&x| x == 0).unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
 impl Debug for Erc20Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ERC20Token")
             .field("net", &self.net)
             .field("address", &self.address)
+            .field("strategy_pool", &self.strategy_pool)
             .finish()
     }
 }
@@ -79,24 +491,16 @@ impl CryptoToken for Erc20Token {
     }
 
     fn get_network_type(&self) -> String {
-        match self.net {
-            EthereumNet::Mainnet => "ERC20@mainnet",
-            EthereumNet::Ropsten => "ERC20@ropsten",
-            EthereumNet::Rinkeby => "ERC20@rinkeby",
-            EthereumNet::Goerli => "ERC20@goerli",
-            EthereumNet::Kovan => "ERC20@kovan",
-            EthereumNet::Local => "ERC20@local",
-        }
-        .to_string()
+        let kind = if self.strategy_pool { "POOL" } else { "ERC20" };
+        format!("{}@{}", kind, self.net.name())
     }
     fn convert_display_unit_to_internal_unit(&self, amount: &str) -> Result<String> {
-        let amount = amount.parse::<f64>()?;
-        let amount = (amount * 1e18).to_string();
-        Ok(amount)
+        let amount = Amount::parse(amount, self.decimals_sync()?)?;
+        Ok(amount.value().to_string())
     }
     fn convert_internal_unit_to_display_unit(&self, amount: &str) -> Result<String> {
         let amount = U256::from_str_radix(amount, 10)?;
-        Ok(wei_to_eth(amount).to_string())
+        Ok(Amount::new(amount, self.decimals_sync()?)?.to_string())
     }
     fn public_exponent_to_address(
         &self,
@@ -110,32 +514,14 @@ impl CryptoToken for Erc20Token {
     }
 
     fn get_address_explorer_url(&self, address: &str) -> String {
-        match self.net {
-            EthereumNet::Mainnet => format!("https://etherscan.io/address/{}", address),
-            EthereumNet::Ropsten => format!("https://ropsten.etherscan.io/address/{}", address),
-            EthereumNet::Rinkeby => format!("https://rinkeby.etherscan.io/address/{}", address),
-            EthereumNet::Goerli => format!("https://goerli.etherscan.io/address/{}", address),
-            EthereumNet::Kovan => format!("https://kovan.etherscan.io/address/{}", address),
-            EthereumNet::Local => format!("http://localhost:3000/address/{}", address),
-        }
+        format!("{}/address/{}", self.net.explorer_url(), address)
     }
 
     fn get_transaction_explorer_url(&self, address: &str) -> String {
-        match self.net {
-            EthereumNet::Mainnet => format!("https://etherscan.io/tx/{}", address),
-            EthereumNet::Ropsten => format!("https://ropsten.etherscan.io/tx/{}", address),
-            EthereumNet::Rinkeby => format!("https://rinkeby.etherscan.io/tx/{}", address),
-            EthereumNet::Goerli => format!("https://goerli.etherscan.io/tx/{}", address),
-            EthereumNet::Kovan => format!("https://kovan.etherscan.io/tx/{}", address),
-            EthereumNet::Local => format!("http://localhost:3000/tx/{}", address),
-        }
+        format!("{}/tx/{}", self.net.explorer_url(), address)
     }
     async fn get_balance(&self, addr: &str) -> Result<String> {
-        let addr = Address::from_str(addr)?;
-        let balance: U256 = self
-            .contract
-            .query("balanceOf", addr, None, Options::default(), None)
-            .await?;
+        let balance = self.balance_of(Address::from_str(addr)?, None).await?;
         Ok(balance.to_string())
     }
     async fn request_airdrop(&self, _addr: &str, _amount: &str) -> Result<String> {
@@ -151,28 +537,14 @@ impl CryptoToken for Erc20Token {
     ) -> Result<String> {
         let amount = U256::from_str_radix(amount, 10)?;
         let by = EthereumSigner::new(by)?;
-        if by.address == Address::from_str(from)? {
-            bail!("from address is not match")
-        }
+        ensure!(
+            by.address == Address::from_str(from)?,
+            "from address {} does not match signer {:?}",
+            from,
+            by.address
+        );
         let to = Address::from_str(to)?;
-        let nonce = self
-            .client
-            .eth()
-            .transaction_count(by.address, None)
-            .await?;
-        let gas_price = self.client.eth().gas_price().await?;
-        let gas_limit = 21000;
-        let options = Options {
-            nonce: Some(nonce),
-            gas_price: Some(gas_price),
-            gas: Some(gas_limit.into()),
-            value: amount.into(),
-            ..Default::default()
-        };
-        let tx_hash = self
-            .contract
-            .signed_call("transfer", (to, amount), options, by)
-            .await?;
+        let tx_hash = self.send_transfer(&by, to, amount).await?;
 
         Ok(format!("{:?}", tx_hash))
     }
@@ -183,7 +555,7 @@ impl CryptoToken for Erc20Token {
         let hash = H256::from_str(hash)?;
         let eth = self.client.eth();
 
-        wait_for_confirmations_simple(&eth, hash, Duration::from_secs_f64(3.0), 10).await?;
+        wait_for_confirmations_simple(&eth, hash, self.poll_interval, self.max_retries).await?;
         Ok(())
     }
     async fn create_account(
@@ -208,28 +580,145 @@ impl CryptoToken for Erc20Token {
         let amount = U256::from_str_radix(amount, 10)?;
         let fee_payer = EthereumSigner::new(fee_payer)?;
         let minter = EthereumSigner::new(minter)?;
-        if fee_payer.address == minter.address {
-            bail!("minter address is not match")
-        }
+        // the sender pays the gas on ethereum, there is no separate fee payer
+        ensure!(
+            fee_payer.address == minter.address,
+            "fee payer {:?} must be the minter {:?}",
+            fee_payer.address,
+            minter.address
+        );
         let account = Address::from_str(account)?;
-        let nonce = self
-            .client
-            .eth()
-            .transaction_count(fee_payer.address, None)
+        self.simulate_call(minter.address, "mintTo", (account, amount))
             .await?;
-        let gas_price = self.client.eth().gas_price().await?;
-        let gas_limit = 21000;
-        let options = Options {
-            nonce: Some(nonce),
-            gas_price: Some(gas_price),
-            gas: Some(gas_limit.into()),
+        let tx_hash = self.send_call(&minter, "mintTo", (account, amount)).await?;
+
+        Ok(format!("{:?}", tx_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::Log;
+
+    #[test]
+    fn test_decode_string_or_bytes32() -> Result<()> {
+        let data = encode(&[Token::String("USD Coin".to_owned())]);
+        assert_eq!(decode_string_or_bytes32(&data)?, "USD Coin");
+        // MKR's symbol()
+        let mut data = b"MKR".to_vec();
+        data.resize(32, 0);
+        assert_eq!(decode_string_or_bytes32(&data)?, "MKR");
+        assert!(decode_string_or_bytes32(&[1, 2, 3]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_permit_digest_recovers_owner() -> Result<()> {
+        let owner = crate::utils::test_signer(0)?;
+        let spender = Address::from_low_u64_be(2);
+        let digest = permit_digest(
+            H256::repeat_byte(7),
+            owner.address,
+            spender,
+            100.into(),
+            0.into(),
+            U256::MAX,
+        );
+        let next = permit_digest(
+            H256::repeat_byte(7),
+            owner.address,
+            spender,
+            100.into(),
+            1.into(),
+            U256::MAX,
+        );
+        assert_ne!(digest, next);
+        let signature = owner.sign_message(digest.as_bytes())?;
+        let mut raw = signature.r.as_bytes().to_vec();
+        raw.extend_from_slice(signature.s.as_bytes());
+        let recovered = web3::signing::recover(digest.as_bytes(), &raw, signature.v as i32)?;
+        assert_eq!(recovered, owner.address);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_bool_return() {
+        assert!(check_bool_return(&[]).is_ok());
+        assert!(check_bool_return(&encode(&[Token::Bool(true)])).is_ok());
+        assert!(check_bool_return(&encode(&[Token::Bool(false)])).is_err());
+        assert!(check_bool_return(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_transferred_amount() {
+        let token = Address::from_low_u64_be(1);
+        let from = Address::from_low_u64_be(2);
+        let to = Address::from_low_u64_be(3);
+        let transfer = |address: Address, from: Address, to: Address, value: u64| Log {
+            address,
+            topics: vec![
+                H256::from(keccak256(b"Transfer(address,address,uint256)")),
+                from.into(),
+                to.into(),
+            ],
+            data: encode(&[Token::Uint(value.into())]).into(),
+            block_hash: None,
+            block_number: None,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        };
+        let receipt = TransactionReceipt {
+            logs: vec![
+                // a fee-on-transfer token burns part of the amount
+                transfer(token, from, to, 98),
+                transfer(token, from, Address::zero(), 2),
+                transfer(Address::from_low_u64_be(9), from, to, 100),
+            ],
             ..Default::default()
         };
-        let tx_hash = self
-            .contract
-            .signed_call("mintTo", (account, amount), options, minter)
-            .await?;
+        assert_eq!(transferred_amount(&receipt, token, from, to), 98.into());
+        assert_eq!(transferred_amount(&receipt, token, to, from), 0.into());
+    }
 
-        Ok(format!("{:?}", tx_hash))
+    #[tokio::test]
+    async fn test_conversion_needs_metadata() -> Result<()> {
+        let token = Erc20Token::new(EthereumNet::Local, Address::from_low_u64_be(1))?;
+        let err = token
+            .convert_display_unit_to_internal_unit("1")
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("query metadata() first"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conversion_uses_decimals() -> Result<()> {
+        let usdc = Erc20Token::new(EthereumNet::Local, Address::from_low_u64_be(1))?.with_metadata(
+            Erc20Metadata {
+                name: "USD Coin".to_owned(),
+                symbol: "USDC".to_owned(),
+                decimals: 6,
+            },
+        );
+        assert_eq!(
+            usdc.convert_display_unit_to_internal_unit("1.5")?,
+            "1500000"
+        );
+        assert_eq!(
+            usdc.convert_internal_unit_to_display_unit("1234567")?,
+            "1.234567"
+        );
+        assert!(usdc
+            .convert_display_unit_to_internal_unit("0.0000001")
+            .is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,226 @@
+use crate::block::{self, BlockRef};
+use crate::erc165::{self, ERC721};
+use crate::signer::EthereumSigner;
+use crate::utils::wait_for_success;
+use crate::EthereumNet;
+use eyre::*;
+use std::fmt::{Debug, Formatter};
+use web3::api::Web3;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::transports::http::Http;
+use web3::types::{Address, H256, U256};
+use web3::Transport;
+
+const ERC721_ABI: &str = include_str!("erc721.abi.json");
+
+/// An ERC-721 collection. Writes are signed like `Erc20Token`'s, through
+/// `EthereumSigner::sign_transaction`
+pub struct Erc721Token<T: Transport = Http> {
+    client: Web3<T>,
+    net: EthereumNet,
+    address: Address,
+    contract: Contract<T>,
+}
+
+impl Erc721Token {
+    pub fn new(net: EthereumNet, address: Address) -> Result<Self> {
+        Self::new_with_client(net, net.client()?, address)
+    }
+}
+
+impl<T: Transport> Erc721Token<T> {
+    /// Through `client` rather than the default RPC endpoint of `net`
+    pub fn new_with_client(net: EthereumNet, client: Web3<T>, address: Address) -> Result<Self> {
+        let contract = Contract::from_json(client.eth(), address, ERC721_ABI.as_bytes())?;
+        Ok(Self {
+            client,
+            net,
+            address,
+            contract,
+        })
+    }
+    pub fn net(&self) -> EthereumNet {
+        self.net
+    }
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    async fn send_call(
+        &self,
+        by: &EthereumSigner,
+        func: &str,
+        params: impl Tokenize,
+    ) -> Result<H256> {
+        by.send_call(&self.client, &self.contract, func, params, U256::zero())
+            .await
+    }
+
+    pub async fn supports_interface(
+        &self,
+        interface_id: [u8; 4],
+        block: Option<BlockRef>,
+    ) -> Result<bool> {
+        erc165::supports_interface(&self.client, self.address, interface_id, block).await
+    }
+    /// Whether the contract reports ERC-721 through ERC-165
+    pub async fn is_erc721(&self, block: Option<BlockRef>) -> Result<bool> {
+        self.supports_interface(ERC721, block).await
+    }
+    pub async fn name(&self, block: Option<BlockRef>) -> Result<String> {
+        block::query(&self.client, &self.contract, "name", (), block).await
+    }
+    pub async fn symbol(&self, block: Option<BlockRef>) -> Result<String> {
+        block::query(&self.client, &self.contract, "symbol", (), block).await
+    }
+    pub async fn token_uri(&self, token_id: U256, block: Option<BlockRef>) -> Result<String> {
+        block::query(&self.client, &self.contract, "tokenURI", token_id, block).await
+    }
+    /// Number of tokens `owner` holds
+    pub async fn balance_of(&self, owner: Address, block: Option<BlockRef>) -> Result<U256> {
+        block::query(&self.client, &self.contract, "balanceOf", owner, block).await
+    }
+    pub async fn owner_of(&self, token_id: U256, block: Option<BlockRef>) -> Result<Address> {
+        block::query(&self.client, &self.contract, "ownerOf", token_id, block).await
+    }
+    pub async fn get_approved(&self, token_id: U256, block: Option<BlockRef>) -> Result<Address> {
+        block::query(&self.client, &self.contract, "getApproved", token_id, block).await
+    }
+    pub async fn is_approved_for_all(
+        &self,
+        owner: Address,
+        operator: Address,
+        block: Option<BlockRef>,
+    ) -> Result<bool> {
+        block::query(
+            &self.client,
+            &self.contract,
+            "isApprovedForAll",
+            (owner, operator),
+            block,
+        )
+        .await
+    }
+
+    /// Reverts if `to` is a contract that does not accept ERC-721 tokens
+    pub async fn safe_transfer_from(
+        &self,
+        by: &EthereumSigner,
+        from: Address,
+        to: Address,
+        token_id: U256,
+        data: Vec<u8>,
+    ) -> Result<H256> {
+        self.send_call(by, "safeTransferFrom", (from, to, token_id, data))
+            .await
+    }
+    /// ERC-721 has no batch transfer, so this sends one `safeTransferFrom` per token, each
+    /// waited for before the next
+    pub async fn safe_batch_transfer_from(
+        &self,
+        by: &EthereumSigner,
+        from: Address,
+        to: Address,
+        token_ids: &[U256],
+    ) -> Result<Vec<H256>> {
+        let mut hashes = vec![];
+        for &token_id in token_ids {
+            let hash = self
+                .safe_transfer_from(by, from, to, token_id, vec![])
+                .await?;
+            wait_for_success(&self.client.eth(), hash)
+                .await
+                .with_context(|| format!("transferring token {}", token_id))?;
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+    pub async fn approve(&self, by: &EthereumSigner, to: Address, token_id: U256) -> Result<H256> {
+        self.send_call(by, "approve", (to, token_id)).await
+    }
+    pub async fn set_approval_for_all(
+        &self,
+        by: &EthereumSigner,
+        operator: Address,
+        approved: bool,
+    ) -> Result<H256> {
+        self.send_call(by, "setApprovalForAll", (operator, approved))
+            .await
+    }
+}
+
+impl<T: Transport> Debug for Erc721Token<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ERC721Token")
+            .field("net", &self.net)
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::utils::{function_selector, test_signer};
+    use web3::ethabi::Token;
+    use web3::types::TransactionReceipt;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transfers() -> Result<()> {
+        let mock = MockTransport::new();
+        let address = Address::repeat_byte(1);
+        let token = Erc721Token::new_with_client(EthereumNet::Local, mock.web3(), address)?;
+        let by = test_signer(0)?;
+        let to = Address::repeat_byte(2);
+        let transfer = token.contract.abi().function("safeTransferFrom")?;
+
+        token
+            .safe_transfer_from(&by, by.address, to, 7.into(), vec![1, 2])
+            .await?;
+        for _ in 0..2 {
+            mock.expect_receipt(TransactionReceipt {
+                status: Some(1.into()),
+                ..Default::default()
+            });
+        }
+        let hashes = token
+            .safe_batch_transfer_from(&by, by.address, to, &[8.into(), 9.into()])
+            .await?;
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(hashes, [sent[1].hash, sent[2].hash]);
+        for (tx, (id, data)) in sent.iter().zip([(7, vec![1, 2]), (8, vec![]), (9, vec![])]) {
+            assert_eq!(tx.to, Some(address));
+            assert_eq!(
+                tx.decode_input(transfer)?,
+                vec![
+                    Token::Address(by.address),
+                    Token::Address(to),
+                    Token::Uint(id.into()),
+                    Token::Bytes(data),
+                ]
+            );
+        }
+
+        // stops at the first token that does not go through
+        mock.expect_receipt(TransactionReceipt {
+            status: Some(0.into()),
+            ..Default::default()
+        });
+        let error = token
+            .safe_batch_transfer_from(&by, by.address, to, &[10.into(), 11.into()])
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("transferring token 10"));
+        assert_eq!(mock.sent().len(), 4);
+
+        let selector = function_selector("supportsInterface(bytes4)");
+        for answer in [true, false, true] {
+            mock.expect_call(selector, &[Token::Bool(answer)]);
+        }
+        assert!(token.is_erc721(None).await?);
+        mock.assert_done();
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-use crate::utils::{wait_for_confirmations_simple, wei_to_eth};
+use crate::block::BlockRef;
+use crate::dry_run::DryRun;
+use crate::utils::{eth_to_wei, wait_for_confirmations_simple, wei_to_eth};
 use crypto::Signer;
 use eyre::*;
 use signer::EthereumSigner;
@@ -12,10 +14,32 @@ use web3::transports::Http;
 use web3::types::{Address, TransactionParameters, TransactionRequest, H256, U256};
 use web3::Web3;
 
+pub mod amount;
+pub mod artifact;
+pub mod asset;
+pub mod block;
 pub mod contract;
+pub mod deployments;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod devnet;
+pub mod dry_run;
+pub mod erc1155;
+pub mod erc165;
 pub mod erc20;
+pub mod erc721;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod multicall;
+pub mod policy;
+pub mod portfolio;
+pub mod proxy;
+pub mod registry;
 pub mod signer;
+pub mod state_override;
+pub mod storage_layout;
 pub mod utils;
+pub mod verify;
+pub mod weth;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EthereumNet {
@@ -24,8 +48,89 @@ pub enum EthereumNet {
     Rinkeby,
     Goerli,
     Kovan,
+    Sepolia,
+    Base,
     Local,
 }
+impl EthereumNet {
+    pub const ALL: [EthereumNet; 8] = [
+        EthereumNet::Mainnet,
+        EthereumNet::Ropsten,
+        EthereumNet::Rinkeby,
+        EthereumNet::Goerli,
+        EthereumNet::Kovan,
+        EthereumNet::Sepolia,
+        EthereumNet::Base,
+        EthereumNet::Local,
+    ];
+    /// The name used after `@` in asset strings such as `ETH@mainnet`
+    pub fn name(&self) -> &'static str {
+        match self {
+            EthereumNet::Mainnet => "mainnet",
+            EthereumNet::Ropsten => "ropsten",
+            EthereumNet::Rinkeby => "rinkeby",
+            EthereumNet::Goerli => "goerli",
+            EthereumNet::Kovan => "kovan",
+            EthereumNet::Sepolia => "sepolia",
+            EthereumNet::Base => "base",
+            EthereumNet::Local => "local",
+        }
+    }
+    pub fn chain_id(&self) -> u64 {
+        match self {
+            EthereumNet::Mainnet => 1,
+            EthereumNet::Ropsten => 3,
+            EthereumNet::Rinkeby => 4,
+            EthereumNet::Goerli => 5,
+            EthereumNet::Kovan => 42,
+            EthereumNet::Sepolia => 11155111,
+            EthereumNet::Base => 8453,
+            EthereumNet::Local => 31337,
+        }
+    }
+    pub fn from_chain_id(chain_id: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.chain_id() == chain_id)
+    }
+    pub fn rpc_url(&self) -> &'static str {
+        // I don't know whose token are these. Copilot gave me these
+        match self {
+            EthereumNet::Mainnet => "https://mainnet.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Ropsten => "https://ropsten.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Rinkeby => "https://rinkeby.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Goerli => "https://goerli.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Kovan => "https://kovan.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Sepolia => "https://sepolia.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
+            EthereumNet::Base => "https://mainnet.base.org",
+            EthereumNet::Local => "http://localhost:8545",
+        }
+    }
+    pub fn explorer_url(&self) -> &'static str {
+        match self {
+            EthereumNet::Mainnet => "https://etherscan.io",
+            EthereumNet::Ropsten => "https://ropsten.etherscan.io",
+            EthereumNet::Rinkeby => "https://rinkeby.etherscan.io",
+            EthereumNet::Goerli => "https://goerli.etherscan.io",
+            EthereumNet::Kovan => "https://kovan.etherscan.io",
+            EthereumNet::Sepolia => "https://sepolia.etherscan.io",
+            EthereumNet::Base => "https://basescan.org",
+            EthereumNet::Local => "http://localhost:3000",
+        }
+    }
+    /// A new client for the RPC endpoint of this network. Its connections belong to the tokio
+    /// runtime it is first used on, to share one see [`asset::AssetFactory::client`]
+    pub fn client(&self) -> Result<Web3<Http>> {
+        Ok(Web3::new(Http::new(self.rpc_url())?))
+    }
+}
+impl FromStr for EthereumNet {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name() == s)
+            .with_context(|| format!("unknown network {}", s))
+    }
+}
 
 #[derive(Clone)]
 pub struct EthereumToken {
@@ -34,50 +139,68 @@ pub struct EthereumToken {
 }
 impl EthereumToken {
     pub fn new(net: EthereumNet) -> Result<Self> {
-        let url = match net {
-            EthereumNet::Mainnet => "https://mainnet.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Ropsten => "https://ropsten.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Rinkeby => "https://rinkeby.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Goerli => "https://goerli.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Kovan => "https://kovan.infura.io/v3/9aa3d95b3bc440fa88ea12eaa4456161",
-            EthereumNet::Local => "http://localhost:8545",
-        };
-        let transport = Http::new(url).unwrap();
-        let client = Web3::new(transport);
+        let client = net.client()?;
         Ok(EthereumToken { client, net })
     }
+    /// Through `client` rather than the default RPC endpoint of `net`
+    pub fn new_with_client(net: EthereumNet, client: Web3<Http>) -> Self {
+        EthereumToken { client, net }
+    }
     pub fn try_from_str(s: &str) -> Result<Option<Self>> {
-        let x = match s {
-            "ETH@mainnet" => Some(EthereumToken::new(EthereumNet::Mainnet)?),
-            "ETH@ropsten" => Some(EthereumToken::new(EthereumNet::Ropsten)?),
-            "ETH@rinkeby" => Some(EthereumToken::new(EthereumNet::Rinkeby)?),
-            "ETH@goerli" => Some(EthereumToken::new(EthereumNet::Goerli)?),
-            "ETH@kovan" => Some(EthereumToken::new(EthereumNet::Kovan)?),
-            "ETH@local" => Some(EthereumToken::new(EthereumNet::Local)?),
-            _ => None,
-        };
-        Ok(x)
+        match s.strip_prefix("ETH@").map(EthereumNet::from_str) {
+            Some(Ok(net)) => Ok(Some(EthereumToken::new(net)?)),
+            _ => Ok(None),
+        }
+    }
+    /// Balance in wei at `block`, `latest` if `None`
+    pub async fn balance_at(&self, address: Address, block: Option<BlockRef>) -> Result<U256> {
+        block::balance(&self.client, address, block).await
     }
     pub async fn get_accounts(&self) -> Result<Vec<Address>> {
         let accounts = self.client.eth().accounts().await?;
 
         Ok(accounts)
     }
-    pub async fn transfer_debug(&self, from: Address, to: Address, amount: f64) -> Result<String> {
-        let amount = (amount * 1e18) as u64;
+    /// Sends `amount` ETH from an account unlocked on the node
+    pub async fn transfer_debug(&self, from: Address, to: Address, amount: &str) -> Result<String> {
+        let amount = eth_to_wei(amount)?;
         let nonce = self.client.eth().transaction_count(from, None).await?;
         let gas_price = self.client.eth().gas_price().await?;
         let tx = TransactionRequest {
-            from: from,
+            from,
             nonce: Some(nonce),
             gas_price: Some(gas_price),
             to: Some(to),
-            value: Some(amount.into()),
+            value: Some(amount),
             ..Default::default()
         };
         let tx_hash = self.client.eth().send_transaction(tx).await?;
         Ok(format!("{:?}", tx_hash))
     }
+    /// What `CryptoToken::transfer` would do, without sending anything
+    pub async fn dry_run_transfer(
+        &self,
+        by: Arc<dyn Signer>,
+        from: &str,
+        to: &str,
+        amount: &str,
+    ) -> Result<DryRun> {
+        let amount = U256::from_str_radix(amount, 10)?;
+        let by = EthereumSigner::new(by)?;
+        ensure!(
+            by.address == Address::from_str(from)?,
+            "from address {} does not match signer {:?}",
+            from,
+            by.address
+        );
+        let tx = TransactionParameters {
+            gas: 21000.into(),
+            to: Some(Address::from_str(to)?),
+            value: amount,
+            ..Default::default()
+        };
+        by.dry_run(&self.client, tx, None, false).await
+    }
 }
 impl Debug for EthereumToken {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -94,20 +217,10 @@ impl CryptoToken for EthereumToken {
     }
 
     fn get_network_type(&self) -> String {
-        match self.net {
-            EthereumNet::Mainnet => "ETH@mainnet",
-            EthereumNet::Ropsten => "ETH@ropsten",
-            EthereumNet::Rinkeby => "ETH@rinkeby",
-            EthereumNet::Goerli => "ETH@goerli",
-            EthereumNet::Kovan => "ETH@kovan",
-            EthereumNet::Local => "ETH@local",
-        }
-        .to_string()
+        format!("ETH@{}", self.net.name())
     }
     fn convert_display_unit_to_internal_unit(&self, amount: &str) -> Result<String> {
-        let amount = amount.parse::<f64>()?;
-        let amount = (amount * 1e18).to_string();
-        Ok(amount)
+        Ok(eth_to_wei(amount)?.to_string())
     }
     fn convert_internal_unit_to_display_unit(&self, amount: &str) -> Result<String> {
         let amount = U256::from_str_radix(amount, 10)?;
@@ -125,29 +238,14 @@ impl CryptoToken for EthereumToken {
     }
 
     fn get_address_explorer_url(&self, address: &str) -> String {
-        match self.net {
-            EthereumNet::Mainnet => format!("https://etherscan.io/address/{}", address),
-            EthereumNet::Ropsten => format!("https://ropsten.etherscan.io/address/{}", address),
-            EthereumNet::Rinkeby => format!("https://rinkeby.etherscan.io/address/{}", address),
-            EthereumNet::Goerli => format!("https://goerli.etherscan.io/address/{}", address),
-            EthereumNet::Kovan => format!("https://kovan.etherscan.io/address/{}", address),
-            EthereumNet::Local => format!("http://localhost:3000/address/{}", address),
-        }
+        format!("{}/address/{}", self.net.explorer_url(), address)
     }
 
     fn get_transaction_explorer_url(&self, address: &str) -> String {
-        match self.net {
-            EthereumNet::Mainnet => format!("https://etherscan.io/tx/{}", address),
-            EthereumNet::Ropsten => format!("https://ropsten.etherscan.io/tx/{}", address),
-            EthereumNet::Rinkeby => format!("https://rinkeby.etherscan.io/tx/{}", address),
-            EthereumNet::Goerli => format!("https://goerli.etherscan.io/tx/{}", address),
-            EthereumNet::Kovan => format!("https://kovan.etherscan.io/tx/{}", address),
-            EthereumNet::Local => format!("http://localhost:3000/tx/{}", address),
-        }
+        format!("{}/tx/{}", self.net.explorer_url(), address)
     }
     async fn get_balance(&self, addr: &str) -> Result<String> {
-        let addr = Address::from_str(addr)?;
-        let balance = self.client.eth().balance(addr, None).await?;
+        let balance = self.balance_at(Address::from_str(addr)?, None).await?;
         Ok(balance.to_string())
     }
     async fn request_airdrop(&self, addr: &str, amount: &str) -> Result<String> {
@@ -185,31 +283,20 @@ impl CryptoToken for EthereumToken {
     ) -> Result<String> {
         let amount = U256::from_str_radix(amount, 10)?;
         let by = EthereumSigner::new(by)?;
-        if by.address != Address::from_str(from)? {
-            bail!("from address is not match")
-        }
+        ensure!(
+            by.address == Address::from_str(from)?,
+            "from address {} does not match signer {:?}",
+            from,
+            by.address
+        );
         let to = Address::from_str(to)?;
-        let nonce = self
-            .client
-            .eth()
-            .transaction_count(by.address, None)
-            .await?;
-        let gas_price = self.client.eth().gas_price().await?;
-        let gas_limit = 21000;
         let tx = TransactionParameters {
-            nonce: Some(nonce),
-            gas_price: Some(gas_price),
-            gas: gas_limit.into(),
+            gas: 21000.into(),
             to: Some(to),
-            value: amount.into(),
+            value: amount,
             ..Default::default()
         };
-        let signed = self.client.accounts().sign_transaction(tx, by).await?;
-        let tx_hash = self
-            .client
-            .eth()
-            .send_raw_transaction(signed.raw_transaction)
-            .await?;
+        let tx_hash = by.send_transaction(&self.client, tx).await?;
         Ok(format!("{:?}", tx_hash))
     }
 
@@ -239,54 +326,74 @@ impl CryptoToken for EthereumToken {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::contract::{ContractDeployer, ForgeJsonOutput};
+    use crate::devnet::Devnet;
     use crate::signer::SecretKeyOwned;
-    use crate::utils::setup_logs;
+    use crate::utils::{setup_logs, test_signer, TEST_MNEMONIC};
     use crate::{EthereumNet, EthereumToken};
+    use crypto::hdwallet::HdWallet;
     use crypto::openssl::OpensslPrivateKey;
     use crypto::securosys::{
         get_securosys_token, make_single_approver_policy, spawn_auto_approver, SecurosysSdk,
     };
     use crypto::PrivateKey;
     use crypto::PublicKey;
-    use serde_json::Value;
     use token::CryptoToken;
     use tracing::info;
     use web3::types::U256;
 
+    /// Account `index` of `TEST_MNEMONIC`, the way `CryptoToken` takes keys
+    fn test_key(index: u32) -> Result<Arc<dyn Signer>> {
+        let key = HdWallet::from_mnemonic(TEST_MNEMONIC, "")?.ethereum_account(index)?;
+        Ok(Arc::new(key.into_key()))
+    }
+
     #[tokio::test]
+    async fn test_transfer_checks_from() -> Result<()> {
+        // fails before reaching the node
+        let token = EthereumToken::new(EthereumNet::Local)?;
+        let from = format!("{:?}", test_signer(1)?.address);
+        let to = format!("{:?}", test_signer(2)?.address);
+        let error = token
+            .transfer(test_key(0)?, test_key(0)?, &from, &to, "1")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "from address {} does not match signer {:?}",
+                from,
+                test_signer(0)?.address
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
     async fn test_get_eth_balance() -> Result<()> {
-        let token = EthereumToken::new(EthereumNet::Mainnet)?;
-        // any address
+        let devnet = Devnet::spawn().await?;
+        let token = EthereumToken::new_with_client(devnet.net(), devnet.web3().clone());
         let balance = token
-            .get_balance("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")
+            .get_balance(&format!("{:?}", devnet.signer(0)?.address))
             .await?;
-        info!("balance: {}", balance);
+        assert_eq!(balance, eth_to_wei("10000")?.to_string());
         Ok(())
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_eth_transfer() -> Result<()> {
-        setup_logs()?;
-        let token = EthereumToken::new(EthereumNet::Local)?;
+        let devnet = Devnet::spawn().await?;
+        let token = EthereumToken::new_with_client(devnet.net(), devnet.web3().clone());
 
-        // raw openssl private key is not working with ethereum: https://github.com/ethereum/EIPs/blob/master/EIPS/eip-2.md
-        let signer = OpensslPrivateKey::new_secp256k1_none("local_eth_key")?;
-
-        let signer = SecretKeyOwned::new_from_private_exponent(&signer.private_exponent()?)?;
-        let signer = Arc::new(signer);
+        let signer = devnet.signer(1)?;
+        let key = test_key(1)?;
         let addr = format!("{:?}", signer.address);
 
-        let tx = token
-            .request_airdrop(&addr, &token.convert_display_unit_to_internal_unit("10.0")?)
-            .await?;
-        token.confirm_transaction(&tx).await?;
         let to_address = "0x111013b7862Ebc1B9726420aa0E8728De310Ee63";
         let balance = U256::from_str_radix(&token.get_balance(&addr).await?, 10)?;
         let tx = token
             .transfer(
-                signer.clone() as _,
-                signer.clone() as _,
+                key.clone(),
+                key,
                 format!("{:?}", signer.address).as_str(),
                 to_address,
                 &(balance / 2).to_string(),
@@ -298,17 +405,13 @@ mod test {
     // This test requires prior deposit to the address
     // gas fee is required
     #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(not(feature = "manual-tests"), ignore = "needs funds on Goerli")]
     async fn test_eth_transfer_on_goerli_eth() -> Result<()> {
         setup_logs()?;
         let token = EthereumToken::new(EthereumNet::Goerli)?;
 
-        // raw openssl private key is not working with ethereum: https://github.com/ethereum/EIPs/blob/master/EIPS/eip-2.md
-        let signer: OpensslPrivateKey = OpensslPrivateKey::new_secp256k1_none("local_eth_key")?;
-        // let private_key = hex::encode(&signer.private_exponent()?.content);
-        // info!("private key: {}", private_key);
-        // return Ok(());
-        let signer = SecretKeyOwned::new_from_private_exponent(&signer.private_exponent()?)?;
-        let signer = Arc::new(signer);
+        let signer = test_signer(1)?;
+        let key = test_key(1)?;
 
         let addr = format!("{:?}", signer.address);
         info!("The address is {}", addr);
@@ -316,8 +419,8 @@ mod test {
         let balance = U256::from_str_radix(&token.get_balance(&addr).await?, 10)?;
         let tx = token
             .transfer(
-                signer.clone() as _,
-                signer.clone() as _,
+                key.clone(),
+                key,
                 format!("{:?}", signer.address).as_str(),
                 to_address,
                 &(balance / 2).to_string(),
@@ -327,15 +430,16 @@ mod test {
         Ok(())
     }
     #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(
+        not(feature = "manual-tests"),
+        ignore = "needs a node on localhost:8545 with unlocked accounts"
+    )]
     async fn test_eth_transfer_with_securosys() -> Result<()> {
         setup_logs()?;
         let token = EthereumToken::new(EthereumNet::Local)?;
 
-        // raw openssl private key is not working with ethereum: https://github.com/ethereum/EIPs/blob/master/EIPS/eip-2.md
-        let signer = OpensslPrivateKey::new_secp256k1_none("local_eth_key")?;
-
-        let signer = SecretKeyOwned::new_from_private_exponent(&signer.private_exponent()?)?;
-        let signer = Arc::new(signer);
+        let signer = test_signer(1)?;
+        let key = test_key(1)?;
         let addr = format!("{:?}", signer.address);
 
         let tx = token.request_airdrop(&addr, "10.0").await?;
@@ -346,8 +450,8 @@ mod test {
         println!("balance: {}", balance);
         let tx = token
             .transfer(
-                signer.clone() as _,
-                signer.clone() as _,
+                key.clone(),
+                key,
                 &addr,
                 to_address,
                 &token.convert_display_unit_to_internal_unit("8.0")?,
@@ -357,6 +461,10 @@ mod test {
         Ok(())
     }
     #[tokio::test(flavor = "multi_thread")]
+    #[cfg_attr(
+        not(feature = "manual-tests"),
+        ignore = "needs a Securosys HSM and a node on localhost:8545"
+    )]
     async fn test_transfer_with_securosys() -> Result<()> {
         setup_logs()?;
         let hsm = Arc::new(SecurosysSdk::new(get_securosys_token()?)?);
@@ -374,7 +482,7 @@ mod test {
         let public_key = local_key.public_key()?;
         hsm.delete_key(keyname).await?;
         let policy = make_single_approver_policy(approver.to_owned(), approver_key1.public_key()?);
-        hsm.import_key_secp256k1(&keyname, policy, private_key, public_key)
+        hsm.import_key_secp256k1(keyname, policy, private_key, public_key)
             .await?;
         let terminate_tx = spawn_auto_approver(hsm.clone(), approver_key);
         let token = EthereumToken::new(EthereumNet::Local)?;
@@ -385,7 +493,7 @@ mod test {
         let balance = token.get_balance(&address1_str).await?;
         info!("balance: {}", balance);
         let tx = token
-            .transfer_debug(address1_addr, hsm_signer.address, 10.0)
+            .transfer_debug(address1_addr, hsm_signer.address, "10.0")
             .await?;
         token.confirm_transaction(&tx).await?;
         let balance2 = token.get_balance(&address1_str).await?;
@@ -412,27 +520,13 @@ mod test {
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_deploy_erc20() -> Result<()> {
-        setup_logs()?;
-        let token = EthereumToken::new(EthereumNet::Local)?;
-        let signer = OpensslPrivateKey::new_secp256k1_none("local_eth_key")?;
-
-        let signer = SecretKeyOwned::new_from_private_exponent(&signer.private_exponent()?)?;
-        let signer = Arc::new(signer);
-        let addr = format!("{:?}", signer.address);
-
-        let tx = token
-            .request_airdrop(&addr, &token.convert_display_unit_to_internal_unit("10.0")?)
-            .await?;
-        token.confirm_transaction(&tx).await?;
-
-        let output = include_str!("../../erc20/out/MyERC20.sol/MyERC20.json");
-        let output: ForgeJsonOutput = serde_json::from_str(output)?;
-
-        let tx = ContractDeployer::new(token.client.eth(), Value::Array(output.abi))?
-            .code(output.bytecode.object)
-            .sign_with_key_and_execute((), EthereumSigner::new(signer.clone() as _)?)
+        let devnet = Devnet::spawn().await?;
+        let signer = devnet.signer(1)?;
+        let token = devnet
+            .deploy_erc20(&signer, "Test", "TEST", 6, U256::exp10(12))
             .await?;
-        info!("deployed tx: {:?}", tx.address());
+        info!("deployed token: {:?}", token.address());
+        assert_eq!(token.decimals().await?, 6);
         Ok(())
     }
 }
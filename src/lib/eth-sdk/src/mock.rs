@@ -0,0 +1,331 @@
+//! An in-memory `Transport` answering scripted requests, to unit test contract wrappers
+//! without a node
+use crate::state_override::{candidate_slots, Erc20Slots, PROBE_VALUE};
+use eyre::*;
+use jsonrpc_core as rpc;
+use rlp::Rlp;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use web3::error::TransportError;
+use web3::ethabi::{self, Token};
+use web3::signing::keccak256;
+use web3::types::{Address, TransactionReceipt, H256, U256};
+use web3::{helpers, RequestId, Transport, Web3};
+
+/// A transaction sent through `eth_sendRawTransaction`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentTransaction {
+    pub hash: H256,
+    pub nonce: U256,
+    /// `None` for deployments
+    pub to: Option<Address>,
+    pub value: U256,
+    pub gas: U256,
+    pub data: Vec<u8>,
+}
+
+impl SentTransaction {
+    /// Decodes legacy, EIP-2930 and EIP-1559 transactions
+    fn decode(raw: &[u8]) -> Result<Self> {
+        // positions of nonce, gas, to, value and data
+        let (rlp, fields) = match raw.first() {
+            Some(1) => (Rlp::new(&raw[1..]), [1, 3, 4, 5, 6]),
+            Some(2) => (Rlp::new(&raw[1..]), [1, 4, 5, 6, 7]),
+            _ => (Rlp::new(raw), [0, 2, 3, 4, 5]),
+        };
+        let field = |i: usize| -> Result<Vec<u8>> { Ok(rlp.at(fields[i])?.data()?.to_vec()) };
+        let to = field(2)?;
+        Ok(Self {
+            hash: H256(keccak256(raw)),
+            nonce: U256::from_big_endian(&field(0)?),
+            to: (!to.is_empty()).then(|| Address::from_slice(&to)),
+            value: U256::from_big_endian(&field(3)?),
+            gas: U256::from_big_endian(&field(1)?),
+            data: field(4)?,
+        })
+    }
+    pub fn selector(&self) -> Option<[u8; 4]> {
+        self.data.get(..4).map(|x| x.try_into().unwrap())
+    }
+    /// Arguments of the call to `function`, failing if another function was called
+    pub fn decode_input(&self, function: &ethabi::Function) -> Result<Vec<Token>> {
+        ensure!(
+            self.selector() == Some(function.short_signature()),
+            "the transaction does not call {}",
+            function.signature()
+        );
+        Ok(function.decode_input(&self.data[4..])?)
+    }
+}
+
+#[derive(Debug)]
+struct Expectation {
+    method: String,
+    /// Only matches calls of this function, for `eth_call` and `eth_estimateGas`
+    selector: Option<[u8; 4]>,
+    response: web3::Result<Value>,
+}
+
+#[derive(Debug)]
+struct State {
+    chain_id: u64,
+    expectations: Vec<Expectation>,
+    requests: Vec<(String, Vec<Value>)>,
+    sent: Vec<SentTransaction>,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        if !self.expectations.is_empty() && !std::thread::panicking() {
+            panic!("unmet expectations: {:?}", self.expectations);
+        }
+    }
+}
+
+/// Answers with the first matching expectation, each used once, in the order they were added.
+/// Without one, signing a transaction works out of the box: the chain id, gas price, nonce and
+/// gas estimate have defaults, and raw transactions are recorded and return their hash. Any
+/// other request fails. Panics when the last clone is dropped with expectations left
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        let state = State {
+            chain_id: 31337,
+            expectations: vec![],
+            requests: vec![],
+            sent: vec![],
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+    pub fn chain_id(self, chain_id: u64) -> Self {
+        self.state.lock().unwrap().chain_id = chain_id;
+        self
+    }
+    pub fn web3(&self) -> Web3<Self> {
+        Web3::new(self.clone())
+    }
+    fn push(&self, method: &str, selector: Option<[u8; 4]>, response: web3::Result<Value>) {
+        self.state.lock().unwrap().expectations.push(Expectation {
+            method: method.to_owned(),
+            selector,
+            response,
+        });
+    }
+    /// Answers the next `method` request with `result`
+    pub fn expect(&self, method: &str, result: Value) -> &Self {
+        self.push(method, None, Ok(result));
+        self
+    }
+    pub fn expect_error(&self, method: &str, error: web3::Error) -> &Self {
+        self.push(method, None, Err(error));
+        self
+    }
+    /// Answers the next `eth_call` of the function with `selector` with `output`
+    pub fn expect_call(&self, selector: [u8; 4], output: &[Token]) -> &Self {
+        let output = format!("0x{}", hex::encode(ethabi::encode(output)));
+        self.push("eth_call", Some(selector), Ok(json!(output)));
+        self
+    }
+    /// Reverts the next `method`, `eth_call` or `eth_estimateGas`, of the function with
+    /// `selector` with `Error(reason)`, the way geth does
+    pub fn expect_revert(&self, method: &str, selector: [u8; 4], reason: &str) -> &Self {
+        let mut data = ethabi::short_signature("Error", &[ethabi::ParamType::String]).to_vec();
+        data.extend(ethabi::encode(&[Token::String(reason.to_owned())]));
+        let error = rpc::Error {
+            code: rpc::ErrorCode::ServerError(3),
+            message: format!("execution reverted: {}", reason),
+            data: Some(json!(format!("0x{}", hex::encode(data)))),
+        };
+        self.push(method, Some(selector), Err(web3::Error::Rpc(error)));
+        self
+    }
+    /// Answers the probes of the next `probe_erc20_slots` as a token with `slots` would
+    pub fn expect_erc20_slots(&self, slots: Erc20Slots) -> &Self {
+        let candidates = candidate_slots();
+        let position = |slot| candidates.iter().position(|x| *x == slot).unwrap();
+        for (signature, slot) in [
+            ("balanceOf(address)", slots.balance),
+            ("allowance(address,address)", slots.allowance),
+        ] {
+            let selector = crate::utils::function_selector(signature);
+            self.expect_call(selector, &[Token::Uint(PROBE_VALUE + position(slot))]);
+            self.expect_call(selector, &[Token::Uint(PROBE_VALUE - 1)]);
+        }
+        self
+    }
+    /// Answers the next `eth_getTransactionReceipt` with `receipt`, for whichever hash
+    pub fn expect_receipt(&self, receipt: TransactionReceipt) -> &Self {
+        self.push(
+            "eth_getTransactionReceipt",
+            None,
+            serde_json::to_value(receipt).map_err(|e| web3::Error::Decoder(e.to_string())),
+        );
+        self
+    }
+    /// Every request so far, with its parameters
+    pub fn requests(&self) -> Vec<(String, Vec<Value>)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+    /// Raw transactions sent so far, decoded
+    pub fn sent(&self) -> Vec<SentTransaction> {
+        self.state.lock().unwrap().sent.clone()
+    }
+    /// Panics unless every expectation was used, without waiting for the drop
+    pub fn assert_done(&self) {
+        let state = self.state.lock().unwrap();
+        assert!(
+            state.expectations.is_empty(),
+            "unmet expectations: {:?}",
+            state.expectations
+        );
+    }
+    fn respond(&self, method: &str, params: Vec<Value>) -> web3::Result<Value> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push((method.to_owned(), params.clone()));
+        let call_selector = params
+            .first()
+            .and_then(|call| call["data"].as_str())
+            .and_then(|data| hex::decode(data.trim_start_matches("0x")).ok())
+            .and_then(|data| data.get(..4).map(|x| <[u8; 4]>::try_from(x).unwrap()));
+        let matching = state.expectations.iter().position(|x| {
+            x.method == method && (x.selector.is_none() || x.selector == call_selector)
+        });
+        if let Some(i) = matching {
+            return state.expectations.remove(i).response;
+        }
+        let unexpected = || {
+            web3::Error::Transport(TransportError::Message(format!(
+                "unexpected request {} {:?}",
+                method, params
+            )))
+        };
+        Ok(match method {
+            "eth_chainId" => json!(format!("{:#x}", state.chain_id)),
+            "eth_gasPrice" => json!("0x3b9aca00"),
+            "eth_getTransactionCount" => json!(format!("{:#x}", state.sent.len())),
+            "eth_estimateGas" => json!("0x186a0"),
+            "eth_sendRawTransaction" => {
+                let raw = params
+                    .first()
+                    .and_then(|x| x.as_str())
+                    .and_then(|x| hex::decode(x.trim_start_matches("0x")).ok())
+                    .ok_or_else(unexpected)?;
+                let tx = SentTransaction::decode(&raw)
+                    .map_err(|e| web3::Error::InvalidResponse(e.to_string()))?;
+                let hash = tx.hash;
+                state.sent.push(tx);
+                json!(hash)
+            }
+            _ => return Err(unexpected()),
+        })
+    }
+}
+
+impl Transport for MockTransport {
+    type Out = std::future::Ready<web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, rpc::Call) {
+        let id = self.state.lock().unwrap().requests.len();
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        std::future::ready(match request {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                method,
+                params: rpc::Params::Array(params),
+                ..
+            }) => self.respond(&method, params),
+            rpc::Call::MethodCall(rpc::MethodCall { method, .. }) => self.respond(&method, vec![]),
+            request => Err(web3::Error::Transport(TransportError::Message(format!(
+                "not a method call: {:?}",
+                request
+            )))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{function_selector, test_signer, wait_for_success};
+    use web3::contract::Contract;
+
+    const ERC20_ABI: &str = include_str!("erc20.abi.json");
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_mock_transport() -> Result<()> {
+        let mock = MockTransport::new().chain_id(1);
+        let web3 = mock.web3();
+        let token = Address::repeat_byte(1);
+        let contract = Contract::from_json(web3.eth(), token, ERC20_ABI.as_bytes())?;
+        let signer = test_signer(0)?;
+        let to = Address::repeat_byte(2);
+
+        mock.expect_call(
+            function_selector("balanceOf(address)"),
+            &[Token::Uint(7.into())],
+        );
+        let balance: U256 = contract
+            .query("balanceOf", to, None, Default::default(), None)
+            .await?;
+        assert_eq!(balance, 7.into());
+
+        let hash = signer
+            .send_call(&web3, &contract, "transfer", (to, U256::from(5)), 0.into())
+            .await?;
+        mock.expect_receipt(TransactionReceipt {
+            transaction_hash: hash,
+            status: Some(1.into()),
+            ..Default::default()
+        });
+        wait_for_success(&web3.eth(), hash).await?;
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].hash, hash);
+        assert_eq!(sent[0].to, Some(token));
+        let transfer = contract.abi().function("transfer")?;
+        assert_eq!(
+            sent[0].decode_input(transfer)?,
+            vec![Token::Address(to), Token::Uint(5.into())]
+        );
+        assert!(sent[0]
+            .decode_input(contract.abi().function("approve")?)
+            .is_err());
+
+        mock.expect_revert(
+            "eth_estimateGas",
+            function_selector("transfer(address,uint256)"),
+            "ERC20: transfer amount exceeds balance",
+        );
+        let error = signer
+            .send_call(&web3, &contract, "transfer", (to, U256::MAX), 0.into())
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("exceeds balance"));
+        // nothing scripted
+        assert!(web3.eth().block_number().await.is_err());
+        mock.assert_done();
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "unmet expectations")]
+    fn test_unmet_expectations() {
+        let mock = MockTransport::new();
+        mock.expect("eth_blockNumber", json!("0x1"));
+        drop(mock);
+    }
+}
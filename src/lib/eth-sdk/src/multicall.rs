@@ -0,0 +1,193 @@
+//! Batches read calls into one `eth_call` through Multicall3, see
+//! <https://github.com/mds1/multicall>. Chains without it fall back to one call each.
+use crate::dry_run::revert_reason;
+use crate::utils::function_selector;
+use eyre::*;
+use web3::ethabi::{decode, encode, ParamType, Token};
+use web3::futures::future::join_all;
+use web3::types::{Address, BlockId, BlockNumber, CallRequest, H160};
+use web3::{Transport, Web3};
+
+/// Multicall3 has the same address on every chain it is deployed to
+pub const MULTICALL3: Address = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+/// Calls per `eth_call`, to stay under node gas and response size limits
+const CHUNK_SIZE: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub target: Address,
+    pub data: Vec<u8>,
+}
+impl Call {
+    pub fn new(target: Address, signature: &str, params: &[Token]) -> Self {
+        let mut data = function_selector(signature).to_vec();
+        data.extend(encode(params));
+        Self { target, data }
+    }
+}
+
+pub fn encode_aggregate3(calls: &[Call]) -> Vec<u8> {
+    let calls = calls
+        .iter()
+        .map(|x| {
+            Token::Tuple(vec![
+                Token::Address(x.target),
+                // a failing call must not fail the whole batch
+                Token::Bool(true),
+                Token::Bytes(x.data.clone()),
+            ])
+        })
+        .collect();
+    Call::new(
+        MULTICALL3,
+        "aggregate3((address,bool,bytes)[])",
+        &[Token::Array(calls)],
+    )
+    .data
+}
+
+/// Return data of each call, `None` for those that reverted
+pub fn decode_aggregate3(output: &[u8]) -> Result<Vec<Option<Vec<u8>>>> {
+    let result = ParamType::Tuple(vec![ParamType::Bool, ParamType::Bytes]);
+    let tokens = decode(&[ParamType::Array(Box::new(result))], output)?;
+    let results = match tokens.as_slice() {
+        [Token::Array(results)] => results,
+        _ => bail!("unexpected aggregate3 output"),
+    };
+    results
+        .iter()
+        .map(|x| match x {
+            Token::Tuple(x) => match x.as_slice() {
+                [Token::Bool(true), Token::Bytes(data)] => Ok(Some(data.clone())),
+                [Token::Bool(false), Token::Bytes(_)] => Ok(None),
+                _ => bail!("unexpected aggregate3 result {:?}", x),
+            },
+            _ => bail!("unexpected aggregate3 result {:?}", x),
+        })
+        .collect()
+}
+
+pub struct Multicall<T: Transport> {
+    web3: Web3<T>,
+    address: Option<Address>,
+}
+impl<T: Transport> Multicall<T> {
+    pub fn new(web3: Web3<T>) -> Self {
+        Self {
+            web3,
+            address: Some(MULTICALL3),
+        }
+    }
+    /// `None` sends every call on its own
+    pub fn address(mut self, address: Option<Address>) -> Self {
+        self.address = address;
+        self
+    }
+    /// Whether batching is possible at `block`, i.e. the contract was deployed by then
+    pub async fn is_available(&self, block: u64) -> Result<bool> {
+        match self.address {
+            Some(address) => {
+                let block = BlockNumber::Number(block.into());
+                let code = self.web3.eth().code(address, Some(block)).await?;
+                Ok(!code.0.is_empty())
+            }
+            None => Ok(false),
+        }
+    }
+    /// Runs every call at `block`, returning `None` for those that reverted
+    pub async fn call(&self, calls: &[Call], block: u64) -> Result<Vec<Option<Vec<u8>>>> {
+        let block_id = BlockId::Number(BlockNumber::Number(block.into()));
+        if !self.is_available(block).await? {
+            let results = join_all(calls.iter().map(|x| {
+                let request = CallRequest {
+                    to: Some(x.target),
+                    data: Some(x.data.clone().into()),
+                    ..Default::default()
+                };
+                self.web3.eth().call(request, Some(block_id))
+            }))
+            .await;
+            return results
+                .into_iter()
+                .map(|x| match x {
+                    Ok(output) => Ok(Some(output.0)),
+                    Err(err) if revert_reason(&err).is_some() => Ok(None),
+                    Err(err) => Err(err.into()),
+                })
+                .collect();
+        }
+        let mut results = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(CHUNK_SIZE) {
+            let request = CallRequest {
+                to: self.address,
+                data: Some(encode_aggregate3(chunk).into()),
+                ..Default::default()
+            };
+            let output = self.web3.eth().call(request, Some(block_id)).await?;
+            let chunk_results = decode_aggregate3(&output.0)?;
+            ensure!(
+                chunk_results.len() == chunk.len(),
+                "aggregate3 returned {} results for {} calls",
+                chunk_results.len(),
+                chunk.len()
+            );
+            results.extend(chunk_results);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+
+    #[test]
+    fn test_aggregate3() -> Result<()> {
+        let call = Call::new(
+            Address::from_low_u64_be(1),
+            "balanceOf(address)",
+            &[Token::Address(Address::from_low_u64_be(2))],
+        );
+        assert_eq!(hex::encode(&call.data[..4]), "70a08231");
+        let input = encode_aggregate3(&[call.clone(), call]);
+        assert_eq!(hex::encode(&input[..4]), "82ad56cb");
+
+        let output = encode(&[Token::Array(vec![
+            Token::Tuple(vec![Token::Bool(true), Token::Bytes(vec![1, 2])]),
+            Token::Tuple(vec![Token::Bool(false), Token::Bytes(vec![])]),
+        ])]);
+        assert_eq!(decode_aggregate3(&output)?, vec![Some(vec![1, 2]), None]);
+        assert!(decode_aggregate3(&[1, 2, 3]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback() -> Result<()> {
+        let mock = MockTransport::new();
+        let multicall = Multicall::new(mock.web3()).address(None);
+        let token = Address::repeat_byte(1);
+        let balance = Call::new(token, "balanceOf(address)", &[Token::Address(token)]);
+        let decimals = Call::new(token, "decimals()", &[]);
+
+        mock.expect_call(
+            function_selector("balanceOf(address)"),
+            &[Token::Uint(5.into())],
+        );
+        mock.expect_revert("eth_call", function_selector("decimals()"), "");
+        let results = multicall.call(&[balance.clone(), decimals], 1).await?;
+        assert_eq!(results, vec![Some(encode(&[Token::Uint(5.into())])), None]);
+
+        // a node that fails is not a call that reverts
+        mock.expect_error(
+            "eth_call",
+            web3::Error::Rpc(jsonrpc_core::Error::internal_error()),
+        );
+        assert!(multicall.call(&[balance], 1).await.is_err());
+        mock.assert_done();
+        Ok(())
+    }
+}
@@ -0,0 +1,664 @@
+//! Rules a transaction must satisfy before an [`EthereumSigner`](crate::signer::EthereumSigner)
+//! with a policy signs it.
+//!
+//! web3 only hands the signer the hash to sign, so transactions are checked by
+//! [`PolicyGuard::authorize`], which approves the exact hash the signer will be asked for.
+use crate::utils::function_selector;
+use eyre::*;
+use rlp::RlpStream;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::ethabi::{decode, ParamType, Token};
+use web3::signing::keccak256;
+use web3::types::{Address, TransactionParameters, H256, U256};
+
+/// Key of the native token in daily limits
+pub const NATIVE_TOKEN: Address = Address::zero();
+
+const ERC20_TRANSFER: &str = "transfer(address,uint256)";
+const ESCROW_TRANSFER_TOKEN_TO: &str = "transferTokenTo(address,address,uint256)";
+
+const ADDRESS: ParamType = ParamType::Address;
+const UINT: ParamType = ParamType::Uint(256);
+
+/// Calls of `to` whose spends are read from the calldata: the signature, its parameters, the
+/// index of the amount and, when not `to` itself, the index of the token
+const SPENDING_CALLS: &[(&str, &[ParamType], usize, Option<usize>)] = &[
+    (ERC20_TRANSFER, &[ADDRESS, UINT], 1, None),
+    ("approve(address,uint256)", &[ADDRESS, UINT], 1, None),
+    (
+        "increaseAllowance(address,uint256)",
+        &[ADDRESS, UINT],
+        1,
+        None,
+    ),
+    // counted even when pulling from another account, the policy cannot tell
+    (
+        "transferFrom(address,address,uint256)",
+        &[ADDRESS, ADDRESS, UINT],
+        2,
+        None,
+    ),
+    // WETH
+    ("withdraw(uint256)", &[UINT], 0, None),
+    ("deposit()", &[], 0, None),
+    (
+        ESCROW_TRANSFER_TOKEN_TO,
+        &[ADDRESS, ADDRESS, UINT],
+        2,
+        Some(0),
+    ),
+];
+
+/// What a transaction does, as far as the policy is concerned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedTransaction {
+    /// `None` for contract creation
+    pub to: Option<Address>,
+    pub value: U256,
+    pub selector: Option<[u8; 4]>,
+    /// Amounts moved out or approved, per token, including `value` under [`NATIVE_TOKEN`]
+    pub spends: Vec<(Address, U256)>,
+    /// Whether the calldata is empty or one of the calls the spends are read from. Other calls
+    /// to a token with a daily limit are denied, e.g. `permit`
+    pub accounted: bool,
+}
+impl DecodedTransaction {
+    pub fn decode(tx: &TransactionParameters) -> Result<Self> {
+        let data = &tx.data.0;
+        // a fallback would get it, which the selector rules cannot tell from a plain transfer
+        ensure!(
+            data.is_empty() || data.len() >= 4,
+            "calldata of {} bytes has no selector",
+            data.len()
+        );
+        let selector = (data.len() >= 4).then(|| [data[0], data[1], data[2], data[3]]);
+        let mut spends = vec![];
+        if !tx.value.is_zero() {
+            spends.push((NATIVE_TOKEN, tx.value));
+        }
+        let call = SPENDING_CALLS
+            .iter()
+            .find(|(signature, ..)| selector == Some(function_selector(signature)));
+        if let (Some((signature, types, amount, token)), Some(to)) = (call, tx.to) {
+            let args = decode(types, &data[4..])
+                .with_context(|| format!("decoding {} calldata", signature))?;
+            let token = match token.map(|i| &args[i]) {
+                Some(Token::Address(token)) => *token,
+                _ => to,
+            };
+            if let Some(Token::Uint(amount)) = args.get(*amount) {
+                spends.push((token, *amount));
+            }
+        }
+        Ok(Self {
+            to: tx.to,
+            value: tx.value,
+            selector,
+            spends,
+            accounted: selector.is_none() || call.is_some(),
+        })
+    }
+}
+
+/// Why a transaction was denied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    ContractCreation,
+    DestinationNotAllowed(Address),
+    SelectorNotAllowed([u8; 4]),
+    /// A call of a token with a daily limit whose spends the policy cannot tell
+    UnaccountedCall {
+        token: Address,
+        selector: [u8; 4],
+    },
+    ValueTooHigh {
+        value: U256,
+        max: U256,
+    },
+    DailyLimitExceeded {
+        token: Address,
+        spent: U256,
+        amount: U256,
+        limit: U256,
+    },
+    Undecodable(String),
+}
+impl Display for PolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyViolation::ContractCreation => write!(f, "contract creation is not allowed"),
+            PolicyViolation::DestinationNotAllowed(to) => {
+                write!(f, "destination {:?} is not allowed", to)
+            }
+            PolicyViolation::SelectorNotAllowed(selector) => {
+                write!(f, "function 0x{} is not allowed", hex::encode(selector))
+            }
+            PolicyViolation::UnaccountedCall { token, selector } => write!(
+                f,
+                "function 0x{} of {:?} is not accounted for by the daily limit",
+                hex::encode(selector),
+                token
+            ),
+            PolicyViolation::ValueTooHigh { value, max } => {
+                write!(f, "value {} exceeds the maximum of {}", value, max)
+            }
+            PolicyViolation::DailyLimitExceeded {
+                token,
+                spent,
+                amount,
+                limit,
+            } => write!(
+                f,
+                "spending {} of {:?} exceeds the daily limit of {} ({} spent today)",
+                amount, token, limit, spent
+            ),
+            PolicyViolation::Undecodable(err) => write!(f, "cannot decode transaction: {}", err),
+        }
+    }
+}
+impl std::error::Error for PolicyViolation {}
+
+/// Unset rules allow everything
+#[derive(Debug, Clone, Default)]
+pub struct SignerPolicy {
+    pub allowed_destinations: Option<HashSet<Address>>,
+    pub allowed_selectors: Option<HashSet<[u8; 4]>>,
+    pub max_value: Option<U256>,
+    pub daily_limits: HashMap<Address, U256>,
+    pub allow_contract_creation: bool,
+}
+impl SignerPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn allow_destination(mut self, address: Address) -> Self {
+        self.allowed_destinations
+            .get_or_insert_with(HashSet::new)
+            .insert(address);
+        self
+    }
+    /// Allows a function by its signature, e.g. `transferTokenTo(address,address,uint256)`
+    pub fn allow_function(mut self, signature: &str) -> Self {
+        self.allowed_selectors
+            .get_or_insert_with(HashSet::new)
+            .insert(function_selector(signature));
+        self
+    }
+    /// Maximum native `value` of a single transaction
+    pub fn max_value(mut self, max: U256) -> Self {
+        self.max_value = Some(max);
+        self
+    }
+    /// Maximum spent per UTC day for `token`, [`NATIVE_TOKEN`] for ETH
+    pub fn daily_limit(mut self, token: Address, limit: U256) -> Self {
+        self.daily_limits.insert(token, limit);
+        self
+    }
+    pub fn allow_contract_creation(mut self) -> Self {
+        self.allow_contract_creation = true;
+        self
+    }
+
+    /// Checks the stateless rules
+    pub fn check(&self, tx: &DecodedTransaction) -> Result<(), PolicyViolation> {
+        match tx.to {
+            None if !self.allow_contract_creation => return Err(PolicyViolation::ContractCreation),
+            Some(to) => {
+                if let Some(allowed) = &self.allowed_destinations {
+                    if !allowed.contains(&to) {
+                        return Err(PolicyViolation::DestinationNotAllowed(to));
+                    }
+                }
+            }
+            None => {}
+        }
+        if let (Some(allowed), Some(selector)) = (&self.allowed_selectors, tx.selector) {
+            if !allowed.contains(&selector) {
+                return Err(PolicyViolation::SelectorNotAllowed(selector));
+            }
+        }
+        if let Some(max) = self.max_value {
+            if tx.value > max {
+                return Err(PolicyViolation::ValueTooHigh {
+                    value: tx.value,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct GuardState {
+    day: u64,
+    spent: HashMap<Address, U256>,
+    /// Spends recorded today, by signing hash, for `rollback`
+    recorded: HashMap<H256, Vec<(Address, U256)>>,
+    approved: HashSet<H256>,
+}
+
+/// A policy plus what it has let through today
+pub struct PolicyGuard {
+    policy: SignerPolicy,
+    state: Mutex<GuardState>,
+}
+impl PolicyGuard {
+    pub fn new(policy: SignerPolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(GuardState::default()),
+        }
+    }
+    pub fn policy(&self) -> &SignerPolicy {
+        &self.policy
+    }
+
+    /// Checks `tx` and, if allowed, records its spends and approves its signing hash.
+    /// `tx` must have `nonce`, `gas_price` and `chain_id` set
+    pub fn authorize(&self, tx: &TransactionParameters) -> Result<H256, PolicyViolation> {
+        let hash = signing_hash(tx).map_err(|e| PolicyViolation::Undecodable(e.to_string()))?;
+        let decoded = DecodedTransaction::decode(tx)
+            .map_err(|e| PolicyViolation::Undecodable(e.to_string()))?;
+        self.policy.check(&decoded)?;
+        if let (false, Some(to), Some(selector)) = (decoded.accounted, tx.to, decoded.selector) {
+            if self.policy.daily_limits.contains_key(&to) {
+                return Err(PolicyViolation::UnaccountedCall {
+                    token: to,
+                    selector,
+                });
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let today = current_day();
+        if state.day != today {
+            state.day = today;
+            state.spent.clear();
+            state.recorded.clear();
+        }
+        for (token, amount) in &decoded.spends {
+            if let Some(limit) = self.policy.daily_limits.get(token) {
+                let spent = state.spent.get(token).copied().unwrap_or_default();
+                if spent.saturating_add(*amount) > *limit {
+                    return Err(PolicyViolation::DailyLimitExceeded {
+                        token: *token,
+                        spent,
+                        amount: *amount,
+                        limit: *limit,
+                    });
+                }
+            }
+        }
+        for (token, amount) in &decoded.spends {
+            let spent = state.spent.entry(*token).or_default();
+            *spent = spent.saturating_add(*amount);
+        }
+        state
+            .recorded
+            .entry(hash)
+            .or_default()
+            .extend(decoded.spends);
+        state.approved.insert(hash);
+        Ok(hash)
+    }
+    /// Undoes `authorize` for a transaction that failed to be signed or sent: drops the approval
+    /// of `hash` and gives back what it spent, unless that was on another day
+    pub fn rollback(&self, hash: &H256) {
+        let mut state = self.state.lock().unwrap();
+        state.approved.remove(hash);
+        if state.day != current_day() {
+            return;
+        }
+        for (token, amount) in state.recorded.remove(hash).unwrap_or_default() {
+            let spent = state.spent.entry(token).or_default();
+            *spent = spent.saturating_sub(amount);
+        }
+    }
+    /// Consumes the approval of a hash returned by `authorize`
+    pub fn take_approval(&self, hash: &[u8]) -> bool {
+        hash.len() == 32
+            && self
+                .state
+                .lock()
+                .unwrap()
+                .approved
+                .remove(&H256::from_slice(hash))
+    }
+    pub fn spent_today(&self, token: Address) -> U256 {
+        let state = self.state.lock().unwrap();
+        match state.day == current_day() {
+            true => state.spent.get(&token).copied().unwrap_or_default(),
+            false => U256::zero(),
+        }
+    }
+}
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / 86400)
+        .unwrap_or_default()
+}
+
+/// The hash web3's `Accounts::sign_transaction` asks the key to sign, for a transaction with
+/// `nonce`, `gas_price` and `chain_id` filled in
+pub fn signing_hash(tx: &TransactionParameters) -> Result<H256> {
+    let nonce = tx.nonce.context("nonce is not set")?;
+    let chain_id = tx.chain_id.context("chain_id is not set")?;
+    let transaction_type = tx.transaction_type.map(|x| x.as_u64()).unwrap_or_default();
+    let gas_price = match (transaction_type, tx.max_fee_per_gas) {
+        (2, Some(max_fee)) => max_fee,
+        _ => tx.gas_price.context("gas_price is not set")?,
+    };
+    let append_to = |stream: &mut RlpStream| {
+        match tx.to {
+            Some(to) => stream.append(&to),
+            None => stream.append(&""),
+        };
+    };
+    let append_access_list = |stream: &mut RlpStream| {
+        let access_list = tx.access_list.clone().unwrap_or_default();
+        stream.begin_list(access_list.len());
+        for access in access_list {
+            stream.begin_list(2);
+            stream.append(&access.address);
+            stream.begin_list(access.storage_keys.len());
+            for key in access.storage_keys {
+                stream.append(&key);
+            }
+        }
+    };
+    let mut stream = RlpStream::new();
+    let encoded = match transaction_type {
+        0 => {
+            stream.begin_list(9);
+            stream.append(&nonce).append(&gas_price).append(&tx.gas);
+            append_to(&mut stream);
+            stream.append(&tx.value).append(&tx.data.0);
+            stream.append(&chain_id).append(&0u8).append(&0u8);
+            stream.out().to_vec()
+        }
+        1 => {
+            stream.begin_list(8);
+            stream.append(&chain_id);
+            stream.append(&nonce).append(&gas_price).append(&tx.gas);
+            append_to(&mut stream);
+            stream.append(&tx.value).append(&tx.data.0);
+            append_access_list(&mut stream);
+            [&[1u8], stream.as_raw()].concat()
+        }
+        2 => {
+            let max_priority_fee = tx.max_priority_fee_per_gas.unwrap_or(gas_price);
+            stream.begin_list(9);
+            stream.append(&chain_id).append(&nonce);
+            stream
+                .append(&max_priority_fee)
+                .append(&gas_price)
+                .append(&tx.gas);
+            append_to(&mut stream);
+            stream.append(&tx.value).append(&tx.data.0);
+            append_access_list(&mut stream);
+            [&[2u8], stream.as_raw()].concat()
+        }
+        x => bail!("unsupported transaction type {}", x),
+    };
+    Ok(keccak256(&encoded).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::utils::test_signer;
+    use secp256k1::SecretKey;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use web3::error::TransportError;
+    use web3::ethabi::encode;
+    use web3::transports::Http;
+    use web3::types::{AccessListItem, Bytes};
+    use web3::Web3;
+
+    fn escrow() -> Address {
+        Address::from_low_u64_be(0xe5c)
+    }
+    fn token() -> Address {
+        Address::from_low_u64_be(0x70c)
+    }
+    fn calldata(signature: &str, args: &[Token]) -> Bytes {
+        let mut data = function_selector(signature).to_vec();
+        data.extend(encode(args));
+        data.into()
+    }
+    fn tx(to: Address, value: u64, data: Bytes) -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(7.into()),
+            gas_price: Some(1_000_000_000u64.into()),
+            chain_id: Some(31337),
+            to: Some(to),
+            value: value.into(),
+            data,
+            ..Default::default()
+        }
+    }
+    fn transfer_token_to(amount: u64) -> TransactionParameters {
+        let data = calldata(
+            ESCROW_TRANSFER_TOKEN_TO,
+            &[
+                Token::Address(token()),
+                Token::Address(Address::from_low_u64_be(1)),
+                Token::Uint(amount.into()),
+            ],
+        );
+        tx(escrow(), 0, data)
+    }
+
+    #[tokio::test]
+    async fn test_signing_hash_matches_web3() -> Result<()> {
+        let web3 = Web3::new(Http::new("http://localhost:1")?);
+        let key = SecretKey::from_str(
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )?;
+        let legacy = transfer_token_to(5);
+        let access_list = TransactionParameters {
+            transaction_type: Some(1.into()),
+            access_list: Some(vec![AccessListItem {
+                address: token(),
+                storage_keys: vec![H256::repeat_byte(1)],
+            }]),
+            ..legacy.clone()
+        };
+        let eip1559 = TransactionParameters {
+            transaction_type: Some(2.into()),
+            max_fee_per_gas: Some(3_000_000_000u64.into()),
+            max_priority_fee_per_gas: Some(1_000_000u64.into()),
+            to: None,
+            ..legacy.clone()
+        };
+        for tx in [legacy, access_list, eip1559] {
+            let signed = web3.accounts().sign_transaction(tx.clone(), &key).await?;
+            assert_eq!(signing_hash(&tx)?, signed.message_hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_spends() -> Result<()> {
+        let decoded = DecodedTransaction::decode(&transfer_token_to(300))?;
+        assert_eq!(decoded.spends, vec![(token(), 300.into())]);
+
+        let data = calldata(
+            ERC20_TRANSFER,
+            &[Token::Address(escrow()), Token::Uint(42.into())],
+        );
+        let decoded = DecodedTransaction::decode(&tx(token(), 5, data))?;
+        assert_eq!(
+            decoded.spends,
+            vec![(NATIVE_TOKEN, 5.into()), (token(), 42.into())]
+        );
+
+        let data = calldata(
+            "approve(address,uint256)",
+            &[Token::Address(escrow()), Token::Uint(U256::MAX)],
+        );
+        let decoded = DecodedTransaction::decode(&tx(token(), 0, data))?;
+        assert_eq!(decoded.spends, vec![(token(), U256::MAX)]);
+        let data = calldata("withdraw(uint256)", &[Token::Uint(7.into())]);
+        let decoded = DecodedTransaction::decode(&tx(token(), 0, data))?;
+        assert_eq!(decoded.spends, vec![(token(), 7.into())]);
+        assert!(decoded.accounted);
+        let data = calldata("renounceOwnership()", &[]);
+        assert!(!DecodedTransaction::decode(&tx(token(), 0, data))?.accounted);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stateless_rules() -> Result<()> {
+        let guard = PolicyGuard::new(
+            SignerPolicy::new()
+                .allow_destination(escrow())
+                .allow_function(ESCROW_TRANSFER_TOKEN_TO)
+                .max_value(10.into()),
+        );
+        guard.authorize(&transfer_token_to(1))?;
+        let other = Address::from_low_u64_be(2);
+        assert_eq!(
+            guard.authorize(&tx(other, 0, Bytes::default())),
+            Err(PolicyViolation::DestinationNotAllowed(other))
+        );
+        assert_eq!(
+            guard.authorize(&tx(escrow(), 11, Bytes::default())),
+            Err(PolicyViolation::ValueTooHigh {
+                value: 11.into(),
+                max: 10.into()
+            })
+        );
+        let renounce = calldata("renounceOwnership()", &[]);
+        assert_eq!(
+            guard.authorize(&tx(escrow(), 0, renounce)),
+            Err(PolicyViolation::SelectorNotAllowed(function_selector(
+                "renounceOwnership()"
+            )))
+        );
+        assert!(matches!(
+            guard.authorize(&tx(escrow(), 0, vec![1, 2, 3].into())),
+            Err(PolicyViolation::Undecodable(_))
+        ));
+        let deploy = TransactionParameters {
+            to: None,
+            ..transfer_token_to(1)
+        };
+        assert_eq!(
+            guard.authorize(&deploy),
+            Err(PolicyViolation::ContractCreation)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_daily_limit() -> Result<()> {
+        let guard = PolicyGuard::new(SignerPolicy::new().daily_limit(token(), 500.into()));
+        guard.authorize(&transfer_token_to(300))?;
+        assert_eq!(
+            guard.authorize(&transfer_token_to(201)),
+            Err(PolicyViolation::DailyLimitExceeded {
+                token: token(),
+                spent: 300.into(),
+                amount: 201.into(),
+                limit: 500.into(),
+            })
+        );
+        guard.authorize(&transfer_token_to(200))?;
+        assert_eq!(guard.spent_today(token()), 500.into());
+
+        // approvals count, calls the policy cannot account for are denied
+        let approve = calldata(
+            "approve(address,uint256)",
+            &[Token::Address(escrow()), Token::Uint(1.into())],
+        );
+        assert!(matches!(
+            guard.authorize(&tx(token(), 0, approve)),
+            Err(PolicyViolation::DailyLimitExceeded { .. })
+        ));
+        let permit = "permit(address,address,uint256,uint256,uint8,bytes32,bytes32)";
+        let data = calldata(permit, &[]);
+        assert_eq!(
+            guard.authorize(&tx(token(), 0, data)),
+            Err(PolicyViolation::UnaccountedCall {
+                token: token(),
+                selector: function_selector(permit),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<()> {
+        let guard = PolicyGuard::new(SignerPolicy::new().daily_limit(token(), 500.into()));
+        guard.authorize(&transfer_token_to(300))?;
+        let hash = guard.authorize(&transfer_token_to(200))?;
+        guard.rollback(&hash);
+        assert_eq!(guard.spent_today(token()), 300.into());
+        assert!(!guard.take_approval(hash.as_bytes()));
+        guard.authorize(&transfer_token_to(200))?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rollback_on_send_failure() -> Result<()> {
+        let mock = MockTransport::new().chain_id(31337);
+        let guard = Arc::new(PolicyGuard::new(
+            SignerPolicy::new().daily_limit(token(), 500.into()),
+        ));
+        let signer = test_signer(0)?.with_policy(guard.clone());
+        mock.expect_error(
+            "eth_sendRawTransaction",
+            web3::Error::Rpc(jsonrpc_core::Error::invalid_params("nonce too low")),
+        );
+        assert!(signer
+            .send_transaction(&mock.web3(), transfer_token_to(300))
+            .await
+            .is_err());
+        assert_eq!(guard.spent_today(token()), 0.into());
+        signer
+            .send_transaction(&mock.web3(), transfer_token_to(300))
+            .await?;
+        assert_eq!(guard.spent_today(token()), 300.into());
+        assert_eq!(mock.sent().len(), 1);
+        // a timeout may come after the node got it
+        mock.expect_error(
+            "eth_sendRawTransaction",
+            web3::Error::Transport(TransportError::Message("timed out".to_owned())),
+        );
+        assert!(signer
+            .send_transaction(&mock.web3(), transfer_token_to(100))
+            .await
+            .is_err());
+        assert_eq!(guard.spent_today(token()), 400.into());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_signer_with_policy() -> Result<()> {
+        let web3 = Web3::new(Http::new("http://localhost:1")?);
+        let guard = PolicyGuard::new(SignerPolicy::new().allow_destination(escrow()));
+        let signer = test_signer(0)?.with_policy(guard.into());
+        signer.sign_transaction(&web3, transfer_token_to(1)).await?;
+        let err = signer
+            .sign_transaction(&web3, tx(token(), 0, Bytes::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(&PolicyViolation::DestinationNotAllowed(token()))
+        );
+        // hashes that did not go through the policy are refused
+        use web3::signing::Key;
+        assert!(signer.sign_message(&[1u8; 32]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+//! Balances of several accounts across ETH, the ERC-20s of a token registry and StrategyPool
+//! shares, all read at one block
+use crate::amount::{Amount, ETH_DECIMALS};
+use crate::block::{self, BlockRef};
+use crate::multicall::{Call, Multicall};
+use crate::registry::{TokenInfo, TokenKind, TokenRegistry};
+use crate::EthereumNet;
+use eyre::*;
+use serde::Serialize;
+use web3::ethabi::{decode, ParamType, Token};
+use web3::futures::future::try_join_all;
+use web3::transports::Http;
+use web3::types::{Address, H256, U256};
+use web3::{Transport, Web3};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Holding {
+    pub symbol: String,
+    /// `None` for ETH
+    pub address: Option<Address>,
+    /// In base units, `None` if it could not be read
+    pub balance: Option<String>,
+    /// With decimals applied
+    pub amount: Option<String>,
+    /// Why the balance could not be read, e.g. a `balanceOf` that reverts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+impl Holding {
+    fn new(symbol: &str, address: Option<Address>, balance: U256, decimals: u8) -> Result<Self> {
+        Ok(Self {
+            symbol: symbol.to_owned(),
+            address,
+            balance: Some(balance.to_string()),
+            amount: Some(Amount::new(balance, decimals)?.to_string()),
+            error: None,
+        })
+    }
+    fn failed(symbol: &str, address: Option<Address>, error: String) -> Self {
+        Self {
+            symbol: symbol.to_owned(),
+            address,
+            balance: None,
+            amount: None,
+            error: Some(error),
+        }
+    }
+}
+/// An asset backing pool shares. `symbol` and `amount` are only known for assets in the registry
+#[derive(Debug, Clone, Serialize)]
+pub struct UnderlyingHolding {
+    pub address: Address,
+    pub symbol: Option<String>,
+    pub balance: String,
+    pub amount: Option<String>,
+}
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolShareHolding {
+    #[serde(flatten)]
+    pub shares: Holding,
+    /// What the shares are worth through `convertToAssets`
+    pub underlying: Vec<UnderlyingHolding>,
+    /// Why `convertToAssets` failed, leaving `underlying` empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underlying_error: Option<String>,
+}
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountPortfolio {
+    pub address: Address,
+    pub native: Holding,
+    pub tokens: Vec<Holding>,
+    pub pool_shares: Vec<PoolShareHolding>,
+}
+#[derive(Debug, Clone, Serialize)]
+pub struct Portfolio {
+    pub network: String,
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub accounts: Vec<AccountPortfolio>,
+}
+
+pub struct PortfolioReader<T: Transport = Http> {
+    net: EthereumNet,
+    client: Web3<T>,
+    registry: TokenRegistry,
+    multicall: Option<Address>,
+}
+impl PortfolioReader {
+    /// Reads every token `registry` has on `net`
+    pub fn new(net: EthereumNet, registry: TokenRegistry) -> Result<Self> {
+        Ok(Self::new_with_client(net, net.client()?, registry))
+    }
+}
+impl<T: Transport> PortfolioReader<T> {
+    /// Through `client` rather than the default RPC endpoint of `net`
+    pub fn new_with_client(net: EthereumNet, client: Web3<T>, registry: TokenRegistry) -> Self {
+        Self {
+            net,
+            client,
+            registry,
+            multicall: Some(crate::multicall::MULTICALL3),
+        }
+    }
+    /// Multicall3 deployment to batch through, `None` to send every call on its own
+    pub fn multicall(mut self, address: Option<Address>) -> Self {
+        self.multicall = address;
+        self
+    }
+    /// Snapshot at `block`, or at the latest block if `None`. Tags and hashes are resolved to a
+    /// block number first, so every read sees the same block
+    pub async fn snapshot(
+        &self,
+        accounts: &[Address],
+        block: Option<BlockRef>,
+    ) -> Result<Portfolio> {
+        let (block_number, block_hash) =
+            block::resolve(&self.client, block.unwrap_or(BlockRef::Latest)).await?;
+        let pinned = Some(BlockRef::Number(block_number));
+        let multicall = Multicall::new(self.client.clone()).address(self.multicall);
+
+        let mut tokens: Vec<&TokenInfo> =
+            self.registry.iter().filter(|x| x.net == self.net).collect();
+        tokens.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+        let native = try_join_all(
+            accounts
+                .iter()
+                .map(|&account| block::balance(&self.client, account, pinned)),
+        )
+        .await?;
+        let balance_calls: Vec<Call> = accounts
+            .iter()
+            .flat_map(|&account| {
+                tokens.iter().map(move |token| {
+                    Call::new(
+                        token.address,
+                        "balanceOf(address)",
+                        &[Token::Address(account)],
+                    )
+                })
+            })
+            .collect();
+        let balances = multicall
+            .call(&balance_calls, block_number)
+            .await?
+            .into_iter()
+            .map(|output| decode_uint(output.as_deref()).map_err(|err| err.to_string()))
+            .collect::<Vec<_>>();
+
+        // pools and shares to value, only for non zero balances
+        let mut shares = vec![];
+        for (i, _) in accounts.iter().enumerate() {
+            for (j, token) in tokens.iter().enumerate() {
+                if let Ok(balance) = balances[i * tokens.len() + j] {
+                    if token.kind == TokenKind::StrategyPoolShare && !balance.is_zero() {
+                        shares.push((i, j, balance));
+                    }
+                }
+            }
+        }
+        let value_calls: Vec<Call> = shares
+            .iter()
+            .map(|&(_, j, balance)| {
+                Call::new(
+                    tokens[j].address,
+                    "convertToAssets(uint256)",
+                    &[Token::Uint(balance)],
+                )
+            })
+            .collect();
+        let mut values = multicall
+            .call(&value_calls, block_number)
+            .await?
+            .into_iter()
+            .map(|output| decode_assets(output.as_deref()).map_err(|err| err.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let mut portfolios = vec![];
+        for (i, &address) in accounts.iter().enumerate() {
+            let mut portfolio = AccountPortfolio {
+                address,
+                native: Holding::new("ETH", None, native[i], ETH_DECIMALS)?,
+                tokens: vec![],
+                pool_shares: vec![],
+            };
+            for (j, token) in tokens.iter().enumerate() {
+                let contract = Some(token.address);
+                let balance = &balances[i * tokens.len() + j];
+                let holding = match balance {
+                    Ok(balance) => Holding::new(&token.symbol, contract, *balance, token.decimals)?,
+                    Err(err) => Holding::failed(&token.symbol, contract, err.clone()),
+                };
+                match token.kind {
+                    TokenKind::Erc20 => portfolio.tokens.push(holding),
+                    TokenKind::StrategyPoolShare => {
+                        let underlying = match balance {
+                            Ok(balance) if !balance.is_zero() => {
+                                values.next().context("missing convertToAssets")?
+                            }
+                            _ => Ok(vec![]),
+                        };
+                        let (underlying, underlying_error) = match underlying {
+                            Ok(assets) => (self.underlying(assets)?, None),
+                            Err(err) => (vec![], Some(err)),
+                        };
+                        portfolio.pool_shares.push(PoolShareHolding {
+                            shares: holding,
+                            underlying,
+                            underlying_error,
+                        });
+                    }
+                }
+            }
+            portfolios.push(portfolio);
+        }
+        Ok(Portfolio {
+            network: self.net.name().to_owned(),
+            block_number,
+            block_hash,
+            accounts: portfolios,
+        })
+    }
+    fn underlying(&self, assets: Vec<(Address, U256)>) -> Result<Vec<UnderlyingHolding>> {
+        assets
+            .into_iter()
+            .map(|(address, balance)| {
+                let info = self.registry.find_by_address(self.net, address);
+                Ok(UnderlyingHolding {
+                    address,
+                    symbol: info.map(|x| x.symbol.clone()),
+                    balance: balance.to_string(),
+                    amount: match info {
+                        Some(info) => Some(Amount::new(balance, info.decimals)?.to_string()),
+                        None => None,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+fn decode_uint(output: Option<&[u8]>) -> Result<U256> {
+    let output = output.context("reverted")?;
+    match decode(&[ParamType::Uint(256)], output)?.as_slice() {
+        [Token::Uint(x)] => Ok(*x),
+        x => bail!("unexpected output {:?}", x),
+    }
+}
+
+/// Output of StrategyPool's `convertToAssets`: `(address[] assets, uint256[] amounts)`
+fn decode_assets(output: Option<&[u8]>) -> Result<Vec<(Address, U256)>> {
+    let output = output.context("reverted")?;
+    let tokens = decode(
+        &[
+            ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+        ],
+        output,
+    )?;
+    let (assets, amounts) = match tokens.as_slice() {
+        [Token::Array(assets), Token::Array(amounts)] if assets.len() == amounts.len() => {
+            (assets, amounts)
+        }
+        x => bail!("unexpected output {:?}", x),
+    };
+    assets
+        .iter()
+        .zip(amounts)
+        .map(|x| match x {
+            (Token::Address(asset), Token::Uint(amount)) => Ok((*asset, *amount)),
+            x => bail!("unexpected output {:?}", x),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::multicall::MULTICALL3;
+    use crate::utils::function_selector;
+    use serde_json::json;
+    use web3::ethabi::encode;
+    use web3::types::Block;
+
+    /// Output of `aggregate3`, `None` for calls that revert
+    fn aggregate3(results: Vec<Option<Vec<Token>>>) -> Token {
+        Token::Array(
+            results
+                .into_iter()
+                .map(|x| {
+                    Token::Tuple(vec![
+                        Token::Bool(x.is_some()),
+                        Token::Bytes(x.map(|x| encode(&x)).unwrap_or_default()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_decode_assets() -> Result<()> {
+        let usdc = Address::from_low_u64_be(1);
+        let weth = Address::from_low_u64_be(2);
+        let output = encode(&[
+            Token::Array(vec![Token::Address(usdc), Token::Address(weth)]),
+            Token::Array(vec![Token::Uint(5.into()), Token::Uint(7.into())]),
+        ]);
+        assert_eq!(
+            decode_assets(Some(&output))?,
+            vec![(usdc, 5.into()), (weth, 7.into())]
+        );
+        let mismatched = encode(&[
+            Token::Array(vec![Token::Address(usdc)]),
+            Token::Array(vec![]),
+        ]);
+        assert!(decode_assets(Some(&mismatched)).is_err());
+        assert!(decode_assets(None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize() -> Result<()> {
+        let portfolio = Portfolio {
+            network: "local".to_owned(),
+            block_number: 7,
+            block_hash: H256::zero(),
+            accounts: vec![AccountPortfolio {
+                address: Address::from_low_u64_be(1),
+                native: Holding::new("ETH", None, U256::exp10(18), ETH_DECIMALS)?,
+                tokens: vec![Holding::failed(
+                    "BAD",
+                    Some(Address::from_low_u64_be(3)),
+                    "reverted".to_owned(),
+                )],
+                pool_shares: vec![PoolShareHolding {
+                    shares: Holding::new("SP", Some(Address::from_low_u64_be(2)), 15.into(), 1)?,
+                    underlying: vec![],
+                    underlying_error: None,
+                }],
+            }],
+        };
+        let json = serde_json::to_value(&portfolio)?;
+        let account = &json["accounts"][0];
+        assert_eq!(account["native"]["amount"], "1");
+        assert_eq!(account["pool_shares"][0]["symbol"], "SP");
+        assert_eq!(account["pool_shares"][0]["amount"], "1.5");
+        assert!(account["pool_shares"][0].get("error").is_none());
+        assert_eq!(account["tokens"][0]["balance"], serde_json::Value::Null);
+        assert_eq!(account["tokens"][0]["error"], "reverted");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() -> Result<()> {
+        let net = EthereumNet::Local;
+        let (usdc, weth, pool) = (
+            Address::repeat_byte(0x11),
+            Address::repeat_byte(0x12),
+            Address::repeat_byte(0x13),
+        );
+        let mut registry = TokenRegistry::new();
+        for (address, symbol, decimals) in [(usdc, "USDC", 6), (weth, "WETH", 18)] {
+            registry.insert(TokenInfo {
+                net,
+                address,
+                name: symbol.to_owned(),
+                symbol: symbol.to_owned(),
+                decimals,
+                kind: TokenKind::Erc20,
+            })?;
+        }
+        registry.insert_strategy_pool(net, pool, "Strategy Pool", "SP", 18)?;
+        let mock = MockTransport::new();
+        let reader = PortfolioReader::new_with_client(net, mock.web3(), registry);
+        let (alice, bob) = (Address::repeat_byte(0xa1), Address::repeat_byte(0xb2));
+        let hash = H256::repeat_byte(7);
+
+        mock.expect(
+            "eth_getBlockByNumber",
+            serde_json::to_value(Block::<H256> {
+                number: Some(16.into()),
+                hash: Some(hash),
+                ..Default::default()
+            })?,
+        );
+        mock.expect("eth_getBalance", json!("0xde0b6b3a7640000"));
+        mock.expect("eth_getBalance", json!("0x0"));
+        let aggregate = function_selector("aggregate3((address,bool,bytes)[])");
+        let uint = |x: U256| Some(vec![Token::Uint(x)]);
+        // SP, USDC then WETH of each account, alice's WETH reverting
+        mock.expect("eth_getCode", json!("0x6080"));
+        mock.expect_call(
+            aggregate,
+            &[aggregate3(vec![
+                uint(U256::exp10(18) * 2),
+                uint(5_000_000.into()),
+                None,
+                uint(0.into()),
+                uint(0.into()),
+                uint(U256::exp10(18)),
+            ])],
+        );
+        // only alice has shares to value, partly in a token outside the registry
+        let unknown = Address::repeat_byte(0x99);
+        mock.expect("eth_getCode", json!("0x6080"));
+        mock.expect_call(
+            aggregate,
+            &[aggregate3(vec![Some(vec![
+                Token::Array(vec![Token::Address(usdc), Token::Address(unknown)]),
+                Token::Array(vec![Token::Uint(3_000_000.into()), Token::Uint(7.into())]),
+            ])])],
+        );
+
+        let portfolio = reader.snapshot(&[alice, bob], None).await?;
+        mock.assert_done();
+        // every read is pinned to the resolved block
+        for (method, params) in mock.requests().into_iter().skip(1) {
+            assert_eq!(params.last(), Some(&json!("0x10")), "{}", method);
+            if method == "eth_call" {
+                assert_eq!(params[0]["to"], json!(MULTICALL3));
+            }
+        }
+        assert_eq!((portfolio.block_number, portfolio.block_hash), (16, hash));
+        let json = serde_json::to_value(&portfolio)?;
+        let alice = &json["accounts"][0];
+        assert_eq!(alice["native"]["amount"], "1");
+        assert_eq!(alice["tokens"][0]["symbol"], "USDC");
+        assert_eq!(alice["tokens"][0]["amount"], "5");
+        assert_eq!(alice["tokens"][1]["error"], "reverted");
+        let shares = &alice["pool_shares"][0];
+        assert_eq!(shares["amount"], "2");
+        assert_eq!(
+            shares["underlying"],
+            json!([
+                {"address": usdc, "symbol": "USDC", "balance": "3000000", "amount": "3"},
+                {"address": unknown, "symbol": null, "balance": "7", "amount": null},
+            ])
+        );
+        let bob = &json["accounts"][1];
+        assert_eq!(bob["native"]["amount"], "0");
+        assert_eq!(bob["tokens"][1]["amount"], "1");
+        assert_eq!(bob["pool_shares"][0]["amount"], "0");
+        assert_eq!(bob["pool_shares"][0]["underlying"], json!([]));
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! ERC-1967 proxies, see <https://eips.ethereum.org/EIPS/eip-1967>
+use crate::block::{self, BlockRef};
+use crate::contract::ContractDeployer;
+use crate::signer::EthereumSigner;
+use crate::utils::simulate_call;
+use eyre::*;
+use web3::api::Namespace;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::ethabi::Token;
+use web3::types::{Address, TransactionReceipt, H256, U256};
+use web3::{Transport, Web3};
+
+const ERC1967_ABI: &str = include_str!("erc1967.abi.json");
+
+/// `keccak256("eip1967.proxy.implementation") - 1`
+pub const IMPLEMENTATION_SLOT: H256 = H256([
+    0x36, 0x08, 0x94, 0xa1, 0x3b, 0xa1, 0xa3, 0x21, 0x06, 0x67, 0xc8, 0x28, 0x49, 0x2d, 0xb9, 0x8d,
+    0xca, 0x3e, 0x20, 0x76, 0xcc, 0x37, 0x35, 0xa9, 0x20, 0xa3, 0xca, 0x50, 0x5d, 0x38, 0x2b, 0xbc,
+]);
+/// `keccak256("eip1967.proxy.admin") - 1`
+pub const ADMIN_SLOT: H256 = H256([
+    0xb5, 0x31, 0x27, 0x68, 0x4a, 0x56, 0x8b, 0x31, 0x73, 0xae, 0x13, 0xb9, 0xf8, 0xa6, 0x01, 0x6e,
+    0x24, 0x3e, 0x63, 0xb6, 0xe8, 0xee, 0x11, 0x78, 0xd6, 0xa7, 0x17, 0x85, 0x0b, 0x5d, 0x61, 0x03,
+]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// `TransparentUpgradeableProxy`. With OpenZeppelin 5 `admin` owns the `ProxyAdmin` the
+    /// proxy creates, with 4 it is the admin itself
+    Transparent { admin: Address },
+    /// `ERC1967Proxy` in front of a UUPS implementation, which upgrades itself
+    Uups,
+}
+impl ProxyKind {
+    fn constructor_args(&self, implementation: Address, init_data: Vec<u8>) -> Vec<Token> {
+        match self {
+            ProxyKind::Transparent { admin } => (implementation, *admin, init_data).into_tokens(),
+            ProxyKind::Uups => (implementation, init_data).into_tokens(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyDeployment<T: Transport> {
+    /// The proxy, with the ABI of the implementation
+    pub contract: Contract<T>,
+    pub implementation: Address,
+    pub receipt: TransactionReceipt,
+}
+
+impl<T: Transport> ContractDeployer<T> {
+    /// Deploys this contract as the implementation, then `proxy` in front of it. The proxy
+    /// calls `init_data`, e.g. an encoded `initialize(...)`, on construction
+    pub async fn sign_with_key_and_deploy_proxy<P>(
+        &self,
+        params: P,
+        proxy: &ContractDeployer<T>,
+        kind: ProxyKind,
+        init_data: Vec<u8>,
+        signer: &EthereumSigner,
+    ) -> Result<ProxyDeployment<T>>
+    where
+        P: Tokenize,
+    {
+        let (implementation, _) = self.sign_with_key_and_deploy(params, signer).await?;
+        let args = kind.constructor_args(implementation.address(), init_data);
+        let (deployed, receipt) = proxy.sign_with_key_and_deploy(&args[..], signer).await?;
+        let web3 = Web3::new(self.eth.transport().clone());
+        let found = Erc1967Proxy::new(web3, deployed.address())
+            .implementation(None)
+            .await?;
+        ensure!(
+            found == implementation.address(),
+            "{:?} is not an ERC-1967 proxy of {:?}",
+            deployed.address(),
+            implementation.address()
+        );
+        Ok(ProxyDeployment {
+            contract: Contract::new(self.eth.clone(), deployed.address(), self.abi.clone()),
+            implementation: implementation.address(),
+            receipt,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Erc1967Proxy<T: Transport> {
+    web3: Web3<T>,
+    address: Address,
+}
+
+impl<T: Transport> Erc1967Proxy<T> {
+    pub fn new(web3: Web3<T>, address: Address) -> Self {
+        Self { web3, address }
+    }
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    async fn slot_address(&self, slot: H256, block: Option<BlockRef>) -> Result<Address> {
+        let value = block::storage(&self.web3, self.address, slot, block).await?;
+        Ok(Address::from_slice(&value[12..]))
+    }
+    pub async fn implementation(&self, block: Option<BlockRef>) -> Result<Address> {
+        self.slot_address(IMPLEMENTATION_SLOT, block).await
+    }
+    /// Zero for UUPS proxies
+    pub async fn admin(&self, block: Option<BlockRef>) -> Result<Address> {
+        self.slot_address(ADMIN_SLOT, block).await
+    }
+    /// Points the proxy at `implementation`, calling `data` on it if not empty. Transparent
+    /// proxies are upgraded by their admin, either `by` itself or a `ProxyAdmin` owned by `by`,
+    /// UUPS ones through the implementation. OpenZeppelin 4 and 5 differ in which functions
+    /// they have, so the first one that simulates successfully is sent
+    pub async fn upgrade(
+        &self,
+        by: &EthereumSigner,
+        implementation: Address,
+        data: Vec<u8>,
+    ) -> Result<H256> {
+        let admin = self.admin(None).await?;
+        let through_admin = !admin.is_zero() && admin != by.address;
+        let (target, calls) = if through_admin {
+            let upgrade = ("upgrade", (self.address, implementation).into_tokens());
+            let upgrade_and_call = (
+                "upgradeAndCall",
+                (self.address, implementation, data.clone()).into_tokens(),
+            );
+            (admin, vec![upgrade, upgrade_and_call])
+        } else {
+            let upgrade = ("upgradeTo", implementation.into_tokens());
+            let upgrade_and_call = (
+                "upgradeToAndCall",
+                (implementation, data.clone()).into_tokens(),
+            );
+            (self.address, vec![upgrade, upgrade_and_call])
+        };
+        let contract = Contract::from_json(self.web3.eth(), target, ERC1967_ABI.as_bytes())?;
+        let mut reverted = vec![];
+        // the versions without a call may only be used when there is nothing to call
+        let skip = if data.is_empty() { 0 } else { 1 };
+        for (func, params) in calls.into_iter().skip(skip) {
+            match simulate_call(&self.web3, &contract, by.address, func, &params[..]).await {
+                Ok(_) => {
+                    return by
+                        .send_call(&self.web3, &contract, func, &params[..], U256::zero())
+                        .await
+                }
+                Err(err) => reverted.push(format!("{}: {:#}", func, err)),
+            }
+        }
+        bail!(
+            "cannot upgrade {:?} as {:?}: {}",
+            self.address,
+            by.address,
+            reverted.join("; ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::utils::{function_selector, test_signer};
+    use serde_json::json;
+    use web3::ethabi;
+    use web3::signing::keccak256;
+
+    fn slot_value(address: Address) -> serde_json::Value {
+        json!(H256::from(address))
+    }
+
+    #[test]
+    fn test_slots() {
+        let slot = |name: &str| {
+            let hash = U256::from_big_endian(&keccak256(name.as_bytes()));
+            let mut slot = [0u8; 32];
+            (hash - 1).to_big_endian(&mut slot);
+            H256(slot)
+        };
+        assert_eq!(IMPLEMENTATION_SLOT, slot("eip1967.proxy.implementation"));
+        assert_eq!(ADMIN_SLOT, slot("eip1967.proxy.admin"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_upgrade() -> Result<()> {
+        let mock = MockTransport::new();
+        let proxy = Erc1967Proxy::new(mock.web3(), Address::repeat_byte(1));
+        let by = test_signer(0)?;
+        let (implementation, proxy_admin) = (Address::repeat_byte(2), Address::repeat_byte(3));
+        let init = vec![0xaa, 0xbb];
+        let abi = ethabi::Contract::load(ERC1967_ABI.as_bytes())?;
+        let upgrade_to = function_selector("upgradeTo(address)");
+        let upgrade_to_and_call = function_selector("upgradeToAndCall(address,bytes)");
+        let upgrade = function_selector("upgrade(address,address)");
+        let upgrade_and_call = function_selector("upgradeAndCall(address,address,bytes)");
+
+        // UUPS, or a transparent proxy administered by `by`, with OpenZeppelin 4
+        mock.expect("eth_getStorageAt", slot_value(Address::zero()));
+        mock.expect_call(upgrade_to, &[]);
+        proxy.upgrade(&by, implementation, vec![]).await?;
+        // OpenZeppelin 5 only has upgradeToAndCall
+        mock.expect("eth_getStorageAt", slot_value(by.address));
+        mock.expect_revert("eth_call", upgrade_to, "");
+        mock.expect_call(upgrade_to_and_call, &[]);
+        proxy.upgrade(&by, implementation, vec![]).await?;
+        // with data, upgradeTo is not tried
+        mock.expect("eth_getStorageAt", slot_value(Address::zero()));
+        mock.expect_call(upgrade_to_and_call, &[]);
+        proxy.upgrade(&by, implementation, init.clone()).await?;
+
+        // through a ProxyAdmin owned by `by`, OpenZeppelin 4 then 5
+        mock.expect("eth_getStorageAt", slot_value(proxy_admin));
+        mock.expect_call(upgrade, &[]);
+        proxy.upgrade(&by, implementation, vec![]).await?;
+        mock.expect("eth_getStorageAt", slot_value(proxy_admin));
+        mock.expect_revert("eth_call", upgrade, "");
+        mock.expect_call(upgrade_and_call, &[]);
+        proxy.upgrade(&by, implementation, vec![]).await?;
+
+        let sent = mock.sent();
+        let targets: Vec<_> = sent.iter().map(|x| (x.to, x.selector())).collect();
+        let on_proxy = Some(proxy.address());
+        assert_eq!(
+            targets,
+            vec![
+                (on_proxy, Some(upgrade_to)),
+                (on_proxy, Some(upgrade_to_and_call)),
+                (on_proxy, Some(upgrade_to_and_call)),
+                (Some(proxy_admin), Some(upgrade)),
+                (Some(proxy_admin), Some(upgrade_and_call)),
+            ]
+        );
+        assert_eq!(
+            sent[2].decode_input(abi.function("upgradeToAndCall")?)?,
+            vec![Token::Address(implementation), Token::Bytes(init)]
+        );
+        assert_eq!(
+            sent[4].decode_input(abi.function("upgradeAndCall")?)?,
+            vec![
+                Token::Address(proxy.address()),
+                Token::Address(implementation),
+                Token::Bytes(vec![])
+            ]
+        );
+
+        // not the admin of either
+        mock.expect("eth_getStorageAt", slot_value(proxy_admin));
+        mock.expect_revert("eth_call", upgrade, "Ownable: caller is not the owner");
+        mock.expect_revert(
+            "eth_call",
+            upgrade_and_call,
+            "Ownable: caller is not the owner",
+        );
+        let error = proxy
+            .upgrade(&by, implementation, vec![])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("upgradeAndCall"));
+        assert_eq!(mock.sent().len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deploy_proxy() -> Result<()> {
+        let mock = MockTransport::new();
+        let by = test_signer(0)?;
+        let constructor = |inputs: &[&str]| {
+            let inputs: Vec<_> = inputs
+                .iter()
+                .map(|x| json!({"name": "", "type": x, "internalType": x}))
+                .collect();
+            json!([{"type": "constructor", "inputs": inputs, "stateMutability": "payable"}])
+        };
+        let deployer = |abi| -> Result<ContractDeployer<MockTransport>> {
+            Ok(ContractDeployer::new(mock.web3().eth(), abi)?
+                .code("0x6080".to_owned())
+                .poll_interval(std::time::Duration::from_millis(1)))
+        };
+        let implementation = deployer(json!([]))?;
+        let transparent = deployer(constructor(&["address", "address", "bytes"]))?;
+        let uups = deployer(constructor(&["address", "bytes"]))?;
+        let (logic, proxy, admin) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+        let deployed = |address| TransactionReceipt {
+            status: Some(1.into()),
+            contract_address: Some(address),
+            ..Default::default()
+        };
+
+        mock.expect_receipt(deployed(logic));
+        mock.expect_receipt(deployed(proxy));
+        mock.expect("eth_getStorageAt", slot_value(logic));
+        let kind = ProxyKind::Transparent { admin };
+        let deployment = implementation
+            .sign_with_key_and_deploy_proxy((), &transparent, kind, vec![0xaa], &by)
+            .await?;
+        assert_eq!(deployment.contract.address(), proxy);
+        assert_eq!(deployment.implementation, logic);
+        let sent = mock.sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|x| x.to.is_none()));
+        assert_eq!(sent[0].data, vec![0x60, 0x80]);
+        let args = (logic, admin, vec![0xaau8]).into_tokens();
+        assert_eq!(sent[1].data[2..], ethabi::encode(&args)[..]);
+
+        // the address does not hold an ERC-1967 proxy of the implementation
+        mock.expect_receipt(deployed(logic));
+        mock.expect_receipt(deployed(proxy));
+        mock.expect("eth_getStorageAt", slot_value(Address::zero()));
+        let error = implementation
+            .sign_with_key_and_deploy_proxy((), &uups, ProxyKind::Uups, vec![], &by)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("is not an ERC-1967 proxy"));
+        let args = (logic, Vec::<u8>::new()).into_tokens();
+        assert_eq!(mock.sent()[3].data[2..], ethabi::encode(&args)[..]);
+        Ok(())
+    }
+}
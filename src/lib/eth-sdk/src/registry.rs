@@ -0,0 +1,259 @@
+//! Known ERC-20 contracts by network and symbol, so that `USDC@mainnet` can be used instead of
+//! a raw contract address. Lists use the Uniswap token list format, see
+//! <https://github.com/Uniswap/token-lists>.
+use crate::erc20::{Erc20Metadata, Erc20Token};
+use crate::EthereumNet;
+use eyre::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::warn;
+use web3::transports::Http;
+use web3::types::Address;
+use web3::Web3;
+
+/// Tag marking StrategyPool share tokens in a token list
+pub const STRATEGY_POOL_TAG: &str = "strategy-pool";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Erc20,
+    /// Shares of a StrategyPool, which is itself an ERC-20
+    StrategyPoolShare,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub net: EthereumNet,
+    pub address: Address,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub kind: TokenKind,
+}
+impl TokenInfo {
+    pub fn metadata(&self) -> Erc20Metadata {
+        Erc20Metadata {
+            name: self.name.clone(),
+            symbol: self.symbol.clone(),
+            decimals: self.decimals,
+        }
+    }
+    /// The token through `client`, with its metadata already known
+    pub fn token(&self, client: Web3<Http>) -> Result<Erc20Token> {
+        let token = Erc20Token::new_with_client(self.net, client, self.address)?
+            .with_metadata(self.metadata());
+        match self.kind {
+            TokenKind::Erc20 => Ok(token),
+            TokenKind::StrategyPoolShare => Ok(token.as_strategy_pool()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenList {
+    tokens: Vec<TokenListEntry>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenListEntry {
+    chain_id: u64,
+    address: Address,
+    name: String,
+    symbol: String,
+    decimals: u8,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TokenRegistry {
+    tokens: HashMap<(EthereumNet, String), TokenInfo>,
+}
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn load_token_list(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("reading token list {}", path.display()))?;
+        let mut registry = Self::new();
+        registry
+            .add_token_list(&json)
+            .with_context(|| format!("token list {}", path.display()))?;
+        Ok(registry)
+    }
+    /// Adds the tokens of a token list JSON, skipping chains that are not an `EthereumNet`.
+    /// A symbol listed twice on a network keeps its first contract, the other is skipped with
+    /// a warning
+    pub fn add_token_list(&mut self, json: &str) -> Result<()> {
+        let list: TokenList = serde_json::from_str(json)?;
+        for entry in list.tokens {
+            let net = match EthereumNet::from_chain_id(entry.chain_id) {
+                Some(net) => net,
+                None => continue,
+            };
+            let kind = if entry.tags.iter().any(|x| x == STRATEGY_POOL_TAG) {
+                TokenKind::StrategyPoolShare
+            } else {
+                TokenKind::Erc20
+            };
+            let token = TokenInfo {
+                net,
+                address: entry.address,
+                name: entry.name,
+                symbol: entry.symbol,
+                decimals: entry.decimals,
+                kind,
+            };
+            if let Err(err) = self.insert(token) {
+                warn!("skipping token list entry: {}", err);
+            }
+        }
+        Ok(())
+    }
+    /// Fails if another contract is already registered under the same symbol on that network
+    pub fn insert(&mut self, token: TokenInfo) -> Result<()> {
+        let key = (token.net, token.symbol.to_uppercase());
+        if let Some(existing) = self.tokens.get(&key) {
+            ensure!(
+                existing.address == token.address,
+                "{}@{} is both {:?} and {:?}",
+                token.symbol,
+                token.net.name(),
+                existing.address,
+                token.address
+            );
+        }
+        self.tokens.insert(key, token);
+        Ok(())
+    }
+    /// Registers the share token of a deployed StrategyPool
+    pub fn insert_strategy_pool(
+        &mut self,
+        net: EthereumNet,
+        address: Address,
+        name: &str,
+        symbol: &str,
+        decimals: u8,
+    ) -> Result<()> {
+        self.insert(TokenInfo {
+            net,
+            address,
+            name: name.to_owned(),
+            symbol: symbol.to_owned(),
+            decimals,
+            kind: TokenKind::StrategyPoolShare,
+        })
+    }
+    /// Symbols are matched case-insensitively
+    pub fn get(&self, net: EthereumNet, symbol: &str) -> Option<&TokenInfo> {
+        self.tokens.get(&(net, symbol.to_uppercase()))
+    }
+    pub fn find_by_address(&self, net: EthereumNet, address: Address) -> Option<&TokenInfo> {
+        self.tokens
+            .values()
+            .find(|x| x.net == net && x.address == address)
+    }
+    /// Looks up an asset string such as `USDC@mainnet`
+    pub fn lookup(&self, s: &str) -> Result<Option<&TokenInfo>> {
+        let (symbol, net) = match s.split_once('@') {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let net = EthereumNet::from_str(net)?;
+        Ok(self.get(net, symbol))
+    }
+    /// Resolves an asset string such as `USDC@mainnet` to a token with its decimals already known
+    pub fn try_from_str(&self, s: &str) -> Result<Option<Erc20Token>> {
+        let info = match self.lookup(s)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        Ok(Some(info.token(info.net.client()?)?))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &TokenInfo> {
+        self.tokens.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token::CryptoToken;
+
+    const TOKEN_LIST: &str = r#"{
+        "name": "test list",
+        "timestamp": "2023-01-01T00:00:00Z",
+        "version": {"major": 1, "minor": 0, "patch": 0},
+        "tokens": [
+            {
+                "chainId": 1,
+                "address": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                "name": "USD Coin",
+                "symbol": "USDC",
+                "decimals": 6,
+                "logoURI": "https://example.com/usdc.png"
+            },
+            {
+                "chainId": 31337,
+                "address": "0x5FbDB2315678afecb367f032d93F642f64180aa3",
+                "name": "Strategy Pool",
+                "symbol": "SP",
+                "decimals": 18,
+                "tags": ["strategy-pool"]
+            },
+            {
+                "chainId": 137,
+                "address": "0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174",
+                "name": "USD Coin (PoS)",
+                "symbol": "USDC",
+                "decimals": 6
+            },
+            {
+                "chainId": 1,
+                "address": "0x0000000000000000000000000000000000000001",
+                "name": "Fake USD Coin",
+                "symbol": "usdc",
+                "decimals": 18
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_token_list() -> Result<()> {
+        let mut registry = TokenRegistry::new();
+        registry.add_token_list(TOKEN_LIST)?;
+        assert_eq!(registry.iter().count(), 2);
+
+        let usdc = registry.try_from_str("usdc@mainnet")?.unwrap();
+        assert_eq!(
+            usdc.address(),
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".parse()?
+        );
+        assert_eq!(
+            usdc.convert_display_unit_to_internal_unit("1.5")?,
+            "1500000"
+        );
+        assert_eq!(
+            registry.lookup("SP@local")?.unwrap().kind,
+            TokenKind::StrategyPoolShare
+        );
+        assert!(registry.lookup("USDC@local")?.is_none());
+        assert!(registry.lookup("USDC")?.is_none());
+        assert!(registry.lookup("USDC@nowhere").is_err());
+
+        let other = Address::from_low_u64_be(1);
+        assert!(registry
+            .insert_strategy_pool(EthereumNet::Local, other, "Strategy Pool", "SP", 18)
+            .is_err());
+        registry.insert_strategy_pool(EthereumNet::Local, other, "Other Pool", "SP2", 18)?;
+        assert_eq!(
+            registry.get(EthereumNet::Local, "sp2").unwrap().address,
+            other
+        );
+        Ok(())
+    }
+}
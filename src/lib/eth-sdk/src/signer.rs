@@ -1,24 +1,263 @@
+use crate::dry_run::{revert_reason, DryRun, DryRunError};
+use crate::policy::PolicyGuard;
 use crate::utils;
+use crypto::hdwallet::HdWallet;
+use crypto::keystore::{KdfParams, KeystoreV3};
 use crypto::{
     sign_sync_compact, DerPublicKey, PrivateExpontent, PublicExpontent, PublicKey, Signer,
 };
 use eyre::*;
 use once_cell::sync::Lazy;
-use secp256k1::{All, Message, Secp256k1, SecretKey};
+use secp256k1::{ecdsa, All, Message, Secp256k1, SecretKey};
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 use tracing::warn;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::ethabi::Function;
 use web3::signing::{keccak256, recover, Key, SigningError};
-use web3::types::{Address, H256};
+use web3::types::{
+    Address, BlockNumber, CallRequest, SignedTransaction, TransactionParameters, H256, U256, U64,
+};
+use web3::{Transport, Web3};
 
 pub struct EthereumSigner {
     inner: Arc<dyn Signer>,
     pub address: Address,
+    policy: Option<Arc<PolicyGuard>>,
 }
 
 impl EthereumSigner {
     pub fn new(inner: Arc<dyn Signer>) -> Result<Self> {
         let address = utils::eth_public_exponent_to_address(&inner.public_exponent()?)?;
-        Ok(Self { inner, address })
+        Ok(Self {
+            inner,
+            address,
+            policy: None,
+        })
+    }
+    /// Account `m/44'/60'/0'/0/{index}` of a BIP-39 mnemonic
+    pub fn new_from_mnemonic(phrase: &str, passphrase: &str, index: u32) -> Result<Self> {
+        let key = HdWallet::from_mnemonic(phrase, passphrase)?.ethereum_account(index)?;
+        Self::new(Arc::new(key.into_key()))
+    }
+    /// Only transactions authorized by `policy` through `sign_transaction` get signed
+    pub fn with_policy(mut self, policy: Arc<PolicyGuard>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+    pub fn policy(&self) -> Option<&Arc<PolicyGuard>> {
+        self.policy.as_ref()
+    }
+    /// Fills in `nonce`, `gas_price` and `chain_id` when missing
+    pub async fn fill_transaction<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        mut tx: TransactionParameters,
+    ) -> Result<TransactionParameters> {
+        if tx.nonce.is_none() {
+            tx.nonce = Some(web3.eth().transaction_count(self.address, None).await?);
+        }
+        let eip1559 = tx.transaction_type == Some(U64::from(2));
+        if tx.gas_price.is_none() && !(eip1559 && tx.max_fee_per_gas.is_some()) {
+            tx.gas_price = Some(web3.eth().gas_price().await?);
+        }
+        if tx.chain_id.is_none() {
+            tx.chain_id = Some(web3.eth().chain_id().await?.as_u64());
+        }
+        Ok(tx)
+    }
+    /// Fills in the transaction through `fill_transaction`, checks the policy if any and
+    /// signs. A denial is returned as a `PolicyViolation`
+    pub async fn sign_transaction<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        tx: TransactionParameters,
+    ) -> Result<SignedTransaction> {
+        let tx = self.fill_transaction(web3, tx).await?;
+        let authorized = match &self.policy {
+            Some(policy) => Some(policy.authorize(&tx)?),
+            None => None,
+        };
+        let signed = web3.accounts().sign_transaction(tx, self).await;
+        if let (Err(_), Some(policy), Some(hash)) = (&signed, &self.policy, authorized) {
+            policy.rollback(&hash);
+        }
+        Ok(signed?)
+    }
+    /// Signs `tx` through `sign_transaction` and sends it. If the node rejects it, what the
+    /// policy recorded for it is rolled back. Other errors, e.g. a timeout, keep it recorded as
+    /// the transaction may have been broadcast anyway
+    pub async fn send_transaction<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        tx: TransactionParameters,
+    ) -> Result<H256> {
+        let signed = self.sign_transaction(web3, tx).await?;
+        let sent = web3
+            .eth()
+            .send_raw_transaction(signed.raw_transaction)
+            .await;
+        if let (Err(web3::Error::Rpc(_)), Some(policy)) = (&sent, &self.policy) {
+            policy.rollback(&signed.message_hash);
+        }
+        Ok(sent?)
+    }
+    /// Sends a call of `func` on `contract` with `value` wei attached, estimating its gas and
+    /// signing it through `sign_transaction`, so any policy applies
+    pub async fn send_call<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        contract: &Contract<T>,
+        func: &str,
+        params: impl Tokenize,
+        value: U256,
+    ) -> Result<H256> {
+        let data = utils::encode_call(contract, func, params)?;
+        let call = CallRequest {
+            from: Some(self.address),
+            to: Some(contract.address()),
+            value: Some(value),
+            data: Some(data.clone().into()),
+            ..Default::default()
+        };
+        let gas = web3
+            .eth()
+            .estimate_gas(call, None)
+            .await
+            .with_context(|| format!("estimating gas of {}", func))?;
+        let tx = TransactionParameters {
+            to: Some(contract.address()),
+            gas,
+            value,
+            data: data.into(),
+            ..Default::default()
+        };
+        self.send_transaction(web3, tx).await
+    }
+    /// Runs `tx`, filled in the way `sign_transaction` would, through `eth_call` against the
+    /// pending block and decodes the output of `function`. Nothing is signed or sent. With
+    /// `estimate_gas` the gas limit is the estimate, as in `send_call`
+    pub async fn dry_run<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        tx: TransactionParameters,
+        function: Option<&Function>,
+        estimate_gas: bool,
+    ) -> Result<DryRun> {
+        let mut tx = self.fill_transaction(web3, tx).await?;
+        let mut call = CallRequest {
+            from: Some(self.address),
+            to: tx.to,
+            gas_price: tx.gas_price,
+            value: Some(tx.value),
+            data: Some(tx.data.clone()),
+            transaction_type: tx.transaction_type,
+            access_list: tx.access_list.clone(),
+            max_fee_per_gas: tx.max_fee_per_gas,
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+            ..Default::default()
+        };
+        let gas = match web3
+            .eth()
+            .estimate_gas(call.clone(), Some(BlockNumber::Pending))
+            .await
+        {
+            Ok(gas) => gas,
+            Err(err) => {
+                let reason = revert_reason(&err).ok_or(err).context("estimating gas")?;
+                return Ok(DryRun {
+                    result: Err(DryRunError::Revert(reason)),
+                    gas: None,
+                    transaction: tx,
+                });
+            }
+        };
+        if estimate_gas {
+            tx.gas = gas;
+        }
+        call.gas = Some(tx.gas);
+        let result = match web3
+            .eth()
+            .call(call, Some(BlockNumber::Pending.into()))
+            .await
+        {
+            Ok(output) if !output.0.is_empty() => match function {
+                Some(function) => Ok(function.decode_output(&output.0)?),
+                None => Ok(vec![]),
+            },
+            // like `transfer` of USDT, functions may return nothing despite their ABI, but so
+            // does any call to an account without code
+            Ok(_) => match tx.to {
+                Some(to) if !tx.data.0.is_empty() => {
+                    let code = web3
+                        .eth()
+                        .code(to, Some(BlockNumber::Pending))
+                        .await
+                        .context("dry run")?;
+                    if code.0.is_empty() {
+                        Err(DryRunError::NoCode(to))
+                    } else {
+                        Ok(vec![])
+                    }
+                }
+                _ => Ok(vec![]),
+            },
+            Err(err) => Err(DryRunError::Revert(
+                revert_reason(&err).ok_or(err).context("dry run")?,
+            )),
+        };
+        Ok(DryRun {
+            result,
+            gas: Some(gas),
+            transaction: tx,
+        })
+    }
+    /// What `send_call` would do, through `dry_run`
+    pub async fn dry_run_call<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        contract: &Contract<T>,
+        func: &str,
+        params: impl Tokenize,
+        value: U256,
+    ) -> Result<DryRun> {
+        let data = utils::encode_call(contract, func, params)?;
+        let tx = TransactionParameters {
+            to: Some(contract.address()),
+            value,
+            data: data.into(),
+            ..Default::default()
+        };
+        let function = contract.abi().function(func)?;
+        self.dry_run(web3, tx, Some(function), true).await
+    }
+    /// HSMs return raw ECDSA signatures, of which about half have an `s` in the upper half of
+    /// the curve order. EIP-2 makes nodes reject those, so `s` is replaced by `n - s`
+    fn sign_compact(&self, message: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let signature = sign_sync_compact(&*self.inner, message).map_err(|x| {
+            warn!("sign error: {:?}", x);
+            SigningError::InvalidMessage
+        })?;
+        let mut signature = ecdsa::Signature::from_compact(&signature).map_err(|x| {
+            warn!("malformed signature: {:?}", x);
+            SigningError::InvalidMessage
+        })?;
+        signature.normalize_s();
+        Ok(signature.serialize_compact().to_vec())
+    }
+    fn check_policy(&self, message: &[u8]) -> Result<(), SigningError> {
+        match &self.policy {
+            Some(policy) if !policy.take_approval(message) => {
+                warn!(
+                    "refusing to sign {}: not authorized by the policy",
+                    hex::encode(message)
+                );
+                Err(SigningError::InvalidMessage)
+            }
+            _ => Ok(()),
+        }
     }
 }
 
@@ -41,10 +280,8 @@ impl Key for EthereumSigner {
         if message.len() != 32 {
             return Err(SigningError::InvalidMessage);
         }
-        let signature = sign_sync_compact(&*self.inner, message).map_err(|x| {
-            warn!("sign error: {:?}", x);
-            SigningError::InvalidMessage
-        })?;
+        self.check_policy(message)?;
+        let signature = self.sign_compact(message)?;
         let recovery_id = get_recovery_id(message, &signature, self.address).map_err(|x| {
             warn!("get_recovery_id error: {:?}", x);
             SigningError::InvalidMessage
@@ -67,10 +304,8 @@ impl Key for EthereumSigner {
         if message.len() != 32 {
             return Err(SigningError::InvalidMessage);
         }
-        let signature = sign_sync_compact(&*self.inner, message).map_err(|x| {
-            warn!("sign error: {:?}", x);
-            SigningError::InvalidMessage
-        })?;
+        self.check_policy(message)?;
+        let signature = self.sign_compact(message)?;
 
         let recovery_id = get_recovery_id(message, &signature, self.address).map_err(|x| {
             warn!("get_recovery_id error: {:?}", x);
@@ -87,6 +322,21 @@ impl Key for EthereumSigner {
         self.address
     }
 }
+impl Key for &EthereumSigner {
+    fn sign(
+        &self,
+        message: &[u8],
+        chain_id: Option<u64>,
+    ) -> Result<web3::signing::Signature, SigningError> {
+        (*self).sign(message, chain_id)
+    }
+    fn sign_message(&self, message: &[u8]) -> Result<web3::signing::Signature, SigningError> {
+        (*self).sign_message(message)
+    }
+    fn address(&self) -> Address {
+        self.address
+    }
+}
 
 pub struct SecretKeyOwned {
     pub key: SecretKey,
@@ -107,6 +357,32 @@ impl SecretKeyOwned {
             address,
         })
     }
+    /// Decrypts a Web3 Secret Storage (keystore v3) JSON file
+    pub fn new_from_keystore(path: impl AsRef<Path>, password: &str) -> Result<Self> {
+        let keystore = KeystoreV3::load(path)?;
+        Self::new_from_keystore_v3(&keystore, password)
+    }
+    pub fn new_from_keystore_v3(keystore: &KeystoreV3, password: &str) -> Result<Self> {
+        let this = Self::new_from_private_exponent(&keystore.decrypt(password)?)?;
+        if let Some(address) = &keystore.address {
+            let expected = Address::from_str(address)
+                .with_context(|| format!("keystore address {}", address))?;
+            ensure!(
+                expected == this.address,
+                "keystore address {:?} does not match key address {:?}",
+                expected,
+                this.address
+            );
+        }
+        Ok(this)
+    }
+    pub fn to_keystore(&self, password: &str, kdf: KdfParams) -> Result<KeystoreV3> {
+        let secret = PrivateExpontent {
+            content: self.key.serialize_secret().to_vec(),
+        };
+        let keystore = KeystoreV3::encrypt(&secret, password, kdf)?;
+        Ok(keystore.with_address(&format!("{:?}", self.address)))
+    }
     pub fn new(key: SecretKey) -> Self {
         let pubkey = secp256k1::PublicKey::from_secret_key(&*CONTEXT, &key);
         let address = utils::eth_public_exponent_to_address(&PublicExpontent {
@@ -126,11 +402,7 @@ impl PublicKey for SecretKeyOwned {
     }
 
     fn public_exponent(&self) -> Result<PublicExpontent> {
-        self.pubkey
-            .serialize_uncompressed()
-            .to_vec()
-            .try_into()
-            .map_err(|_| eyre!("Failed to convert public exponent"))
+        Ok(self.pubkey.serialize_uncompressed().to_vec().into())
     }
 
     fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
@@ -237,6 +509,7 @@ mod test {
     use super::*;
     use crate::utils::{eth_public_exponent_to_address, setup_logs};
     use crypto::openssl::OpensslPrivateKey;
+    use crypto::secp256k1::Secp256k1Key;
     use crypto::PrivateKey;
     use secp256k1::SecretKey;
     use web3::signing::{keccak256, Key};
@@ -251,13 +524,83 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_keystore_roundtrip() -> Result<()> {
+        let key = SecretKey::from_str(
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d",
+        )?;
+        let key = SecretKeyOwned::new(key);
+        assert_eq!(
+            format!("{:?}", key.address),
+            "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b"
+        );
+        let keystore = key.to_keystore("testpassword", KdfParams::scrypt_light())?;
+        let decrypted = SecretKeyOwned::new_from_keystore_v3(&keystore, "testpassword")?;
+        assert_eq!(decrypted.address, key.address);
+        assert!(SecretKeyOwned::new_from_keystore_v3(&keystore, "wrong").is_err());
+        Ok(())
+    }
+
+    /// Signs like an HSM that never normalizes, always returning the high `s`
+    struct HighS(Secp256k1Key);
+    impl PublicKey for HighS {
+        fn public_key(&self) -> Result<DerPublicKey> {
+            self.0.public_key()
+        }
+        fn public_exponent(&self) -> Result<PublicExpontent> {
+            self.0.public_exponent()
+        }
+        fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+            self.0.verify(data, signature)
+        }
+    }
+    #[async_trait::async_trait]
+    impl Signer for HighS {
+        async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.0.sign(data).await
+        }
+        async fn sign_compact(&self, data: &[u8]) -> Result<Vec<u8>> {
+            let mut signature = self.0.sign_compact(data).await?;
+            let s = U256::from_big_endian(&signature[32..]);
+            (*SECP256K1_N - s).to_big_endian(&mut signature[32..]);
+            Ok(signature)
+        }
+    }
+    static SECP256K1_N: Lazy<U256> = Lazy::new(|| {
+        U256::from_str("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141").unwrap()
+    });
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_low_s() -> Result<()> {
+        let key = Secp256k1Key::from_hex(
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d",
+        )?;
+        let signer = EthereumSigner::new(Arc::new(HighS(key)))?;
+        for i in 0u32..64 {
+            let message = keccak256(&i.to_be_bytes());
+            for signature in [
+                signer.sign(&message, Some(1))?,
+                signer.sign_message(&message)?,
+            ] {
+                assert!(U256::from_big_endian(signature.s.as_bytes()) <= *SECP256K1_N / 2);
+            }
+            let signature = signer.sign_message(&message)?;
+            let compact = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+            assert_eq!(
+                recover(&message, &compact, signature.v as i32)?,
+                signer.address
+            );
+        }
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_btc_sign_messages() -> Result<()> {
         setup_logs()?;
         let key = OpensslPrivateKey::new_secp256k1_sha256("test_eth_key")?;
         println!("Private key {}", hex::encode(key.private_key()?.content));
         let key2 = &SecretKey::from_slice(&key.private_exponent()?.content)?;
-        let key_owned = SecretKeyOwned::new(key2.clone());
+        let key_owned = SecretKeyOwned::new(*key2);
         let msg = keccak256(b"hello world");
         let sig2 = key2.sign_message(&msg)?;
         println!("sig2: {:?}", sig2.v);
@@ -272,7 +615,7 @@ mod test {
         let key = OpensslPrivateKey::new_secp256k1_none("test_eth_key")?;
         println!("Private key {}", hex::encode(key.private_key()?.content));
         let key2 = &SecretKey::from_slice(&key.private_exponent()?.content)?;
-        let key3 = SecretKeyOwned::new(key2.clone());
+        let key3 = SecretKeyOwned::new(*key2);
         let key1 = EthereumSigner::new(Arc::new(key))?;
         let msg = keccak256(b"hello world");
         let sig3 = key3.sign_message(&msg)?;
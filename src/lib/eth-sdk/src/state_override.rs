@@ -0,0 +1,317 @@
+//! State overrides of `eth_call`, to simulate against balances, code and storage that do not
+//! exist on chain, see <https://geth.ethereum.org/docs/interacting-with-geth/rpc/ns-eth#eth-call>
+use crate::block::{self, BlockRef};
+use eyre::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use web3::contract::Contract;
+use web3::ethabi::Token;
+use web3::signing::keccak256;
+use web3::types::{Address, Bytes, H256, U256, U64};
+use web3::{Transport, Web3};
+
+const ERC20_ABI: &str = include_str!("erc20.abi.json");
+/// Mappings are looked for at slots below this, where every layout without gaps puts them
+const PROBED_SLOTS: u64 = 100;
+/// Candidate `i` of a probe is set to `PROBE_VALUE + i`
+pub(crate) const PROBE_VALUE: U256 = U256([0, 0, 0x5eed_5eed, 0]);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    /// Replaces the whole storage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<BTreeMap<H256, H256>>,
+    /// Changes these slots only
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub state_diff: BTreeMap<H256, H256>,
+}
+
+/// The third parameter of `eth_call`, by account
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct StateOverride(BTreeMap<Address, AccountOverride>);
+
+impl StateOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn account(&mut self, address: Address) -> &mut AccountOverride {
+        self.0.entry(address).or_default()
+    }
+    /// ETH balance in wei
+    pub fn balance(mut self, address: Address, wei: U256) -> Self {
+        self.account(address).balance = Some(wei);
+        self
+    }
+    pub fn code(mut self, address: Address, code: Vec<u8>) -> Self {
+        self.account(address).code = Some(code.into());
+        self
+    }
+    pub fn storage(mut self, address: Address, slot: H256, value: U256) -> Self {
+        self.account(address)
+            .state_diff
+            .insert(slot, u256_to_h256(value));
+        self
+    }
+    /// Gives `holder` `amount` of `token`, with the slots `probe_erc20_slots` found
+    pub fn erc20_balance(
+        self,
+        token: Address,
+        slots: &Erc20Slots,
+        holder: Address,
+        amount: U256,
+    ) -> Self {
+        self.storage(token, slots.balance.key(&[holder]), amount)
+    }
+    /// Lets `spender` move `amount` of the `token` of `owner`
+    pub fn erc20_allowance(
+        self,
+        token: Address,
+        slots: &Erc20Slots,
+        owner: Address,
+        spender: Address,
+        amount: U256,
+    ) -> Self {
+        self.storage(token, slots.allowance.key(&[owner, spender]), amount)
+    }
+}
+
+fn u256_to_h256(value: U256) -> H256 {
+    let mut slot = H256::zero();
+    value.to_big_endian(slot.as_bytes_mut());
+    slot
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingLayout {
+    /// `keccak256(key . slot)`
+    Solidity,
+    /// `keccak256(slot . key)`
+    Vyper,
+}
+
+/// Where a `mapping(address => ...)` is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappingSlot {
+    pub slot: U256,
+    pub layout: MappingLayout,
+}
+
+impl MappingSlot {
+    /// Slot of `mapping[keys[0]][keys[1]]...`
+    pub fn key(&self, keys: &[Address]) -> H256 {
+        keys.iter().fold(u256_to_h256(self.slot), |slot, key| {
+            let key = H256::from(*key);
+            let (first, second) = match self.layout {
+                MappingLayout::Solidity => (key, slot),
+                MappingLayout::Vyper => (slot, key),
+            };
+            H256(keccak256(&[first.as_bytes(), second.as_bytes()].concat()))
+        })
+    }
+}
+
+/// Base slot of an ERC-7201 namespace, as used by OpenZeppelin 5 upgradeable contracts
+pub fn erc7201_slot(namespace: &str) -> U256 {
+    let id = U256::from_big_endian(&keccak256(namespace.as_bytes())) - 1;
+    let hash = U256::from_big_endian(&keccak256(&u256_to_h256(id).0));
+    hash & !U256::from(0xff)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Erc20Slots {
+    pub balance: MappingSlot,
+    pub allowance: MappingSlot,
+}
+
+pub(crate) fn candidate_slots() -> Vec<MappingSlot> {
+    let erc7201 = erc7201_slot("openzeppelin.storage.ERC20");
+    let slots = (0..PROBED_SLOTS)
+        .map(U256::from)
+        .chain([erc7201, erc7201 + 1]);
+    slots
+        .flat_map(|slot| {
+            [MappingLayout::Solidity, MappingLayout::Vyper]
+                .map(|layout| MappingSlot { slot, layout })
+        })
+        .collect()
+}
+
+/// Finds which candidate `func` reads `mapping[keys...]` from: each is overridden with another
+/// value in a single call, then the one returned is checked alone with yet another value
+async fn probe_mapping<T: Transport>(
+    web3: &Web3<T>,
+    contract: &Contract<T>,
+    func: &str,
+    keys: &[Address],
+    block: Option<BlockRef>,
+) -> Result<MappingSlot> {
+    let candidates = candidate_slots();
+    let address = contract.address();
+    let params: Vec<_> = keys.iter().copied().map(Token::Address).collect();
+    let params = &params[..];
+    let overrides =
+        candidates
+            .iter()
+            .enumerate()
+            .fold(StateOverride::new(), |overrides, (i, candidate)| {
+                overrides.storage(address, candidate.key(keys), PROBE_VALUE + i)
+            });
+    let query = |overrides| async move {
+        let value: U256 =
+            block::query_with_overrides(web3, contract, None, func, params, block, &overrides)
+                .await?;
+        Ok::<_, Error>(value)
+    };
+    let value = query(overrides).await?;
+    let found = value
+        .checked_sub(PROBE_VALUE)
+        .filter(|i| *i < U256::from(candidates.len()))
+        .map(|i| candidates[i.as_usize()])
+        .with_context(|| {
+            format!(
+                "{} of {:?} is not read from a mapping at the usual slots",
+                func, address
+            )
+        })?;
+    let check = PROBE_VALUE - 1;
+    let value = query(StateOverride::new().storage(address, found.key(keys), check)).await?;
+    ensure!(
+        value == check,
+        "{} of {:?} is not read from slot {} alone",
+        func,
+        address,
+        found.slot
+    );
+    Ok(found)
+}
+
+/// Finds where `token` keeps balances and allowances, so that `StateOverride::erc20_balance`
+/// and `erc20_allowance` can fake them. Fails for tokens that compute balances, e.g. rebasing
+/// ones, or keep them outside the slots probed
+pub async fn probe_erc20_slots<T: Transport>(
+    web3: &Web3<T>,
+    token: Address,
+    block: Option<BlockRef>,
+) -> Result<Erc20Slots> {
+    let contract = Contract::from_json(web3.eth(), token, ERC20_ABI.as_bytes())?;
+    let (owner, spender) = (Address::repeat_byte(0xa1), Address::repeat_byte(0xa2));
+    let balance = probe_mapping(web3, &contract, "balanceOf", &[owner], block).await?;
+    let allowance = probe_mapping(web3, &contract, "allowance", &[owner, spender], block).await?;
+    Ok(Erc20Slots { balance, allowance })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use crate::utils::function_selector;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_state_override() -> Result<()> {
+        let account = Address::repeat_byte(1);
+        let overrides = StateOverride::new()
+            .balance(account, U256::exp10(18))
+            .code(account, vec![0x60, 0x00])
+            .storage(account, H256::zero(), U256::from(7));
+        assert_eq!(
+            serde_json::to_value(&overrides)?,
+            json!({format!("{:?}", account): {
+                "balance": "0xde0b6b3a7640000",
+                "code": "0x6000",
+                "stateDiff": {format!("{:?}", H256::zero()): format!("{:?}", u256_to_h256(7.into()))},
+            }})
+        );
+        // OpenZeppelin's ERC20StorageLocation
+        assert_eq!(
+            u256_to_h256(erc7201_slot("openzeppelin.storage.ERC20")),
+            H256::from_str("52c63247e1f47db19d5ce0460030c497f067ca4cebf71ba98eeadabe20bace00")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapping_key() {
+        let (owner, spender) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let slot = |x: u64| u256_to_h256(x.into());
+        let hash = |a: H256, b: H256| H256(keccak256(&[a.0, b.0].concat()));
+        let solidity = MappingSlot {
+            slot: 3.into(),
+            layout: MappingLayout::Solidity,
+        };
+        assert_eq!(
+            solidity.key(&[owner, spender]),
+            hash(spender.into(), hash(owner.into(), slot(3)))
+        );
+        let vyper = MappingSlot {
+            layout: MappingLayout::Vyper,
+            ..solidity
+        };
+        assert_eq!(vyper.key(&[owner]), hash(slot(3), owner.into()));
+    }
+
+    #[tokio::test]
+    async fn test_probe_erc20_slots() -> Result<()> {
+        let mock = MockTransport::new();
+        let token = Address::repeat_byte(9);
+        let candidates = candidate_slots();
+        let value = |x: U256| [Token::Uint(x)];
+        // balances at slot 0 with Solidity's layout, allowances at slot 4 with Vyper's
+        mock.expect_call(function_selector("balanceOf(address)"), &value(PROBE_VALUE));
+        mock.expect_call(
+            function_selector("balanceOf(address)"),
+            &value(PROBE_VALUE - 1),
+        );
+        mock.expect_call(
+            function_selector("allowance(address,address)"),
+            &value(PROBE_VALUE + 9),
+        );
+        mock.expect_call(
+            function_selector("allowance(address,address)"),
+            &value(PROBE_VALUE - 1),
+        );
+        let slots = probe_erc20_slots(&mock.web3(), token, None).await?;
+        assert_eq!(slots.balance, candidates[0]);
+        assert_eq!(
+            slots.allowance,
+            MappingSlot {
+                slot: 4.into(),
+                layout: MappingLayout::Vyper
+            }
+        );
+        let requests = mock.requests();
+        assert_eq!(
+            requests[0].1[2][format!("{:?}", token)]["stateDiff"]
+                .as_object()
+                .unwrap()
+                .len(),
+            candidates.len()
+        );
+        let holder = Address::repeat_byte(0xa1);
+        let checked = &requests[1].1[2][format!("{:?}", token)]["stateDiff"];
+        assert_eq!(
+            checked,
+            &json!({format!("{:?}", slots.balance.key(&[holder])): format!("{:?}", u256_to_h256(PROBE_VALUE - 1))})
+        );
+
+        mock.expect_erc20_slots(slots);
+        assert_eq!(probe_erc20_slots(&mock.web3(), token, None).await?, slots);
+
+        // a computed balance matches no candidate
+        mock.expect_call(function_selector("balanceOf(address)"), &value(5.into()));
+        assert!(probe_erc20_slots(&mock.web3(), token, None).await.is_err());
+        Ok(())
+    }
+}
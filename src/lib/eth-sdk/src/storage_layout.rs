@@ -0,0 +1,307 @@
+//! Solidity storage layouts, as in the `storageLayout` of Forge artifacts built with
+//! `extra_output = ["storageLayout"]`, and whether one can replace another behind a proxy
+use eyre::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageVariable {
+    pub contract: String,
+    pub label: String,
+    /// Decimal, solc writes it as a string
+    pub slot: String,
+    pub offset: u64,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageType {
+    pub encoding: String,
+    pub label: String,
+    pub number_of_bytes: String,
+    pub members: Option<Vec<StorageVariable>>,
+    pub key: Option<String>,
+    pub value: Option<String>,
+    pub base: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StorageLayout {
+    pub storage: Vec<StorageVariable>,
+    #[serde(default)]
+    pub types: BTreeMap<String, StorageType>,
+}
+
+impl StorageLayout {
+    /// Byte range of `var` in storage, counting 32 bytes per slot
+    fn range(&self, var: &StorageVariable) -> Result<(u128, u128)> {
+        let slot: u128 = var
+            .slot
+            .parse()
+            .with_context(|| format!("slot of {}: {}", var.label, var.slot))?;
+        let size: u128 = match self.types.get(&var.ty) {
+            Some(ty) => ty
+                .number_of_bytes
+                .parse()
+                .with_context(|| format!("size of {}: {}", ty.label, ty.number_of_bytes))?,
+            None => bail!("type {} of {} is not in the layout", var.ty, var.label),
+        };
+        let start = slot * 32 + var.offset as u128;
+        Ok((start, start + size))
+    }
+    /// Type ids embed AST ids that change between builds, so types are compared by what they
+    /// are rather than by id
+    fn same_type(&self, ty: &str, new: &StorageLayout, new_ty: &str) -> bool {
+        self.same_type_visited(ty, new, new_ty, &mut HashSet::new())
+    }
+    /// Pairs in `visited` are assumed the same, so that recursive types such as a struct with
+    /// an array of itself end
+    fn same_type_visited<'a>(
+        &'a self,
+        ty: &'a str,
+        new: &'a StorageLayout,
+        new_ty: &'a str,
+        visited: &mut HashSet<(&'a str, &'a str)>,
+    ) -> bool {
+        let (a, b) = match (self.types.get(ty), new.types.get(new_ty)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return ty == new_ty,
+        };
+        if !visited.insert((ty, new_ty)) {
+            return true;
+        }
+        if a.label != b.label || a.encoding != b.encoding || a.number_of_bytes != b.number_of_bytes
+        {
+            return false;
+        }
+        for (x, y) in [(&a.key, &b.key), (&a.value, &b.value), (&a.base, &b.base)] {
+            let same = match (x, y) {
+                (Some(x), Some(y)) => self.same_type_visited(x, new, y, visited),
+                (None, None) => true,
+                _ => false,
+            };
+            if !same {
+                return false;
+            }
+        }
+        match (&a.members, &b.members) {
+            (Some(x), Some(y)) => {
+                x.len() == y.len()
+                    && x.iter().zip(y).all(|(x, y)| {
+                        x.label == y.label
+                            && x.slot == y.slot
+                            && x.offset == y.offset
+                            && self.same_type_visited(&x.ty, new, &y.ty, visited)
+                    })
+            }
+            (None, None) => true,
+            _ => false,
+        }
+    }
+    /// Why `new` cannot replace `self` behind a proxy, empty if it can. Every variable must
+    /// keep its slot, offset, name and type. New ones go after the old layout or inside an old
+    /// `__gap`
+    pub fn upgrade_errors(&self, new: &StorageLayout) -> Result<Vec<String>> {
+        let is_gap = |var: &StorageVariable| var.label.starts_with("__gap");
+        let same_place =
+            |x: &StorageVariable, y: &StorageVariable| x.slot == y.slot && x.offset == y.offset;
+        let mut errors = vec![];
+        let mut end = 0;
+        let mut gaps = vec![];
+        for var in &self.storage {
+            let range = self.range(var)?;
+            end = end.max(range.1);
+            if is_gap(var) {
+                gaps.push(range);
+                continue;
+            }
+            match new.storage.iter().find(|x| same_place(x, var)) {
+                None => errors.push(format!(
+                    "{}.{} is no longer at slot {} offset {}",
+                    var.contract, var.label, var.slot, var.offset
+                )),
+                Some(x) if x.label != var.label => errors.push(format!(
+                    "{}.{} at slot {} was renamed to {}",
+                    var.contract, var.label, var.slot, x.label
+                )),
+                Some(x) if !self.same_type(&var.ty, new, &x.ty) => errors.push(format!(
+                    "{}.{} changed type from {} to {}",
+                    var.contract,
+                    var.label,
+                    self.types.get(&var.ty).map_or(&var.ty, |x| &x.label),
+                    new.types.get(&x.ty).map_or(&x.ty, |x| &x.label)
+                )),
+                Some(_) => {}
+            }
+        }
+        for var in &new.storage {
+            let kept = self
+                .storage
+                .iter()
+                .any(|x| !is_gap(x) && same_place(x, var));
+            if kept {
+                continue;
+            }
+            let (start, stop) = new.range(var)?;
+            let in_gap = gaps.iter().any(|gap| start >= gap.0 && stop <= gap.1);
+            if start < end && !in_gap {
+                errors.push(format!(
+                    "{}.{} at slot {} overlaps the old layout",
+                    var.contract, var.label, var.slot
+                ));
+            }
+        }
+        Ok(errors)
+    }
+    pub fn check_upgrade(&self, new: &StorageLayout) -> Result<()> {
+        let errors = self.upgrade_errors(new)?;
+        ensure!(
+            errors.is_empty(),
+            "incompatible storage layout: {}",
+            errors.join("; ")
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layout(vars: &[(&str, u64, u64, &str)]) -> StorageLayout {
+        let storage: Vec<_> = vars
+            .iter()
+            .map(|(label, slot, offset, ty)| {
+                json!({
+                    "astId": 1,
+                    "contract": "src/Pool.sol:Pool",
+                    "label": label,
+                    "offset": offset,
+                    "slot": slot.to_string(),
+                    "type": ty,
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({
+            "storage": storage,
+            "types": {
+                "t_address": {"encoding": "inplace", "label": "address", "numberOfBytes": "20"},
+                "t_bool": {"encoding": "inplace", "label": "bool", "numberOfBytes": "1"},
+                "t_uint256": {"encoding": "inplace", "label": "uint256", "numberOfBytes": "32"},
+                "t_array(t_uint256)10_storage": {
+                    "encoding": "inplace",
+                    "label": "uint256[10]",
+                    "numberOfBytes": "320",
+                    "base": "t_uint256"
+                },
+                "t_array(t_uint256)9_storage": {
+                    "encoding": "inplace",
+                    "label": "uint256[9]",
+                    "numberOfBytes": "288",
+                    "base": "t_uint256"
+                },
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_upgrade_errors() -> Result<()> {
+        let old = layout(&[
+            ("owner", 0, 0, "t_address"),
+            ("paused", 0, 20, "t_bool"),
+            ("supply", 1, 0, "t_uint256"),
+            ("__gap", 2, 0, "t_array(t_uint256)10_storage"),
+        ]);
+        // appended after the gap, and carved out of it
+        let appended = layout(&[
+            ("owner", 0, 0, "t_address"),
+            ("paused", 0, 20, "t_bool"),
+            ("supply", 1, 0, "t_uint256"),
+            ("fee", 2, 0, "t_uint256"),
+            ("__gap", 3, 0, "t_array(t_uint256)9_storage"),
+            ("extra", 12, 0, "t_uint256"),
+        ]);
+        assert!(old.upgrade_errors(&appended)?.is_empty());
+        old.check_upgrade(&appended)?;
+
+        let inserted = layout(&[
+            ("owner", 0, 0, "t_address"),
+            ("fee", 1, 0, "t_uint256"),
+            ("supply", 2, 0, "t_uint256"),
+        ]);
+        let errors = old.upgrade_errors(&inserted)?;
+        assert!(errors.iter().any(|x| x.contains("paused is no longer")));
+        assert!(errors
+            .iter()
+            .any(|x| x.contains("supply at slot 1 was renamed to fee")));
+
+        let retyped = layout(&[
+            ("owner", 0, 0, "t_uint256"),
+            ("paused", 0, 20, "t_bool"),
+            ("supply", 1, 0, "t_uint256"),
+        ]);
+        let errors = old.upgrade_errors(&retyped)?;
+        assert_eq!(
+            errors,
+            vec!["src/Pool.sol:Pool.owner changed type from address to uint256"]
+        );
+
+        // the gap was not shrunk to make room
+        let overflowing = layout(&[
+            ("owner", 0, 0, "t_address"),
+            ("paused", 0, 20, "t_bool"),
+            ("supply", 1, 0, "t_uint256"),
+            ("fee", 2, 0, "t_uint256"),
+            ("__gap", 3, 0, "t_array(t_uint256)10_storage"),
+        ]);
+        assert!(old.check_upgrade(&overflowing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursive_type() -> Result<()> {
+        // struct Node { uint256 value; Node[] children; }, with AST ids that differ per build
+        let tree = |id: u64, value: &str| -> StorageLayout {
+            let node = format!("t_struct(Node){}_storage", id);
+            let children = format!("t_array({})dyn_storage", node);
+            let member = |label: &str, slot: u64, ty: &str| {
+                json!({
+                    "astId": id,
+                    "contract": "src/Tree.sol:Tree",
+                    "label": label,
+                    "offset": 0,
+                    "slot": slot.to_string(),
+                    "type": ty,
+                })
+            };
+            serde_json::from_value(json!({
+                "storage": [member("root", 0, &node)],
+                "types": {
+                    value: {"encoding": "inplace", "label": value, "numberOfBytes": "32"},
+                    node.clone(): {
+                        "encoding": "inplace",
+                        "label": "struct Tree.Node",
+                        "numberOfBytes": "64",
+                        "members": [member("value", 0, value), member("children", 1, &children)]
+                    },
+                    children: {
+                        "encoding": "dynamic_array",
+                        "label": "struct Tree.Node[]",
+                        "numberOfBytes": "32",
+                        "base": node
+                    },
+                }
+            }))
+            .unwrap()
+        };
+        tree(3, "t_uint256").check_upgrade(&tree(7, "t_uint256"))?;
+        assert!(tree(3, "t_uint256")
+            .check_upgrade(&tree(7, "t_int256"))
+            .is_err());
+        Ok(())
+    }
+}
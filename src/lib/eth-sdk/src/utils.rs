@@ -1,13 +1,18 @@
+use crate::amount::Amount;
+use crypto::hdwallet::derive_public_exponent;
 use eyre::*;
+use once_cell::sync::OnceCell;
 use secp256k1::PublicKey;
 use std::time::Duration;
 use tracing::level_filters::LevelFilter;
 use tracing_log::LogTracer;
 use tracing_subscriber::{fmt, EnvFilter};
 use web3::api::Eth;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
 use web3::signing::keccak256;
-use web3::types::{Address, TransactionReceipt, H256, U256};
-use web3::Transport;
+use web3::types::{Address, CallRequest, TransactionReceipt, H256, U256};
+use web3::{Transport, Web3};
 pub fn eth_public_exponent_to_address(
     public_exponent: &crypto::PublicExpontent,
 ) -> Result<Address> {
@@ -25,24 +30,49 @@ pub fn eth_public_exponent_to_address(
     Ok(Address::from_slice(&hash[12..]))
 }
 
-pub fn wei_to_eth(wei_val: web3::types::U256) -> f64 {
-    let u = U256::from_str_radix("1000000000000000000", 10).unwrap();
-    let n = wei_val / u;
-    let f = wei_val % u;
-    (n.as_u128() as f64) + f.as_u128() as f64 / 1e18
+/// Address of account `index` under an extended public key, see `HdWallet::ethereum_xpub`
+pub fn eth_address_from_xpub(xpub: &str, index: u32) -> Result<Address> {
+    eth_public_exponent_to_address(&derive_public_exponent(xpub, index)?)
 }
 
+/// First 4 bytes of the keccak of a signature such as `transfer(address,uint256)`
+pub fn function_selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+pub fn wei_to_eth(wei_val: U256) -> Amount {
+    Amount::from_wei(wei_val)
+}
+pub fn eth_to_wei(eth: &str) -> Result<U256> {
+    Ok(Amount::parse_eth(eth)?.value())
+}
+
+/// Anvil and Hardhat fund the first accounts of this mnemonic
+#[cfg(any(test, feature = "test-utils"))]
+pub const TEST_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
 // for testing only
+#[cfg(any(test, feature = "test-utils"))]
+pub fn test_signer(index: u32) -> Result<crate::signer::EthereumSigner> {
+    crate::signer::EthereumSigner::new_from_mnemonic(TEST_MNEMONIC, "", index)
+}
+
+// for testing only, later calls do nothing so that every test may call it
 pub fn setup_logs() -> Result<()> {
-    LogTracer::init().context("Cannot setup_logs")?;
-    let filter = EnvFilter::from_default_env().add_directive(LevelFilter::TRACE.into());
+    static LOGS: OnceCell<()> = OnceCell::new();
+    LOGS.get_or_try_init(|| {
+        // quickcheck may have installed its own `log` logger already, which is fine
+        let _ = LogTracer::init();
+        let filter = EnvFilter::from_default_env().add_directive(LevelFilter::TRACE.into());
 
-    let subscriber = fmt()
-        .with_thread_names(true)
-        .with_env_filter(filter)
-        .finish();
+        let subscriber = fmt()
+            .with_thread_names(true)
+            .with_env_filter(filter)
+            .finish();
 
-    tracing::subscriber::set_global_default(subscriber).context("Cannot setup_logs")?;
+        tracing::subscriber::set_global_default(subscriber).context("Cannot setup_logs")
+    })?;
     Ok(())
 }
 
@@ -69,8 +99,66 @@ where
     )
 }
 
+/// Waits for the receipt of `hash`, failing if the transaction reverted
+pub async fn wait_for_success<T: Transport>(
+    eth: &Eth<T>,
+    hash: H256,
+) -> Result<TransactionReceipt> {
+    wait_for_success_with(eth, hash, Duration::from_secs(3), 10).await
+}
+
+/// Same as `wait_for_success`, polling `max_retry` times every `poll_interval`
+pub async fn wait_for_success_with<T: Transport>(
+    eth: &Eth<T>,
+    hash: H256,
+    poll_interval: Duration,
+    max_retry: usize,
+) -> Result<TransactionReceipt> {
+    let receipt = wait_for_confirmations_simple(eth, hash, poll_interval, max_retry).await?;
+    ensure!(
+        receipt.status == Some(1.into()),
+        "transaction {:?} reverted",
+        hash
+    );
+    Ok(receipt)
+}
+
+pub fn encode_call<T: Transport>(
+    contract: &Contract<T>,
+    func: &str,
+    params: impl Tokenize,
+) -> Result<Vec<u8>> {
+    Ok(contract
+        .abi()
+        .function(func)?
+        .encode_input(&params.into_tokens())?)
+}
+
+/// Runs `func` as `from` through `eth_call`, returning the raw output or the revert
+pub async fn simulate_call<T: Transport>(
+    web3: &Web3<T>,
+    contract: &Contract<T>,
+    from: Address,
+    func: &str,
+    params: impl Tokenize,
+) -> Result<Vec<u8>> {
+    let call = CallRequest {
+        from: Some(from),
+        to: Some(contract.address()),
+        data: Some(encode_call(contract, func, params)?.into()),
+        ..Default::default()
+    };
+    let output = web3
+        .eth()
+        .call(call, None)
+        .await
+        .with_context(|| format!("simulating {} on {:?}", func, contract.address()))?;
+    Ok(output.0)
+}
+
 #[cfg(test)]
 mod tests {
+    use crypto::hdwallet::HdWallet;
     use crypto::openssl::OpensslPrivateKey;
     use crypto::PublicKey;
     use eyre::*;
@@ -83,4 +171,17 @@ mod tests {
         println!("address: {}", address);
         Ok(())
     }
+
+    #[test]
+    fn test_eth_address_from_xpub() -> Result<()> {
+        let wallet = HdWallet::from_mnemonic(super::TEST_MNEMONIC, "")?;
+        let xpub = wallet.ethereum_xpub()?;
+        let address = super::eth_address_from_xpub(&xpub, 0)?;
+        assert_eq!(
+            format!("{:?}", address),
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266"
+        );
+        assert_eq!(address, super::test_signer(0)?.address);
+        Ok(())
+    }
 }
@@ -0,0 +1,216 @@
+//! Whether the code at an address is what a build artifact deploys
+use crate::artifact::{Artifact, ImmutableReferences, LinkOffset, LinkReferences};
+use crate::block::{self, BlockRef};
+use crate::contract::ForgeJsonOutput;
+use crate::deployments::Deployments;
+use eyre::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use web3::types::Address;
+use web3::{Transport, Web3};
+
+/// Runtime code of an artifact, with the bytes that vary between deployments masked: library
+/// addresses and immutables, which are only known once deployed
+#[derive(Debug, Clone)]
+pub struct ExpectedCode {
+    code: Vec<u8>,
+    masked: Vec<LinkOffset>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Verification {
+    Match,
+    /// `offset` is the first byte that differs, ignoring the metadata hash
+    Mismatch {
+        offset: usize,
+        expected_len: usize,
+        actual_len: usize,
+    },
+    NoCode,
+    /// The deployments file has a contract no artifact was given for
+    NoArtifact,
+}
+
+impl Verification {
+    pub fn is_match(&self) -> bool {
+        matches!(self, Verification::Match)
+    }
+}
+
+impl ExpectedCode {
+    pub fn new(
+        code: &str,
+        link_references: &LinkReferences,
+        immutable_references: &ImmutableReferences,
+    ) -> Result<Self> {
+        let code = code.trim().trim_matches('"');
+        let mut code = code.strip_prefix("0x").unwrap_or(code).to_owned();
+        let mut masked = vec![];
+        for offset in link_references.values().flat_map(|x| x.values()).flatten() {
+            let range = offset.start * 2..(offset.start + offset.length) * 2;
+            ensure!(
+                range.end <= code.len(),
+                "bad link reference at {:?}",
+                offset
+            );
+            code.replace_range(range, &"0".repeat(offset.length * 2));
+            masked.push(*offset);
+        }
+        let mut code = hex::decode(&code).context("deployed bytecode is not hex")?;
+        for offset in immutable_references.values().flatten() {
+            let range = offset.start..offset.start + offset.length;
+            ensure!(
+                range.end <= code.len(),
+                "bad immutable reference at {:?}",
+                offset
+            );
+            code[range].fill(0);
+            masked.push(*offset);
+        }
+        ensure!(!code.is_empty(), "the artifact has no deployed bytecode");
+        Ok(Self { code, masked })
+    }
+    pub fn from_artifact(artifact: &Artifact) -> Result<Self> {
+        Self::new(
+            &artifact.deployed_bytecode,
+            &artifact.deployed_link_references,
+            &artifact.deployed_immutable_references,
+        )
+    }
+    pub fn from_forge(output: &ForgeJsonOutput) -> Result<Self> {
+        let code = &output.deployed_bytecode;
+        Self::new(
+            &code.object,
+            &serde_json::from_value(code.link_references.clone())?,
+            &code.immutable_references,
+        )
+    }
+    /// Compares with `actual`, the result of `eth_getCode`
+    pub fn compare(&self, actual: &[u8]) -> Verification {
+        if actual.is_empty() {
+            return Verification::NoCode;
+        }
+        let mut actual = actual.to_vec();
+        for offset in &self.masked {
+            if let Some(bytes) = actual.get_mut(offset.start..offset.start + offset.length) {
+                bytes.fill(0);
+            }
+        }
+        let expected = strip_metadata(&self.code);
+        let actual = strip_metadata(&actual);
+        if expected == actual {
+            return Verification::Match;
+        }
+        let offset = expected
+            .iter()
+            .zip(actual)
+            .position(|(x, y)| x != y)
+            .unwrap_or_else(|| expected.len().min(actual.len()));
+        Verification::Mismatch {
+            offset,
+            expected_len: expected.len(),
+            actual_len: actual.len(),
+        }
+    }
+    /// Compares with the code at `address`
+    pub async fn verify<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        address: Address,
+        block: Option<BlockRef>,
+    ) -> Result<Verification> {
+        Ok(self.compare(&block::code(web3, address, block).await?))
+    }
+}
+
+/// Cuts the CBOR metadata solc appends to runtime code. Its length is in the last 2 bytes, and
+/// it hashes the sources with their comments and paths, so it differs between equivalent builds
+pub fn strip_metadata(code: &[u8]) -> &[u8] {
+    let len = code.len();
+    if len < 2 {
+        return code;
+    }
+    let metadata_len = u16::from_be_bytes([code[len - 2], code[len - 1]]) as usize;
+    match len.checked_sub(metadata_len + 2) {
+        // a CBOR map
+        Some(start) if code[start] & 0xe0 == 0xa0 => &code[..start],
+        _ => code,
+    }
+}
+
+/// Verifies every contract in `deployments` against its artifact in `expected`, by label
+pub async fn verify_deployments<T: Transport>(
+    web3: &Web3<T>,
+    deployments: &Deployments,
+    expected: &BTreeMap<String, ExpectedCode>,
+    block: Option<BlockRef>,
+) -> Result<BTreeMap<String, Verification>> {
+    let mut results = BTreeMap::new();
+    for (label, deployment) in &deployments.contracts {
+        let result = match expected.get(label) {
+            Some(code) => code
+                .verify(web3, deployment.address, block)
+                .await
+                .with_context(|| format!("verifying {} at {:?}", label, deployment.address))?,
+            None => Verification::NoArtifact,
+        };
+        results.insert(label.clone(), result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PUSH32 <immutable> PUSH20 <library> STOP, then metadata `{"solc": 0x000813}`
+    fn code(immutable: u8, library: u8, solc: u8) -> Vec<u8> {
+        let mut code = vec![0x7f];
+        code.extend([immutable; 32]);
+        code.push(0x73);
+        code.extend([library; 20]);
+        code.push(0x00);
+        code.extend([0xa1, 0x64]);
+        code.extend(b"solc");
+        code.extend([0x43, 0x00, 0x08, solc, 0x00, 0x0a]);
+        code
+    }
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let artifact = serde_json::json!({
+            "abi": [],
+            "bytecode": {"object": "0x", "linkReferences": {}},
+            "deployedBytecode": {
+                "object": format!(
+                    "0x{}{}{}",
+                    hex::encode(&code(0, 0, 0x13)[..34]),
+                    crate::artifact::placeholder("src/Math.sol:Math"),
+                    hex::encode(&code(0, 0, 0x13)[54..])
+                ),
+                "linkReferences": {"src/Math.sol": {"Math": [{"start": 34, "length": 20}]}},
+                "immutableReferences": {"12": [{"start": 1, "length": 32}]}
+            }
+        });
+        let artifact = Artifact::from_json(&artifact.to_string())?;
+        let expected = ExpectedCode::from_artifact(&artifact)?;
+
+        assert_eq!(strip_metadata(&code(1, 2, 0x13)).len(), 55);
+        // other immutables, libraries and compiler version in the metadata
+        assert!(expected.compare(&code(1, 2, 0x11)).is_match());
+        let mut changed = code(1, 2, 0x13);
+        changed[54] = 0xfe;
+        assert_eq!(
+            expected.compare(&changed),
+            Verification::Mismatch {
+                offset: 54,
+                expected_len: 55,
+                actual_len: 55
+            }
+        );
+        assert_eq!(expected.compare(&[]), Verification::NoCode);
+        assert!(!expected.compare(&code(1, 2, 0x13)[..40]).is_match());
+        Ok(())
+    }
+}
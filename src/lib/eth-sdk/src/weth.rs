@@ -0,0 +1,174 @@
+//! WETH9, the ERC-20 wrapper of ETH. StrategyPool deposits only take ERC-20 assets, so ETH has
+//! to be wrapped first
+use crate::block::{self, BlockRef};
+use crate::erc20::{Erc20Metadata, Erc20Token};
+use crate::signer::EthereumSigner;
+use crate::utils::wait_for_success;
+use crate::EthereumNet;
+use eyre::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+use web3::api::Web3;
+use web3::contract::Contract;
+use web3::transports::http::Http;
+use web3::types::{Address, H256, U256};
+
+const WETH9_ABI: &str = include_str!("weth9.abi.json");
+
+/// WETH contract per network. The defaults are the canonical deployments; local chains need
+/// their own through `with_address`
+#[derive(Debug, Clone)]
+pub struct WethConfig {
+    addresses: HashMap<EthereumNet, Address>,
+}
+impl Default for WethConfig {
+    fn default() -> Self {
+        let addresses = [
+            (
+                EthereumNet::Mainnet,
+                "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            ),
+            (
+                EthereumNet::Goerli,
+                "0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6",
+            ),
+            (
+                EthereumNet::Sepolia,
+                "0xfFf9976782d46CC05630D1f6eBAb18b2324d6B14",
+            ),
+            (
+                EthereumNet::Base,
+                "0x4200000000000000000000000000000000000006",
+            ),
+        ]
+        .into_iter()
+        .map(|(net, address)| (net, Address::from_str(address).unwrap()))
+        .collect();
+        Self { addresses }
+    }
+}
+impl WethConfig {
+    pub fn with_address(mut self, net: EthereumNet, address: Address) -> Self {
+        self.addresses.insert(net, address);
+        self
+    }
+    pub fn address(&self, net: EthereumNet) -> Option<Address> {
+        self.addresses.get(&net).copied()
+    }
+    pub fn weth(&self, net: EthereumNet) -> Result<Weth> {
+        let address = self
+            .address(net)
+            .with_context(|| format!("no WETH address configured for {}", net.name()))?;
+        Weth::new(net, address)
+    }
+}
+
+pub struct Weth {
+    client: Web3<Http>,
+    contract: Contract<Http>,
+    token: Erc20Token,
+}
+impl Weth {
+    pub fn new(net: EthereumNet, address: Address) -> Result<Self> {
+        let client = net.client()?;
+        let contract = Contract::from_json(client.eth(), address, WETH9_ABI.as_bytes())?;
+        let token = Erc20Token::new_with_client(net, client.clone(), address)?.with_metadata(
+            Erc20Metadata {
+                name: "Wrapped Ether".to_owned(),
+                symbol: "WETH".to_owned(),
+                decimals: 18,
+            },
+        );
+        Ok(Self {
+            client,
+            contract,
+            token,
+        })
+    }
+    /// The ERC-20 side, for transfers and approvals
+    pub fn token(&self) -> &Erc20Token {
+        &self.token
+    }
+    pub fn address(&self) -> Address {
+        self.token.address()
+    }
+    /// Wrapped balance in wei
+    pub async fn balance_of(&self, owner: Address, block: Option<BlockRef>) -> Result<U256> {
+        self.token.balance_of(owner, block).await
+    }
+    /// Unwrapped balance in wei
+    pub async fn eth_balance(&self, owner: Address, block: Option<BlockRef>) -> Result<U256> {
+        block::balance(&self.client, owner, block).await
+    }
+    /// Wraps `amount` wei
+    pub async fn deposit(&self, by: &EthereumSigner, amount: U256) -> Result<H256> {
+        by.send_call(&self.client, &self.contract, "deposit", (), amount)
+            .await
+    }
+    /// Unwraps `amount` wei
+    pub async fn withdraw(&self, by: &EthereumSigner, amount: U256) -> Result<H256> {
+        let balance = self.balance_of(by.address, None).await?;
+        ensure!(
+            balance >= amount,
+            "{:?} has {} WETH wei, less than {}",
+            by.address,
+            balance,
+            amount
+        );
+        by.send_call(
+            &self.client,
+            &self.contract,
+            "withdraw",
+            amount,
+            U256::zero(),
+        )
+        .await
+    }
+    /// Wraps `amount` wei and lets `pool` spend it, waiting for both. Returns the deposit and,
+    /// if the allowance was not already enough, the approval
+    pub async fn wrap_and_approve(
+        &self,
+        by: &EthereumSigner,
+        amount: U256,
+        pool: Address,
+    ) -> Result<(H256, Option<H256>)> {
+        let deposit = self.deposit(by, amount).await?;
+        wait_for_success(&self.client.eth(), deposit)
+            .await
+            .context("wrapping ETH")?;
+        let approval = self.token.ensure_allowance(by, pool, amount).await?;
+        Ok((deposit, approval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::encode_call;
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let config = WethConfig::default();
+        assert_eq!(
+            config.address(EthereumNet::Base),
+            Some(Address::from_str(
+                "0x4200000000000000000000000000000000000006"
+            )?)
+        );
+        assert!(config.weth(EthereumNet::Local).is_err());
+        let local = Address::from_low_u64_be(1);
+        let weth = config
+            .with_address(EthereumNet::Local, local)
+            .weth(EthereumNet::Local)?;
+        assert_eq!(weth.address(), local);
+        assert_eq!(
+            hex::encode(encode_call(&weth.contract, "deposit", ())?),
+            "d0e30db0"
+        );
+        assert_eq!(
+            hex::encode(&encode_call(&weth.contract, "withdraw", U256::one())?[..4]),
+            "2e1a7d4d"
+        );
+        Ok(())
+    }
+}
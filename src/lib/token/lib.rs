@@ -0,0 +1,47 @@
+use crypto::Signer;
+use eyre::*;
+use std::any::Any;
+use std::sync::Arc;
+
+/// A token of some chain, with amounts and addresses as strings so that chains can be mixed
+#[async_trait::async_trait]
+pub trait CryptoToken: Send + Sync + std::fmt::Debug {
+    fn as_any(&self) -> &dyn Any;
+    fn get_network_type(&self) -> String;
+    fn convert_display_unit_to_internal_unit(&self, amount: &str) -> Result<String>;
+    fn convert_internal_unit_to_display_unit(&self, amount: &str) -> Result<String>;
+    fn public_exponent_to_address(
+        &self,
+        public_exponent: &crypto::PublicExpontent,
+    ) -> Result<String>;
+    fn address_to_public_exponent(&self, address: &str) -> Result<crypto::PublicExpontent>;
+    fn get_address_explorer_url(&self, address: &str) -> String;
+    fn get_transaction_explorer_url(&self, address: &str) -> String;
+    async fn get_balance(&self, addr: &str) -> Result<String>;
+    async fn request_airdrop(&self, addr: &str, amount: &str) -> Result<String>;
+    async fn transfer(
+        &self,
+        fee_payer: Arc<dyn Signer>,
+        by: Arc<dyn Signer>,
+        from: &str,
+        to: &str,
+        amount: &str,
+    ) -> Result<String>;
+    async fn confirm_transaction(&self, hash: &str) -> Result<()>;
+    async fn create_account(
+        &self,
+        fee_payer: Arc<dyn Signer>,
+        owner: &str,
+        account: Arc<dyn Signer>,
+    ) -> Result<String>;
+    async fn get_latest_blockhash(&self) -> Result<String>;
+    async fn mint_to(
+        &self,
+        _fee_payer: Arc<dyn Signer>,
+        _minter: Arc<dyn Signer>,
+        _account: &str,
+        _amount: &str,
+    ) -> Result<String> {
+        bail!("not supported")
+    }
+}
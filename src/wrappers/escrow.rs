@@ -1,12 +1,157 @@
-use web3::types::{
-    Address, BlockNumber, Bytes, CallRequest, SignedTransaction, TransactionParameters, H256, U256,
-};
-
+use crate::wrappers::load_abi;
+use eth_sdk::block::{self, BlockRef};
+use eth_sdk::dry_run::DryRun;
+use eth_sdk::signer::EthereumSigner;
+use eyre::*;
 use web3::contract::Contract;
-use web3::Transport;
+use web3::types::{Address, H256, U256};
+use web3::{Transport, Web3};
+
+const ESCROW_ABI: &[u8] = include_bytes!("../../abi/internal/escrow.json");
 
 #[derive(Debug, Clone)]
 pub struct EscrowContract<T: Transport> {
+    web3: Web3<T>,
     inner: Contract<T>,
-    address: Address,
+}
+
+impl<T: Transport> EscrowContract<T> {
+    pub fn new(web3: Web3<T>, address: Address) -> Result<Self> {
+        let inner = Contract::new(web3.eth(), address, load_abi(ESCROW_ABI)?);
+        Ok(Self { web3, inner })
+    }
+    #[cfg(test)]
+    pub fn address(&self) -> Address {
+        self.inner.address()
+    }
+    pub async fn owner(&self, block: Option<BlockRef>) -> Result<Address> {
+        block::query(&self.web3, &self.inner, "owner", (), block).await
+    }
+    /// Sends `amount` of the escrowed `token` to `recipient`. Only the owner may
+    pub async fn transfer_token_to(
+        &self,
+        by: &EthereumSigner,
+        token: Address,
+        recipient: Address,
+        amount: U256,
+    ) -> Result<H256> {
+        by.send_call(
+            &self.web3,
+            &self.inner,
+            "transferTokenTo",
+            (token, recipient, amount),
+            U256::zero(),
+        )
+        .await
+    }
+    /// What `transfer_token_to` would do, without sending anything
+    pub async fn dry_run_transfer_token_to(
+        &self,
+        by: &EthereumSigner,
+        token: Address,
+        recipient: Address,
+        amount: U256,
+    ) -> Result<DryRun> {
+        by.dry_run_call(
+            &self.web3,
+            &self.inner,
+            "transferTokenTo",
+            (token, recipient, amount),
+            U256::zero(),
+        )
+        .await
+    }
+    pub async fn transfer_ownership(
+        &self,
+        by: &EthereumSigner,
+        new_owner: Address,
+    ) -> Result<H256> {
+        by.send_call(
+            &self.web3,
+            &self.inner,
+            "transferOwnership",
+            new_owner,
+            U256::zero(),
+        )
+        .await
+    }
+    pub async fn dry_run_transfer_ownership(
+        &self,
+        by: &EthereumSigner,
+        new_owner: Address,
+    ) -> Result<DryRun> {
+        by.dry_run_call(
+            &self.web3,
+            &self.inner,
+            "transferOwnership",
+            new_owner,
+            U256::zero(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eth_sdk::mock::MockTransport;
+    use eth_sdk::utils::{function_selector, test_signer};
+    use serde_json::json;
+    use web3::ethabi::Token;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_escrow() -> Result<()> {
+        let mock = MockTransport::new();
+        let address = Address::repeat_byte(1);
+        let escrow = EscrowContract::new(mock.web3(), address)?;
+        let owner = test_signer(0)?;
+
+        mock.expect_call(
+            function_selector("owner()"),
+            &[Token::Address(owner.address)],
+        );
+        assert_eq!(escrow.owner(None).await?, owner.address);
+
+        let (token, recipient) = (Address::repeat_byte(2), Address::repeat_byte(3));
+        escrow
+            .transfer_token_to(&owner, token, recipient, U256::from(100))
+            .await?;
+        let sent = mock.sent();
+        assert_eq!(sent[0].to, Some(address));
+        let abi = load_abi(ESCROW_ABI)?;
+        assert_eq!(
+            sent[0].decode_input(abi.function("transferTokenTo")?)?,
+            vec![
+                Token::Address(token),
+                Token::Address(recipient),
+                Token::Uint(100.into())
+            ]
+        );
+
+        mock.expect_revert(
+            "eth_estimateGas",
+            function_selector("transferOwnership(address)"),
+            "Ownable: caller is not the owner",
+        );
+        let error = escrow
+            .transfer_ownership(&test_signer(1)?, recipient)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("caller is not the owner"));
+
+        mock.expect_call(
+            function_selector("transferTokenTo(address,address,uint256)"),
+            &[],
+        );
+        // transferTokenTo returns nothing, which only means success from a contract
+        mock.expect("eth_getCode", json!("0x6080"));
+        let dry_run = escrow
+            .dry_run_transfer_token_to(&owner, token, recipient, U256::from(100))
+            .await?;
+        assert!(dry_run.is_success());
+        assert_eq!(dry_run.transaction.to, Some(address));
+        assert_eq!(dry_run.transaction.nonce, Some(1.into()));
+        assert_eq!(mock.sent().len(), 1);
+        Ok(())
+    }
 }
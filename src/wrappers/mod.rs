@@ -1,3 +1,49 @@
 pub mod escrow;
 pub mod strategy_pool;
 pub mod strategy_pool_factory;
+
+use eyre::*;
+use web3::ethabi;
+use web3::types::U256;
+
+/// ABIs are either a bare JSON array or a Forge artifact with an `abi` field
+pub fn load_abi(json: &[u8]) -> Result<ethabi::Contract> {
+    let value: serde_json::Value = serde_json::from_slice(json)?;
+    let abi = match value.get("abi") {
+        Some(abi) => abi.clone(),
+        None => value,
+    };
+    Ok(serde_json::from_value(abi)?)
+}
+
+/// Two's complement encoding of an `int256`
+pub fn int256(x: i128) -> U256 {
+    if x >= 0 {
+        U256::from(x as u128)
+    } else {
+        !U256::from((-(x + 1)) as u128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_abi() -> Result<()> {
+        // escrow.json is a bare array, the others are Forge artifacts
+        let escrow = load_abi(include_bytes!("../../abi/internal/escrow.json"))?;
+        assert!(escrow.function("transferTokenTo").is_ok());
+        let pool = load_abi(include_bytes!("../../abi/internal/strategy_pool.json"))?;
+        assert!(pool.function("changeStrategy").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_int256() {
+        assert_eq!(int256(300), U256::from(300));
+        assert_eq!(int256(-1), U256::MAX);
+        assert_eq!(int256(-300).overflowing_add(300.into()).0, U256::zero());
+        assert_eq!(int256(i128::MIN), !U256::from(i128::MAX as u128));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::wrappers::{int256, load_abi};
+use eth_sdk::block::{self, BlockRef};
+use eth_sdk::dry_run::DryRun;
+use eth_sdk::signer::EthereumSigner;
+use eth_sdk::state_override::{probe_erc20_slots, StateOverride};
+use eyre::*;
+use std::collections::BTreeMap;
+use web3::contract::Contract;
+use web3::ethabi::Token;
+use web3::types::{Address, H256, U256};
+use web3::{Transport, Web3};
+
+const STRATEGY_POOL_ABI: &[u8] = include_bytes!("../../abi/internal/strategy_pool.json");
+
+fn deposit_args(
+    assets: Vec<Address>,
+    amounts: Vec<U256>,
+    receiver: Address,
+) -> Result<(Vec<Address>, Vec<U256>, Address)> {
+    ensure!(
+        assets.len() == amounts.len(),
+        "{} assets for {} amounts",
+        assets.len(),
+        amounts.len()
+    );
+    Ok((assets, amounts, receiver))
+}
+
+fn change_strategy_args(assets: Vec<Address>, deltas: Vec<i128>) -> Result<(Vec<Address>, Token)> {
+    ensure!(
+        assets.len() == deltas.len(),
+        "{} assets for {} deltas",
+        assets.len(),
+        deltas.len()
+    );
+    let deltas = Token::Array(deltas.into_iter().map(|x| Token::Int(int256(x))).collect());
+    Ok((assets, deltas))
+}
+
+#[derive(Debug, Clone)]
+pub struct StrategyPoolContract<T: Transport> {
+    web3: Web3<T>,
+    inner: Contract<T>,
+}
+
+impl<T: Transport> StrategyPoolContract<T> {
+    pub fn new(web3: Web3<T>, address: Address) -> Result<Self> {
+        let inner = Contract::new(web3.eth(), address, load_abi(STRATEGY_POOL_ABI)?);
+        Ok(Self { web3, inner })
+    }
+    /// Deposits `amounts[i]` of `assets[i]`, minting shares to `receiver`. The pool must be
+    /// allowed to spend every asset first
+    pub async fn deposit(
+        &self,
+        by: &EthereumSigner,
+        assets: Vec<Address>,
+        amounts: Vec<U256>,
+        receiver: Address,
+    ) -> Result<H256> {
+        let args = deposit_args(assets, amounts, receiver)?;
+        by.send_call(&self.web3, &self.inner, "deposit", args, U256::zero())
+            .await
+    }
+    /// What `deposit` would do, without sending anything. The output is the shares minted
+    pub async fn dry_run_deposit(
+        &self,
+        by: &EthereumSigner,
+        assets: Vec<Address>,
+        amounts: Vec<U256>,
+        receiver: Address,
+    ) -> Result<DryRun> {
+        let args = deposit_args(assets, amounts, receiver)?;
+        by.dry_run_call(&self.web3, &self.inner, "deposit", args, U256::zero())
+            .await
+    }
+    /// Burns `shares` of `owner`, sending the assets they are worth to `receiver`
+    pub async fn redeem(
+        &self,
+        by: &EthereumSigner,
+        shares: U256,
+        receiver: Address,
+        owner: Address,
+    ) -> Result<H256> {
+        by.send_call(
+            &self.web3,
+            &self.inner,
+            "redeem",
+            (shares, receiver, owner),
+            U256::zero(),
+        )
+        .await
+    }
+    /// What `redeem` would do, without sending anything. The output is the assets paid out
+    pub async fn dry_run_redeem(
+        &self,
+        by: &EthereumSigner,
+        shares: U256,
+        receiver: Address,
+        owner: Address,
+    ) -> Result<DryRun> {
+        by.dry_run_call(
+            &self.web3,
+            &self.inner,
+            "redeem",
+            (shares, receiver, owner),
+            U256::zero(),
+        )
+        .await
+    }
+    /// Moves the pool's holdings by `deltas[i]` of `assets[i]`. Only the owner may
+    pub async fn change_strategy(
+        &self,
+        by: &EthereumSigner,
+        assets: Vec<Address>,
+        deltas: Vec<i128>,
+    ) -> Result<H256> {
+        let args = change_strategy_args(assets, deltas)?;
+        by.send_call(
+            &self.web3,
+            &self.inner,
+            "changeStrategy",
+            args,
+            U256::zero(),
+        )
+        .await
+    }
+    pub async fn dry_run_change_strategy(
+        &self,
+        by: &EthereumSigner,
+        assets: Vec<Address>,
+        deltas: Vec<i128>,
+    ) -> Result<DryRun> {
+        let args = change_strategy_args(assets, deltas)?;
+        by.dry_run_call(
+            &self.web3,
+            &self.inner,
+            "changeStrategy",
+            args,
+            U256::zero(),
+        )
+        .await
+    }
+    /// Shares `deposit` by `holder` would mint, running it with the assets and approvals it
+    /// needs faked through state overrides, so for accounts that hold nothing yet
+    pub async fn simulate_deposit(
+        &self,
+        holder: Address,
+        assets: Vec<Address>,
+        amounts: Vec<U256>,
+        receiver: Address,
+        block: Option<BlockRef>,
+    ) -> Result<U256> {
+        let args = deposit_args(assets, amounts, receiver)?;
+        let mut needed = BTreeMap::<Address, U256>::new();
+        for (asset, amount) in args.0.iter().zip(&args.1) {
+            *needed.entry(*asset).or_default() += *amount;
+        }
+        let mut overrides = StateOverride::new();
+        for (asset, amount) in needed {
+            let slots = probe_erc20_slots(&self.web3, asset, block)
+                .await
+                .with_context(|| format!("faking a balance of {:?}", asset))?;
+            overrides = overrides
+                .erc20_balance(asset, &slots, holder, amount)
+                .erc20_allowance(asset, &slots, holder, self.inner.address(), amount);
+        }
+        block::query_with_overrides(
+            &self.web3,
+            &self.inner,
+            Some(holder),
+            "deposit",
+            args,
+            block,
+            &overrides,
+        )
+        .await
+    }
+    /// Assets `redeem` by `holder` would pay out, running it with the shares faked through
+    /// state overrides. The supply is not, so `shares` may not exceed it
+    pub async fn simulate_redeem(
+        &self,
+        holder: Address,
+        shares: U256,
+        receiver: Address,
+        block: Option<BlockRef>,
+    ) -> Result<(Vec<Address>, Vec<U256>)> {
+        let pool = self.inner.address();
+        let slots = probe_erc20_slots(&self.web3, pool, block).await?;
+        let overrides = StateOverride::new().erc20_balance(pool, &slots, holder, shares);
+        block::query_with_overrides(
+            &self.web3,
+            &self.inner,
+            Some(holder),
+            "redeem",
+            (shares, receiver, holder),
+            block,
+            &overrides,
+        )
+        .await
+    }
+    /// Shares `deposit` would mint
+    pub async fn preview_deposit(
+        &self,
+        assets: Vec<Address>,
+        amounts: Vec<U256>,
+        block: Option<BlockRef>,
+    ) -> Result<U256> {
+        block::query(
+            &self.web3,
+            &self.inner,
+            "previewDeposit",
+            (assets, amounts),
+            block,
+        )
+        .await
+    }
+    /// Assets `redeem` would pay out
+    pub async fn preview_redeem(
+        &self,
+        shares: U256,
+        block: Option<BlockRef>,
+    ) -> Result<(Vec<Address>, Vec<U256>)> {
+        block::query(&self.web3, &self.inner, "previewRedeem", shares, block).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eth_sdk::dry_run::DryRunError;
+    use eth_sdk::mock::MockTransport;
+    use eth_sdk::state_override::{Erc20Slots, MappingLayout, MappingSlot};
+    use eth_sdk::utils::{function_selector, test_signer};
+    use serde_json::{json, Value};
+
+    /// OpenZeppelin 4's `_balances` and `_allowances`
+    const SLOTS: Erc20Slots = Erc20Slots {
+        balance: MappingSlot {
+            slot: U256([0, 0, 0, 0]),
+            layout: MappingLayout::Solidity,
+        },
+        allowance: MappingSlot {
+            slot: U256([1, 0, 0, 0]),
+            layout: MappingLayout::Solidity,
+        },
+    };
+
+    /// The `stateDiff` of `address` in the overrides of the last `eth_call`
+    fn state_diff(mock: &MockTransport, address: Address) -> Value {
+        let (method, params) = mock.requests().pop().unwrap();
+        assert_eq!(method, "eth_call");
+        params[2][format!("{:?}", address)]["stateDiff"].clone()
+    }
+
+    fn slot_value(x: u64) -> String {
+        format!("{:#066x}", x)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_strategy_pool() -> Result<()> {
+        let mock = MockTransport::new();
+        let pool = StrategyPoolContract::new(mock.web3(), Address::repeat_byte(1))?;
+        let abi = load_abi(STRATEGY_POOL_ABI)?;
+        let owner = test_signer(0)?;
+        let assets = vec![Address::repeat_byte(2), Address::repeat_byte(3)];
+
+        mock.expect_call(
+            function_selector("previewRedeem(uint256)"),
+            &[
+                Token::Array(assets.iter().copied().map(Token::Address).collect()),
+                Token::Array(vec![Token::Uint(5.into()), Token::Uint(6.into())]),
+            ],
+        );
+        let (redeemed, amounts) = pool.preview_redeem(U256::from(10), None).await?;
+        assert_eq!(redeemed, assets);
+        assert_eq!(amounts, vec![U256::from(5), U256::from(6)]);
+
+        pool.change_strategy(&owner, assets.clone(), vec![-300, 200])
+            .await?;
+        let sent = mock.sent();
+        let input = sent[0].decode_input(abi.function("changeStrategy")?)?;
+        assert_eq!(
+            input[1],
+            Token::Array(vec![Token::Int(int256(-300)), Token::Int(int256(200))])
+        );
+
+        mock.expect_call(
+            function_selector("deposit(address[],uint256[],address)"),
+            &[Token::Uint(42.into())],
+        );
+        let dry_run = pool
+            .dry_run_deposit(
+                &owner,
+                assets.clone(),
+                vec![5.into(), 6.into()],
+                owner.address,
+            )
+            .await?;
+        assert_eq!(dry_run.into_output()?, vec![Token::Uint(42.into())]);
+        mock.expect_revert(
+            "eth_call",
+            function_selector("redeem(uint256,address,address)"),
+            "insufficient shares",
+        );
+        let dry_run = pool
+            .dry_run_redeem(&owner, U256::from(10), owner.address, owner.address)
+            .await?;
+        assert_eq!(
+            dry_run.result,
+            Err(DryRunError::Revert("insufficient shares".to_owned()))
+        );
+
+        // nothing is sent for mismatched lengths
+        assert!(pool
+            .deposit(&owner, assets, vec![U256::one()], owner.address)
+            .await
+            .is_err());
+        assert_eq!(mock.sent().len(), 1);
+        mock.assert_done();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_simulate() -> Result<()> {
+        let mock = MockTransport::new();
+        let address = Address::repeat_byte(1);
+        let pool = StrategyPoolContract::new(mock.web3(), address)?;
+        let holder = Address::repeat_byte(0xa);
+        let (usdc, weth) = (Address::repeat_byte(2), Address::repeat_byte(3));
+
+        // probed once per asset, in the order of their addresses
+        mock.expect_erc20_slots(SLOTS).expect_erc20_slots(SLOTS);
+        mock.expect_call(
+            function_selector("deposit(address[],uint256[],address)"),
+            &[Token::Uint(42.into())],
+        );
+        let shares = pool
+            .simulate_deposit(
+                holder,
+                vec![weth, usdc, weth],
+                vec![5.into(), 6.into(), 7.into()],
+                holder,
+                None,
+            )
+            .await?;
+        assert_eq!(shares, 42.into());
+        let (_, params) = mock.requests().pop().unwrap();
+        assert_eq!(params[0]["from"], json!(holder));
+        assert_eq!(params[0]["to"], json!(address));
+        for (asset, amount) in [(usdc, 6), (weth, 12)] {
+            assert_eq!(
+                state_diff(&mock, asset),
+                json!({
+                    format!("{:?}", SLOTS.balance.key(&[holder])): slot_value(amount),
+                    format!("{:?}", SLOTS.allowance.key(&[holder, address])): slot_value(amount),
+                })
+            );
+        }
+
+        mock.expect_erc20_slots(SLOTS);
+        mock.expect_call(
+            function_selector("redeem(uint256,address,address)"),
+            &[
+                Token::Array(vec![Token::Address(usdc), Token::Address(weth)]),
+                Token::Array(vec![Token::Uint(6.into()), Token::Uint(12.into())]),
+            ],
+        );
+        let (assets, amounts) = pool
+            .simulate_redeem(holder, 42.into(), holder, None)
+            .await?;
+        assert_eq!(assets, vec![usdc, weth]);
+        assert_eq!(amounts, vec![U256::from(6), U256::from(12)]);
+        // the pool is its own share token
+        assert_eq!(
+            state_diff(&mock, address),
+            json!({format!("{:?}", SLOTS.balance.key(&[holder])): slot_value(42)})
+        );
+        mock.assert_done();
+        Ok(())
+    }
+}
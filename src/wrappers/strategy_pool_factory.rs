@@ -0,0 +1,137 @@
+use crate::wrappers::load_abi;
+use eth_sdk::block::{self, BlockRef};
+use eth_sdk::dry_run::{self, DryRun};
+use eth_sdk::signer::EthereumSigner;
+use eyre::*;
+use web3::contract::tokens::Tokenize;
+use web3::contract::Contract;
+use web3::ethabi::{decode, ParamType, Token};
+use web3::types::{Address, CallRequest, H256, U256};
+use web3::{Transport, Web3};
+
+const STRATEGY_POOL_FACTORY_ABI: &[u8] =
+    include_bytes!("../../abi/internal/strategy_pool_factory.json");
+
+#[derive(Debug, Clone)]
+pub struct StrategyPoolFactoryContract<T: Transport> {
+    web3: Web3<T>,
+    inner: Contract<T>,
+}
+
+impl<T: Transport> StrategyPoolFactoryContract<T> {
+    pub fn new(web3: Web3<T>, address: Address) -> Result<Self> {
+        let inner = Contract::new(web3.eth(), address, load_abi(STRATEGY_POOL_FACTORY_ABI)?);
+        Ok(Self { web3, inner })
+    }
+    /// Deploys a pool for `trader`, who may have only one
+    pub async fn create_pool(
+        &self,
+        by: &EthereumSigner,
+        trader: Address,
+        name: &str,
+        symbol: &str,
+        initial_deposit_share_value: U256,
+    ) -> Result<H256> {
+        by.send_call(
+            &self.web3,
+            &self.inner,
+            "createPool",
+            (
+                trader,
+                name.to_owned(),
+                symbol.to_owned(),
+                initial_deposit_share_value,
+            ),
+            U256::zero(),
+        )
+        .await
+    }
+    /// What `create_pool` would do, without sending anything
+    pub async fn dry_run_create_pool(
+        &self,
+        by: &EthereumSigner,
+        trader: Address,
+        name: &str,
+        symbol: &str,
+        initial_deposit_share_value: U256,
+    ) -> Result<DryRun> {
+        by.dry_run_call(
+            &self.web3,
+            &self.inner,
+            "createPool",
+            (
+                trader,
+                name.to_owned(),
+                symbol.to_owned(),
+                initial_deposit_share_value,
+            ),
+            U256::zero(),
+        )
+        .await
+    }
+    /// Pool of `trader`, zero if there is none
+    pub async fn get_pool(&self, trader: Address, block: Option<BlockRef>) -> Result<Address> {
+        block::query(&self.web3, &self.inner, "getPool", trader, block).await
+    }
+    /// Trader at `index` of `traderAddresses`, `None` past the end
+    pub async fn trader_at(
+        &self,
+        index: usize,
+        block: Option<BlockRef>,
+    ) -> Result<Option<Address>> {
+        let data = self
+            .inner
+            .abi()
+            .function("traderAddresses")?
+            .encode_input(&U256::from(index).into_tokens())?;
+        let request = CallRequest {
+            to: Some(self.inner.address()),
+            data: Some(data.into()),
+            ..Default::default()
+        };
+        // the array getter reverts past the end, there is no length getter
+        let output = match block::call(&self.web3, request, block).await {
+            Ok(output) => output,
+            Err(err) if dry_run::revert_reason(&err).is_some() => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        match decode(&[ParamType::Address], &output.0)?.as_slice() {
+            [Token::Address(trader)] => Ok(Some(*trader)),
+            x => bail!("unexpected traderAddresses output {:?}", x),
+        }
+    }
+    /// Every trader with their pool, read at one block
+    pub async fn list_pools(&self, block: Option<BlockRef>) -> Result<Vec<(Address, Address)>> {
+        let (number, _) = block::resolve(&self.web3, block.unwrap_or(BlockRef::Latest)).await?;
+        let block = Some(BlockRef::Number(number));
+        let mut pools = vec![];
+        while let Some(trader) = self.trader_at(pools.len(), block).await? {
+            pools.push((trader, self.get_pool(trader, block).await?));
+        }
+        Ok(pools)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eth_sdk::mock::MockTransport;
+    use eth_sdk::utils::function_selector;
+
+    #[tokio::test]
+    async fn test_trader_at() -> Result<()> {
+        let mock = MockTransport::new();
+        let factory = StrategyPoolFactoryContract::new(mock.web3(), Address::repeat_byte(1))?;
+        let getter = function_selector("traderAddresses(uint256)");
+        let trader = Address::repeat_byte(2);
+
+        mock.expect_call(getter, &[Token::Address(trader)]);
+        assert_eq!(factory.trader_at(0, None).await?, Some(trader));
+        mock.expect_revert("eth_call", getter, "");
+        assert_eq!(factory.trader_at(1, None).await?, None);
+        // a node that is down is not the end of the list
+        mock.expect_error("eth_call", web3::Error::Unreachable);
+        assert!(factory.trader_at(1, None).await.is_err());
+        Ok(())
+    }
+}